@@ -0,0 +1,89 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Instant;
+
+const EOT: u8 = 0x04;
+
+const INSERT: u8 = 2;
+const REMOVE: u8 = 3;
+
+/// Sends the request with a version 2 key and returns the status code of the response
+fn request(stream: &mut TcpStream, command: u8, key: &[u8], data: &[u8]) -> u16 {
+    let content_length = 2 + key.len() + data.len();
+
+    let mut msg: Vec<u8> = Vec::with_capacity(content_length + 5);
+    msg.push(2); // version
+    msg.push(command);
+    msg.extend_from_slice(&(content_length as u16).to_be_bytes()); // content_length
+    msg.extend_from_slice(&(key.len() as u16).to_be_bytes()); // key length
+    msg.extend_from_slice(key);
+    msg.extend_from_slice(data);
+    msg.push(EOT);
+
+    stream.write_all(&msg).expect("write failed");
+
+    let mut header = [0u8; 6];
+    stream.read_exact(&mut header).expect("read header failed");
+
+    let status_code = u16::from_be_bytes([header[2], header[3]]);
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+
+    // content and EOT
+    let mut rest = vec![0u8; content_length + 1];
+    stream.read_exact(&mut rest).expect("read content failed");
+
+    status_code
+}
+
+/// Inserts and removes entries with different keys over every connection at the same time,
+/// run it against servers with a different number of shards (e.g. --shards 1 and --shards 16).
+///
+/// cargo run --release --example benchmark -- <requests per connection> <connections...>
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let requests: usize = args.next()
+        .map(|arg| arg.parse().expect("failed to parse requests per connection"))
+        .unwrap_or(10_000);
+    let mut connection_counts: Vec<usize> = args
+        .map(|arg| arg.parse().expect("failed to parse connections"))
+        .collect();
+    if connection_counts.is_empty() {
+        let cores = thread::available_parallelism().map(|cores| cores.get()).unwrap_or(4);
+        connection_counts = (0..).map(|i| 1 << i).take_while(|count| *count <= cores * 2).collect();
+    }
+
+    println!("{} inserts and removes per connection", requests);
+
+    for (run, connections) in connection_counts.into_iter().enumerate() {
+        let start = Instant::now();
+
+        let threads: Vec<_> = (0..connections)
+            .map(|connection| thread::spawn(move || {
+                let mut stream = TcpStream::connect("127.0.0.1:6379").expect("connect failed");
+                stream.set_nodelay(true).expect("set nodelay failed");
+
+                for i in 0..requests {
+                    let key = format!("benchmark:{}:{}:{}", run, connection, i);
+
+                    assert_eq!(request(&mut stream, INSERT, key.as_bytes(), b"hello"), 200, "insert failed");
+                    assert_eq!(request(&mut stream, REMOVE, key.as_bytes(), b""), 200, "remove failed");
+                }
+            }))
+            .collect();
+
+        for thread in threads {
+            thread.join().expect("connection failed");
+        }
+
+        let elapsed = start.elapsed();
+        let total = connections * requests * 2;
+        println!(
+            "{:>3} connections: {:>8} requests in {:>6} ms, {:>9.0} requests/s",
+            connections,
+            total,
+            elapsed.as_millis(),
+            total as f64 / elapsed.as_secs_f64(),
+        );
+    }
+}
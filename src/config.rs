@@ -0,0 +1,273 @@
+use std::path::PathBuf;
+use crate::persistence::aof::{AutoRewrite, FsyncPolicy};
+use crate::repository::eviction::EvictionPolicy;
+use crate::repository::DEFAULT_SHARDS;
+
+/// Server settings, parsed from the command line arguments
+///
+/// --appendonly <yes|no>        log every change to the append only file (default: no)
+/// --appendfilename <path>      path of the append only file (default: appendonly.aof)
+/// --appendfsync <always|everysec|no>  when the append only file is flushed to the disk (default: everysec)
+/// --auto-aof-rewrite-percentage <percent>  growth since the last rewrite that triggers a rewrite, 0 disables it (default: 100)
+/// --auto-aof-rewrite-min-size <bytes>  minimum size of the append only file for a rewrite (default: 64mb)
+/// --dbfilename <path>          path of the snapshot file (default: dump.rdb)
+/// --maxmemory <bytes>          memory limit of the entries, 0 disables it (default: 0)
+/// --maxmemory-policy <noeviction|allkeys-lru|allkeys-lfu|volatile-ttl|random>  which entries are removed once the limit is reached (default: noeviction)
+/// --shards <count>             number of independently locked parts of the keyspace (default: 16)
+/// --max-frame-size <bytes>     longest content of a request or response, at most 4gb - 1 (default: 512mb)
+/// --resp-port <port>           port of the listener speaking the Redis serialization protocol (default: disabled)
+#[derive(Debug, PartialEq)]
+pub(crate) struct Config {
+    pub(crate) appendonly: bool,
+    pub(crate) appendfilename: PathBuf,
+    pub(crate) appendfsync: FsyncPolicy,
+    pub(crate) auto_aof_rewrite: AutoRewrite,
+    pub(crate) dbfilename: PathBuf,
+    pub(crate) maxmemory: u64,
+    pub(crate) maxmemory_policy: EvictionPolicy,
+    pub(crate) shards: usize,
+    pub(crate) max_frame_size: u32,
+    pub(crate) resp_port: Option<u16>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            appendonly: false,
+            appendfilename: PathBuf::from("appendonly.aof"),
+            appendfsync: FsyncPolicy::EverySec,
+            auto_aof_rewrite: AutoRewrite {
+                percentage: 100,
+                min_size: 64 * 1024 * 1024,
+            },
+            dbfilename: PathBuf::from("dump.rdb"),
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            shards: DEFAULT_SHARDS,
+            max_frame_size: 512 * 1024 * 1024,
+            resp_port: None,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn from_args<I>(args: I) -> Result<Config, anyhow::Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+
+        while let Some(name) = args.next() {
+            let value = args.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for {}", name))?;
+
+            match name.as_str() {
+                "--appendonly" => config.appendonly = parse_yes_no(&name, &value)?,
+                "--appendfilename" => config.appendfilename = PathBuf::from(value),
+                "--appendfsync" => config.appendfsync = parse_fsync_policy(&value)?,
+                "--auto-aof-rewrite-percentage" => config.auto_aof_rewrite.percentage = parse_number(&name, &value)?,
+                "--auto-aof-rewrite-min-size" => config.auto_aof_rewrite.min_size = parse_bytes(&name, &value)?,
+                "--dbfilename" => config.dbfilename = PathBuf::from(value),
+                "--maxmemory" => config.maxmemory = parse_bytes(&name, &value)?,
+                "--maxmemory-policy" => config.maxmemory_policy = parse_eviction_policy(&value)?,
+                "--shards" => config.shards = parse_shards(&name, &value)?,
+                "--max-frame-size" => config.max_frame_size = parse_frame_size(&name, &value)?,
+                "--resp-port" => config.resp_port = Some(parse_port(&name, &value)?),
+                _ => return Err(anyhow::anyhow!("unknown argument {}", name)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_yes_no(name: &str, value: &str) -> Result<bool, anyhow::Error> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(anyhow::anyhow!("{} must be yes or no, got {}", name, value)),
+    }
+}
+
+fn parse_fsync_policy(value: &str) -> Result<FsyncPolicy, anyhow::Error> {
+    match value {
+        "always" => Ok(FsyncPolicy::Always),
+        "everysec" => Ok(FsyncPolicy::EverySec),
+        "no" => Ok(FsyncPolicy::No),
+        _ => Err(anyhow::anyhow!("--appendfsync must be always, everysec or no, got {}", value)),
+    }
+}
+
+fn parse_eviction_policy(value: &str) -> Result<EvictionPolicy, anyhow::Error> {
+    match value {
+        "noeviction" => Ok(EvictionPolicy::NoEviction),
+        "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+        "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+        "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+        "random" => Ok(EvictionPolicy::Random),
+        _ => Err(anyhow::anyhow!("--maxmemory-policy must be noeviction, allkeys-lru, allkeys-lfu, volatile-ttl or random, got {}", value)),
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64, anyhow::Error> {
+    value.parse::<u64>()
+        .map_err(|_| anyhow::anyhow!("{} must be a positive number, got {}", name, value))
+}
+
+fn parse_shards(name: &str, value: &str) -> Result<usize, anyhow::Error> {
+    match value.parse::<usize>() {
+        Ok(shards) if shards > 0 => Ok(shards),
+        _ => Err(anyhow::anyhow!("{} must be at least 1, got {}", name, value)),
+    }
+}
+
+fn parse_port(name: &str, value: &str) -> Result<u16, anyhow::Error> {
+    match value.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(anyhow::anyhow!("{} must be a port between 1 and 65535, got {}", name, value)),
+    }
+}
+
+/// The content length of a request or response is at most a u32
+fn parse_frame_size(name: &str, value: &str) -> Result<u32, anyhow::Error> {
+    u32::try_from(parse_bytes(name, value)?)
+        .map_err(|_| anyhow::anyhow!("{} must be less than 4gb, got {}", name, value))
+}
+
+/// Parses a number of bytes with an optional unit (kb, mb, gb), e.g. 64mb
+fn parse_bytes(name: &str, value: &str) -> Result<u64, anyhow::Error> {
+    let lowercase = value.to_ascii_lowercase();
+
+    let (number, unit) = match lowercase.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => lowercase.split_at(i),
+        None => (lowercase.as_str(), ""),
+    };
+
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "kb" => 1024,
+        "mb" => 1024 * 1024,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(anyhow::anyhow!("{} has an unknown unit, got {}", name, value)),
+    };
+
+    parse_number(name, number)?
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("{} is too large, got {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args_default() {
+        let config = Config::from_args(args(&[])).unwrap();
+
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = Config::from_args(args(&["--appendonly", "yes", "--appendfilename", "/data/db.aof", "--appendfsync", "always", "--dbfilename", "/data/db.rdb"])).unwrap();
+
+        assert!(config.appendonly);
+        assert_eq!(config.dbfilename, PathBuf::from("/data/db.rdb"));
+        assert_eq!(config.appendfilename, PathBuf::from("/data/db.aof"));
+        assert_eq!(config.appendfsync, FsyncPolicy::Always);
+    }
+
+    #[test]
+    fn test_from_args_missing_value() {
+        let err = Config::from_args(args(&["--appendonly"])).unwrap_err();
+
+        assert!(err.to_string().contains("missing value for --appendonly"));
+    }
+
+    #[test]
+    fn test_from_args_invalid_value() {
+        let err = Config::from_args(args(&["--appendonly", "maybe"])).unwrap_err();
+
+        assert!(err.to_string().contains("must be yes or no"));
+    }
+
+    #[test]
+    fn test_from_args_invalid_fsync_policy() {
+        let err = Config::from_args(args(&["--appendfsync", "sometimes"])).unwrap_err();
+
+        assert!(err.to_string().contains("--appendfsync must be always, everysec or no"));
+    }
+
+    #[test]
+    fn test_from_args_auto_aof_rewrite() {
+        let config = Config::from_args(args(&["--auto-aof-rewrite-percentage", "50", "--auto-aof-rewrite-min-size", "1kb"])).unwrap();
+
+        assert_eq!(config.auto_aof_rewrite, AutoRewrite { percentage: 50, min_size: 1024 });
+    }
+
+    #[test]
+    fn test_from_args_maxmemory() {
+        let config = Config::from_args(args(&["--maxmemory", "100mb", "--maxmemory-policy", "allkeys-lru"])).unwrap();
+
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.maxmemory_policy, EvictionPolicy::AllKeysLru);
+    }
+
+    #[test]
+    fn test_from_args_shards() {
+        let config = Config::from_args(args(&["--shards", "64"])).unwrap();
+
+        assert_eq!(config.shards, 64);
+        assert!(Config::from_args(args(&["--shards", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_from_args_max_frame_size() {
+        let config = Config::from_args(args(&["--max-frame-size", "1mb"])).unwrap();
+
+        assert_eq!(config.max_frame_size, 1024 * 1024);
+
+        let err = Config::from_args(args(&["--max-frame-size", "4gb"])).unwrap_err();
+        assert!(err.to_string().contains("--max-frame-size must be less than 4gb"));
+    }
+
+    #[test]
+    fn test_from_args_resp_port() {
+        let config = Config::from_args(args(&["--resp-port", "6380"])).unwrap();
+
+        assert_eq!(config.resp_port, Some(6380));
+        assert!(Config::from_args(args(&["--resp-port", "0"])).is_err());
+        assert!(Config::from_args(args(&["--resp-port", "70000"])).is_err());
+    }
+
+    #[test]
+    fn test_from_args_invalid_eviction_policy() {
+        let err = Config::from_args(args(&["--maxmemory-policy", "volatile-lru"])).unwrap_err();
+
+        assert!(err.to_string().contains("--maxmemory-policy must be"));
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("size", "100").unwrap(), 100);
+        assert_eq!(parse_bytes("size", "2KB").unwrap(), 2 * 1024);
+        assert_eq!(parse_bytes("size", "64mb").unwrap(), 64 * 1024 * 1024);
+        assert_eq!(parse_bytes("size", "1gb").unwrap(), 1024 * 1024 * 1024);
+
+        assert!(parse_bytes("size", "mb").is_err());
+        assert!(parse_bytes("size", "10tb").is_err());
+        assert!(parse_bytes("size", "-1").is_err());
+    }
+
+    #[test]
+    fn test_from_args_unknown_argument() {
+        let err = Config::from_args(args(&["--port", "6379"])).unwrap_err();
+
+        assert!(err.to_string().contains("unknown argument --port"));
+    }
+}
@@ -1,57 +1,264 @@
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
 use crate::connection::read_content::read_content;
-use crate::connection::read_header::read_header;
+use crate::connection::read_header::{read_header, UnsupportedVersion};
 use crate::connection::send_response::send_response;
-use crate::controller::route_request;
+use crate::controller::{route_request, uses_transaction, Transaction};
 use crate::repository::SharedRepository;
-use crate::types::{Request};
+use crate::types::{Request, Response, StatusCode, PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN};
 
-pub(super) async fn handle_connection<S>(mut stream: S, db: SharedRepository)
+/// most requests of a connection that are processed at the same time,
+/// the next request is only read once one of them is done
+const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+pub(super) async fn handle_connection<S>(stream: S, db: SharedRepository, max_frame_size: u32)
 where
     S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
 {
-    loop {
-        // read header
-        let header_data = match read_header(&mut stream).await {
-            Ok(header_data) => header_data,
-            Err(e) => {
-                eprintln!("read header failed: {}", e);
-                // don't return a response and let the client reconnect with a new connection
-
-                // break / close connection to make sure that there are no leftover bytes in the stream from this request
-                break;
-            }
-        };
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // all responses are written by a single writer, in the order they are done
+    let (sender, mut receiver) = mpsc::channel::<(Option<u32>, Response)>(MAX_CONCURRENT_REQUESTS);
+
+    let read_requests = async move {
+        // the watched entries and the transaction started by MULTI, they are dropped with the connection
+        let mut transaction = Transaction::default();
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS));
+
+        loop {
+            // read header
+            let header_data = match read_header(&mut reader).await {
+                Ok(header_data) => header_data,
+                Err(e) => {
+                    eprintln!("read header failed: {}", e);
+
+                    // the response has the layout of the lowest version, which every client can read,
+                    // with the supported versions to retry with (see HELLO)
+                    if let Some(UnsupportedVersion(_, command)) = e.downcast_ref::<UnsupportedVersion>() {
+                        let content = vec![PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_MAX];
+                        let response = Response {
+                            version: PROTOCOL_VERSION_MIN,
+                            command: *command,
+                            status_code: StatusCode::VersionNotSupported,
+                            content_length: content.len() as u32,
+                            content: Some(content),
+                        };
+                        let _ = sender.send((None, response)).await;
+                    }
 
-        // read content
-        let content = match read_content(&mut stream, header_data.content_length as usize).await {
-            Ok(v) => v,
-            Err(e) => {
-                eprintln!("read content failed: {}", e);
-                // don't return a response and let the client reconnect with a new connection
+                    // otherwise don't return a response and let the client reconnect with a new connection
 
-                // break / close connection to make sure that there are no leftover bytes in the stream from this request
+                    // break / close connection to make sure that there are no leftover bytes in the stream from this request
+                    break;
+                }
+            };
+
+            // the content isn't read, so the connection is closed after the response
+            if header_data.content_length > max_frame_size {
+                eprintln!("request content of {} bytes is larger than the max frame size", header_data.content_length);
+                let response = Response {
+                    version: header_data.version,
+                    command: header_data.command,
+                    status_code: StatusCode::ContentTooLarge,
+                    content_length: 0,
+                    content: None,
+                };
+                let _ = sender.send((header_data.request_id, response)).await;
                 break;
             }
-        };
 
-        let request = Request {
-            version: header_data.version,
-            command: header_data.command,
-            content_length: header_data.content_length,
-            content: if content.is_empty() { None } else { Some(content) },
-        };
+            // read content
+            let content = match read_content(&mut reader, header_data.content_length as usize).await {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("read content failed: {}", e);
+                    // don't return a response and let the client reconnect with a new connection
+
+                    // break / close connection to make sure that there are no leftover bytes in the stream from this request
+                    break;
+                }
+            };
 
-        let local_db = db.clone();
+            let request = Request {
+                version: header_data.version,
+                command: header_data.command,
+                content_length: header_data.content_length,
+                content: if content.is_empty() { None } else { Some(content) },
+            };
+            let request_id = header_data.request_id;
 
-        let response = route_request(request, local_db).await;
+            let local_db = db.clone();
 
-        match send_response(response, &mut stream).await {
-            Ok(_) => {}
-            Err(e) => {
+            // requests with a request id are processed concurrently, the client matches the responses by the id.
+            // requests without one are answered in order, and so are the ones using the transaction of the connection
+            if request_id.is_some() && !uses_transaction(request.command, &transaction) {
+                let permit = permits.clone().acquire_owned().await.expect("semaphore is never closed");
+                let sender = sender.clone();
+
+                tokio::spawn(async move {
+                    // the request doesn't use the transaction, so it gets one of its own
+                    let response = route_request(request, local_db, &mut Transaction::default()).await;
+                    let _ = sender.send((request_id, response)).await;
+                    drop(permit);
+                });
+                continue;
+            }
+
+            let response = route_request(request, local_db, &mut transaction).await;
+
+            if sender.send((request_id, response)).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let write_responses = async move {
+        // ends once the requests are read and all spawned requests are done
+        while let Some((request_id, response)) = receiver.recv().await {
+            if let Err(e) = send_response(response, request_id, max_frame_size, &mut writer).await {
                 eprintln!("sending response failed: {}", e);
-                continue; // skip to wait for next request
             }
-        };
-    }
+        }
+    };
+
+    tokio::join!(read_requests, write_responses);
+
     println!("close connection");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::repository::MockRepository;
+    use crate::types::{Command, PROTOCOL_VERSION_LARGE_CONTENT, PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_REQUEST_ID};
+
+    fn make_frame(version: u8, command: Command, request_id: Option<u32>, content: &[u8]) -> Vec<u8> {
+        let mut frame = vec![version, command as u8];
+        if version >= PROTOCOL_VERSION_LARGE_CONTENT {
+            frame.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        } else {
+            frame.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+        if let Some(request_id) = request_id {
+            frame.extend_from_slice(&request_id.to_be_bytes());
+        }
+        frame.extend_from_slice(content);
+        frame.push(0x04);
+        frame
+    }
+
+    // ---- TESTS ----
+
+    // the blocking mock needs a second worker to answer the other request
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn pipelined_requests_answered_when_done() {
+        let mut mock = MockRepository::new();
+
+        // the first request blocks, the second one is answered before it
+        mock.expect_set_wait()
+            .times(1)
+            .returning(|_, _, _| {
+                std::thread::sleep(Duration::from_millis(200));
+                Ok(())
+            });
+        mock.expect_get()
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 1)));
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(mock), u32::MAX));
+
+        let mut set_wait = 42u32.to_be_bytes().to_vec();
+        set_wait.extend_from_slice(&250u64.to_be_bytes());
+        set_wait.extend_from_slice(b"hello");
+
+        // version 4 keys are the same as version 2 keys
+        let mut frames = make_frame(PROTOCOL_VERSION_REQUEST_ID, Command::SetWait, Some(1), &[&[0, 4][..], &set_wait].concat());
+        frames.extend(make_frame(PROTOCOL_VERSION_REQUEST_ID, Command::Get, Some(2), b"\x00\x02hi"));
+        client.write_all(&frames).await.unwrap();
+
+        // u8 version + u8 command + u16 status + u16 length + u32 request id
+        let mut header = [0u8; 10];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, [4, Command::Get as u8, 0, 200, 0, 13, 0, 0, 0, 2]);
+        let mut rest = [0u8; 14];
+        client.read_exact(&mut rest).await.unwrap();
+
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, [4, Command::SetWait as u8, 0, 200, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(client.read_u8().await.unwrap(), 0x04);
+
+        drop(client);
+        connection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_without_request_id_answered_in_order() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(2)
+            .returning(|key| Some((key, 1)));
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(mock), u32::MAX));
+
+        let mut frames = make_frame(1, Command::Get, None, &42u32.to_be_bytes());
+        frames.extend(make_frame(1, Command::Get, None, &7u32.to_be_bytes()));
+        client.write_all(&frames).await.unwrap();
+
+        let mut response = [0u8; 11];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [1, 0, 0, 200, 0, 4, 0, 0, 0, 42, 0x04]);
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [1, 0, 0, 200, 0, 4, 0, 0, 0, 7, 0x04]);
+
+        drop(client);
+        connection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn content_too_large_when_request_larger_than_max_frame_size() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set().never();
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(mock), 8));
+
+        let frame = make_frame(PROTOCOL_VERSION_LARGE_CONTENT, Command::Set, Some(7), b"\x00\x02hihello");
+        client.write_all(&frame).await.unwrap();
+
+        // u8 version + u8 command + u16 status + u32 length + u32 request id + EOT
+        let mut response = [0u8; 13];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [5, Command::Set as u8, 0x01, 0x9D, 0, 0, 0, 0, 0, 0, 0, 7, 0x04]);
+
+        // the connection is closed after the response
+        connection.await.unwrap();
+        assert_eq!(client.read_u8().await.ok(), None);
+    }
+
+    #[tokio::test]
+    async fn version_not_supported_when_version_unknown() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get().never();
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(mock), u32::MAX));
+
+        // only the version and the command are read
+        client.write_all(&[PROTOCOL_VERSION_MAX + 1, Command::Get as u8]).await.unwrap();
+
+        let mut response = [0u8; 9];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [1, Command::Get as u8, 0x01, 0xF9, 0, 2, 1, PROTOCOL_VERSION_MAX, 0x04]);
+
+        // the connection is closed after the response
+        connection.await.unwrap();
+        assert_eq!(client.read_u8().await.ok(), None);
+    }
+}
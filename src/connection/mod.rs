@@ -7,7 +7,7 @@ use tokio::net::{TcpListener};
 use crate::connection::handle_connection::handle_connection;
 use crate::repository::SharedRepository;
 
-pub async fn listen_for_connections(tcp_listener: TcpListener, db: SharedRepository) {
+pub async fn listen_for_connections(tcp_listener: TcpListener, db: SharedRepository, max_frame_size: u32) {
     loop {
         // accept connections and pass TcpStream to handle_connection
         let (stream, _) = match tcp_listener.accept().await {
@@ -21,7 +21,7 @@ pub async fn listen_for_connections(tcp_listener: TcpListener, db: SharedReposit
         let local_db = db.clone();
 
         tokio::spawn(async move {
-            handle_connection(stream, local_db).await;
+            handle_connection(stream, local_db, max_frame_size).await;
         });
     }
 }
@@ -1,14 +1,18 @@
 use anyhow::Context;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+/// most bytes reserved for the content before it is read
+const INITIAL_CAPACITY: usize = 64 * 1024;
+
 pub(super) async fn read_content<S>(reader: &mut S, content_length: usize) -> Result<Vec<u8>, anyhow::Error>
 where
     S: AsyncRead + Unpin,
 {
-    // a vector needs to be used since the length of content_length isn't known at compile time
-    let mut content = vec![0; content_length];
+    // the content is read as it arrives instead of allocating the whole content length up front,
+    // so a header alone can't reserve up to the max frame size
+    let mut content = Vec::with_capacity(content_length.min(INITIAL_CAPACITY));
 
-    reader.read_exact(&mut content).await
+    (&mut *reader).take(content_length as u64).read_to_end(&mut content).await
         .context("content smaller than expected")?;
 
     if content.len() < content_length {
@@ -66,4 +70,14 @@ mod tests {
         let err = read_content(&mut cursor, data.len() - 5).await.unwrap_err();
         assert!(err.to_string().contains("eot character not found"));
     }
+
+    #[tokio::test]
+    async fn test_read_content_large() {
+        let mut data = vec![7; 200_000];
+        data.push(0x04);
+        let mut cursor = Cursor::new(data);
+
+        let result = read_content(&mut cursor, 200_000).await.unwrap();
+        assert_eq!(result, vec![7; 200_000]);
+    }
 }
\ No newline at end of file
@@ -1,31 +1,58 @@
 use anyhow::Context;
+use thiserror::Error;
 use tokio::io::{AsyncReadExt};
-use crate::types::Command;
+use crate::types::{is_supported_version, Command, PROTOCOL_VERSION_LARGE_CONTENT, PROTOCOL_VERSION_REQUEST_ID};
+
+/// The server doesn't support the protocol version of the request (with the command of the request),
+/// so the rest of the header isn't read since its layout is unknown
+#[derive(Debug, Error, PartialEq)]
+#[error("unsupported protocol version {0}")]
+pub(super) struct UnsupportedVersion(pub(super) u8, pub(super) Command);
 
 #[derive(Debug, PartialEq)]
 pub(super) struct HeaderData {
     pub(super) version: u8,
     pub(super) command: Command,
-    pub(super) content_length: u16,
+    pub(super) content_length: u32,
+    // since version 4
+    pub(super) request_id: Option<u32>,
 }
 
 pub(super) async fn read_header<R>(reader: &mut R) -> Result<HeaderData, anyhow::Error>
 where
     R: AsyncReadExt + Unpin,
 {
-    // u8 version + u8 command + u16 content length
-    let mut header = [0u8; 4];
+    // u8 version + u8 command
+    let mut header = [0u8; 2];
     reader.read_exact(&mut header).await
         .context("header too short")?;
 
     let version = header[0];
     let command = header[1].into();
-    let content_length = u16::from_be_bytes([header[2], header[3]]);
+
+    if !is_supported_version(version) {
+        return Err(UnsupportedVersion(version, command).into());
+    }
+
+    // u16 content length, u32 content length since version 5
+    let content_length = if version >= PROTOCOL_VERSION_LARGE_CONTENT {
+        reader.read_u32().await.context("header too short")?
+    } else {
+        reader.read_u16().await.context("header too short")? as u32
+    };
+
+    // the request id follows the header since version 4
+    let request_id = if version >= PROTOCOL_VERSION_REQUEST_ID {
+        Some(reader.read_u32().await.context("request id missing")?)
+    } else {
+        None
+    };
 
     Ok(HeaderData {
         version,
         command,
-        content_length
+        content_length,
+        request_id,
     })
 }
 
@@ -47,10 +74,83 @@ mod tests {
         assert_eq!(result, HeaderData {
             version: 1,
             command: Command::Get,
-            content_length: 255
+            content_length: 255,
+            request_id: None,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_read_header_with_request_id() {
+        let data = [
+            /* version 4 */ 0x04,
+            /* command get */ 0x00,
+            /* content_length 255 */ 0x00, 0xFF,
+            /* request id 42 */ 0x00, 0x00, 0x00, 0x2A];
+        let mut cursor = Cursor::new(data);
+
+        let result = read_header(&mut cursor).await.unwrap();
+        assert_eq!(result, HeaderData {
+            version: 4,
+            command: Command::Get,
+            content_length: 255,
+            request_id: Some(42),
         });
     }
 
+    #[tokio::test]
+    async fn test_read_header_with_large_content_length() {
+        let data = [
+            /* version 5 */ 0x05,
+            /* command get */ 0x00,
+            /* content_length 65536 */ 0x00, 0x01, 0x00, 0x00,
+            /* request id 42 */ 0x00, 0x00, 0x00, 0x2A];
+        let mut cursor = Cursor::new(data);
+
+        let result = read_header(&mut cursor).await.unwrap();
+        assert_eq!(result, HeaderData {
+            version: 5,
+            command: Command::Get,
+            content_length: 65536,
+            request_id: Some(42),
+        });
+    }
+
+    #[tokio::test]
+    async fn test_read_header_large_content_length_too_short() {
+        let data = [
+            /* version 5 */ 0x05,
+            /* command get */ 0x00,
+            /* content_length, missing one byte */ 0x00, 0x01, 0x00];
+        let mut cursor = Cursor::new(data);
+
+        let err = read_header(&mut cursor).await.unwrap_err();
+        assert!(err.to_string().contains("header too short"));
+    }
+
+    #[tokio::test]
+    async fn test_read_header_request_id_missing() {
+        let data = [
+            /* version 4 */ 0x04,
+            /* command get */ 0x00,
+            /* content_length 255 */ 0x00, 0xFF,
+            /* request id, missing one byte */ 0x00, 0x00, 0x00];
+        let mut cursor = Cursor::new(data);
+
+        let err = read_header(&mut cursor).await.unwrap_err();
+        assert!(err.to_string().contains("request id missing"));
+    }
+
+    #[tokio::test]
+    async fn test_read_header_with_unsupported_version() {
+        let data = [
+            /* version 200 */ 0xC8,
+            /* command get */ 0x00];
+        let mut cursor = Cursor::new(data);
+
+        let err = read_header(&mut cursor).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<UnsupportedVersion>(), Some(&UnsupportedVersion(200, Command::Get)));
+    }
+
     #[tokio::test]
     async fn test_read_header_too_short() {
         let data = [
@@ -67,7 +167,7 @@ mod tests {
     async fn test_read_header_with_invalid_command() {
         let data = [
             /* version 1 */ 0x01,
-            /* command invalid */ 0x10,
+            /* command invalid */ 0xF0,
             /* content_length 255 */ 0x00, 0xFF];
         let mut cursor = Cursor::new(data);
 
@@ -1,17 +1,44 @@
 use anyhow::Context;
 use tokio::io::{AsyncWriteExt};
-use crate::types::{Response};
+use crate::types::{max_content_length, Response, StatusCode, PROTOCOL_VERSION_LARGE_CONTENT};
 
-pub(super) async fn send_response<W>(response: Response, mut writer: W) -> Result<(), anyhow::Error>
+/// Writes the response, with the request id after the header if the request had one (since version 4).
+///
+/// A response with more content than the version of the request or the max frame size allows
+/// is sent as 413 content too large without content, instead of with a content length that wrapped around.
+pub(super) async fn send_response<W>(response: Response, request_id: Option<u32>, max_frame_size: u32, mut writer: W) -> Result<(), anyhow::Error>
 where
     W: AsyncWriteExt + Unpin,
 {
-    let mut msg: Vec<u8> = Vec::with_capacity(6 /* Header */ + response.content_length as usize);
+    let response = if response.content_length > max_content_length(response.version).min(max_frame_size) {
+        eprintln!("response content of {} bytes is too large", response.content_length);
+        Response {
+            version: response.version,
+            command: response.command,
+            status_code: StatusCode::ContentTooLarge,
+            content_length: 0,
+            content: None,
+        }
+    } else {
+        response
+    };
+
+    let mut msg: Vec<u8> = Vec::with_capacity(12 /* Header */ + response.content_length as usize);
 
     msg.push(response.version);
     msg.push(response.command as u8);
     msg.append(&mut (response.status_code as u16).to_be_bytes().to_vec());
-    msg.append(&mut (response.content_length).to_be_bytes().to_vec());
+
+    // u16 content length, u32 content length since version 5
+    if response.version >= PROTOCOL_VERSION_LARGE_CONTENT {
+        msg.extend_from_slice(&response.content_length.to_be_bytes());
+    } else {
+        msg.extend_from_slice(&(response.content_length as u16).to_be_bytes());
+    }
+
+    if let Some(request_id) = request_id {
+        msg.extend_from_slice(&request_id.to_be_bytes());
+    }
 
     if let Some(mut content) = response.content {
         msg.append(&mut content);
@@ -40,14 +67,14 @@ mod tests {
             version: 1,
             command: Command::Get,
             status_code: StatusCode::Ok,
-            content_length: content.len() as u16,
+            content_length: content.len() as u32,
             content: Some(content.to_vec()),
         };
 
         let buffer = Vec::new();
         let mut cursor = Cursor::new(buffer);
 
-        send_response(response, &mut cursor)
+        send_response(response, None, u32::MAX, &mut cursor)
             .await
             .expect("send_response failed");
 
@@ -71,14 +98,14 @@ mod tests {
             version: 1,
             command: Command::Get,
             status_code: StatusCode::Ok,
-            content_length: 0u16,
+            content_length: 0,
             content: None,
         };
 
         let buffer = Vec::new();
         let mut cursor = Cursor::new(buffer);
 
-        send_response(response, &mut cursor)
+        send_response(response, None, u32::MAX, &mut cursor)
             .await
             .expect("send_response failed");
 
@@ -94,4 +121,130 @@ mod tests {
 
         assert_eq!(written, expected);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_send_response_with_request_id() {
+        let response = Response {
+            version: 4,
+            command: Command::Get,
+            status_code: StatusCode::NotFound,
+            content_length: 0,
+            content: None,
+        };
+
+        let buffer = Vec::new();
+        let mut cursor = Cursor::new(buffer);
+
+        send_response(response, Some(42), u32::MAX, &mut cursor)
+            .await
+            .expect("send_response failed");
+
+        let written = cursor.into_inner();
+
+        let expected = vec![
+            4,                // version
+            0,                // command
+            1, 148,            // status_code (u16, big-endian)
+            0, 0,              // content_length (u16)
+            0, 0, 0, 42,       // request id (u32)
+            4 // eot
+        ];
+
+        assert_eq!(written, expected);
+    }
+
+    #[tokio::test]
+    async fn test_send_response_with_large_content() {
+        let content = vec![7; 70_000];
+
+        let response = Response {
+            version: 5,
+            command: Command::Get,
+            status_code: StatusCode::Ok,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+
+        let buffer = Vec::new();
+        let mut cursor = Cursor::new(buffer);
+
+        send_response(response, Some(42), u32::MAX, &mut cursor)
+            .await
+            .expect("send_response failed");
+
+        let written = cursor.into_inner();
+
+        assert_eq!(written.len(), 12 + 70_000 + 1);
+        assert_eq!(written[..12], [
+            5,                // version
+            0,                // command
+            0, 200,            // status_code (u16, big-endian)
+            0, 1, 0x11, 0x70,  // content_length (u32)
+            0, 0, 0, 42,       // request id (u32)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_send_response_content_too_large_for_version() {
+        let content = vec![7; 70_000];
+
+        let response = Response {
+            version: 1,
+            command: Command::Get,
+            status_code: StatusCode::Ok,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+
+        let buffer = Vec::new();
+        let mut cursor = Cursor::new(buffer);
+
+        send_response(response, None, u32::MAX, &mut cursor)
+            .await
+            .expect("send_response failed");
+
+        let written = cursor.into_inner();
+
+        let expected = vec![
+            1,                // version
+            0,                // command
+            1, 157,            // status_code 413 (u16, big-endian)
+            0, 0,              // content_length (u16)
+            4 // eot
+        ];
+
+        assert_eq!(written, expected);
+    }
+
+    #[tokio::test]
+    async fn test_send_response_content_larger_than_max_frame_size() {
+        let content = b"hello";
+
+        let response = Response {
+            version: 5,
+            command: Command::Get,
+            status_code: StatusCode::Ok,
+            content_length: content.len() as u32,
+            content: Some(content.to_vec()),
+        };
+
+        let buffer = Vec::new();
+        let mut cursor = Cursor::new(buffer);
+
+        send_response(response, None, 4, &mut cursor)
+            .await
+            .expect("send_response failed");
+
+        let written = cursor.into_inner();
+
+        let expected = vec![
+            5,                // version
+            0,                // command
+            1, 157,            // status_code 413 (u16, big-endian)
+            0, 0, 0, 0,        // content_length (u32)
+            4 // eot
+        ];
+
+        assert_eq!(written, expected);
+    }
+}
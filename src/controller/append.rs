@@ -0,0 +1,156 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, Request, Response, StatusCode};
+
+/// APPEND REQUEST
+///
+/// Appends the content to the data of the entry, a missing entry is inserted with the content.
+/// The expiry of an existing entry is kept.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// content (at least 1 byte)
+///
+/// Responses:
+/// 200 with 8 bytes u64 new length of the data of the entry as body
+/// 400 invalid request
+/// 413 content too large: the data of the entry would be longer than 512 MiB
+/// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
+pub(super) async fn handle_append_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the data can't be empty
+    let (key, data) = match split_key(request.version, &content) {
+        Some((key, data)) if !data.is_empty() => (key, data),
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.append(key, data.to_vec()).await {
+        Ok(length) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 8,
+                content: Some(length.to_be_bytes().to_vec()),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::OutOfMemory => StatusCode::InsufficientStorage,
+                DatabaseError::DataTooLarge(_) => StatusCode::ContentTooLarge,
+                _ => {
+                    eprintln!("append failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(id: u32, data: &[u8]) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(data);
+
+        Request {
+            version: 1,
+            command: Command::Append,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_data_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_append().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_append_request(make_request(42, b""), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn out_of_memory() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_append()
+            .times(1)
+            .returning(|_, _| Err(DatabaseError::OutOfMemory));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_append_request(make_request(42, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InsufficientStorage);
+    }
+
+    #[tokio::test]
+    async fn content_too_large_when_data_exceeds_max_length() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_append()
+            .times(1)
+            .returning(|key, _| Err(DatabaseError::DataTooLarge(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_append_request(make_request(42, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::ContentTooLarge);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_append()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b" world".to_vec()))
+            .times(1)
+            .returning(|_, _| Ok(11));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_append_request(make_request(42, b" world"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 8);
+        assert_eq!(response.content, Some(11u64.to_be_bytes().to_vec()));
+    }
+}
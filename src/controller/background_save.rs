@@ -0,0 +1,153 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{Request, Response, StatusCode};
+
+/// BACKGROUND SAVE REQUEST
+///
+/// Takes a snapshot of all entries and saves it to the snapshot file in the background.
+/// The response is sent once the snapshot is taken, it may not be saved yet.
+///
+/// Request Body:
+/// empty
+///
+/// Responses:
+/// 200 ok
+/// 400 invalid request
+/// 409 conflict: a snapshot is already being saved
+/// 500 internal server error
+/// 501 not implemented: snapshots are disabled
+pub(super) async fn handle_background_save_request(request: Request, db: SharedRepository) -> Response {
+    if request.content_length != 0 {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    }
+
+    match db.background_save_snapshot().await {
+        Ok(()) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 0,
+                content: None,
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::SaveInProgress => StatusCode::Conflict,
+                DatabaseError::SnapshotDisabled => StatusCode::NotImplemented,
+                _ => {
+                    eprintln!("starting background save of snapshot failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(content_length: u32, content: Option<Vec<u8>>) -> Request {
+        Request {
+            version: 1,
+            command: Command::BackgroundSave,
+            content_length,
+            content,
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_content_not_empty() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_background_save_snapshot().never();
+
+        let mock = Arc::new(mock);
+
+        let request = make_request(1, Some(vec![0]));
+        let response = handle_background_save_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn conflict_when_save_in_progress() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_background_save_snapshot()
+            .times(1)
+            .returning(|| Err(DatabaseError::SaveInProgress));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_background_save_request(make_request(0, None), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Conflict);
+    }
+
+    #[tokio::test]
+    async fn not_implemented_when_snapshots_disabled() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_background_save_snapshot()
+            .times(1)
+            .returning(|| Err(DatabaseError::SnapshotDisabled));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_background_save_request(make_request(0, None), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::NotImplemented);
+    }
+
+    #[tokio::test]
+    async fn unknown_db_error() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_background_save_snapshot()
+            .times(1)
+            .returning(|| Err(DatabaseError::Persistence("disk full".to_string())));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_background_save_request(make_request(0, None), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InternalServerError);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_background_save_snapshot()
+            .times(1)
+            .returning(|| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_background_save_request(make_request(0, None), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 0);
+    }
+}
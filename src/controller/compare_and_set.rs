@@ -0,0 +1,204 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, split_u64, Request, Response, StatusCode};
+
+/// COMPARE AND SET REQUEST
+///
+/// Replaces the data of the entry only if it still has the version the client read (see GET of version 3),
+/// the expiry of the entry is removed like with SET.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// 8 bytes u64 expected version of the entry
+/// content (at least 1 byte)
+///
+/// Responses:
+/// 200 with 8 bytes u64 new version of the entry as body
+/// 400 invalid request
+/// 404 not found
+/// 409 conflict: the entry has another version, with 8 bytes u64 current version of the entry as body
+/// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
+pub(super) async fn handle_compare_and_set_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the expected version is between the key and the data, the data can't be empty
+    let (key, expected_version, data) = match split_key(request.version, &content) {
+        Some((key, rest)) => match split_u64(rest) {
+            Some((expected_version, data)) if !data.is_empty() => (key, expected_version, data),
+            _ => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.compare_and_set(key, expected_version, data.to_vec()).await {
+        Ok(version) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 8,
+                content: Some(version.to_be_bytes().to_vec()),
+            }
+        }
+        Err(DatabaseError::VersionMismatch(current_version)) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Conflict,
+                content_length: 8,
+                content: Some(current_version.to_be_bytes().to_vec()),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::NotFound(_) => StatusCode::NotFound,
+                DatabaseError::OutOfMemory => StatusCode::InsufficientStorage,
+                _ => {
+                    eprintln!("compare and set failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(id: u32, expected_version: u64, data: &[u8]) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(&expected_version.to_be_bytes());
+        content.extend_from_slice(data);
+
+        Request {
+            version: 1,
+            command: Command::CompareAndSet,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_data_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_compare_and_set().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_compare_and_set_request(make_request(42, 7, b""), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_when_version_too_short() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_compare_and_set().never();
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&[0, 0, 7]);
+
+        let request = Request {
+            version: 1,
+            command: Command::CompareAndSet,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_compare_and_set_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn not_found_when_entry_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_compare_and_set()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_compare_and_set_request(make_request(42, 7, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn conflict_with_current_version() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_compare_and_set()
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::VersionMismatch(9)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_compare_and_set_request(make_request(42, 7, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Conflict);
+        assert_eq!(response.content, Some(9u64.to_be_bytes().to_vec()));
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_compare_and_set()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(7),
+                mockall::predicate::eq(b"hello".to_vec()),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(8));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_compare_and_set_request(make_request(42, 7, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 8);
+        assert_eq!(response.content, Some(8u64.to_be_bytes().to_vec()));
+    }
+}
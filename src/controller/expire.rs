@@ -0,0 +1,177 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, split_ttl, Request, Response, StatusCode};
+
+/// EXPIRE REQUEST
+///
+/// Lets the entry expire after the ttl, expired entries are treated as if they don't exist.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// 8 bytes u64 ttl in milliseconds
+///
+/// Responses:
+/// 200 ok
+/// 400 invalid request
+/// 404 not found
+/// 409 conflict: returned if someone is currently reading or writing to the same entry
+/// 500 internal server error
+pub(super) async fn handle_expire_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the ttl has to be the rest of the content after the key
+    let (key, ttl) = match split_key(request.version, &content) {
+        Some((key, rest)) => match split_ttl(rest) {
+            Some((ttl, [])) => (key, ttl),
+            _ => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.expire(key, ttl).await {
+        Ok(()) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 0,
+                content: None,
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::NotFound(_) => StatusCode::NotFound,
+                DatabaseError::WriteBlocked(_) => StatusCode::Conflict,
+                _ => {
+                    eprintln!("expire failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(id: u32, ttl: &[u8]) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(ttl);
+
+        Request {
+            version: 1,
+            command: Command::Expire,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_ttl_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_expire_request(make_request(42, &[]), mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_content_after_ttl() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_expire_request(make_request(42, &[0, 0, 0, 0, 0, 0, 0, 1, 0]), mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn entry_not_found() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire()
+            .times(1)
+            .returning(|key, _| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_expire_request(make_request(42, &1000u64.to_be_bytes()), mock).await;
+
+        assert_eq!(StatusCode::NotFound, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn lock_currently_in_use() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire()
+            .times(1)
+            .returning(|key, _| Err(DatabaseError::WriteBlocked(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_expire_request(make_request(42, &1000u64.to_be_bytes()), mock).await;
+
+        assert_eq!(StatusCode::Conflict, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(Duration::from_secs(1)))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_expire_request(make_request(42, &1000u64.to_be_bytes()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 0);
+    }
+}
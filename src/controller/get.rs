@@ -1,29 +1,32 @@
 use crate::repository::SharedRepository;
-use crate::types::{Request, Response, StatusCode};
+use crate::types::{max_content_length, split_key, Request, Response, StatusCode, PROTOCOL_VERSION_ENTRY_VERSION};
 
 /// GET REQUEST
 ///
 /// Request Body:
-/// 4 bytes u32 id to read
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
 ///
 /// Responses:
-/// 200 with content as body
+/// 200 with content as body, since version 3 with 8 bytes u64 version of the entry before the content
 /// 400 invalid request
 /// 404 not found
+/// 413 content too large: the data doesn't fit into the content length of the version (65535 bytes before version 5)
 pub(super) async fn handle_get_request(request: Request, db: SharedRepository) -> Response {
-    if request.content_length != 4 {
-        return Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
             version: request.version,
             command: request.command,
             status_code: StatusCode::InvalidRequest,
             content_length: 0,
             content: None,
         }
-    }
+    };
 
-    let content = match request.content {
-        Some(content) => content,
-        None => return Response {
+    // the content has to be only the key
+    let key = match split_key(request.version, &content) {
+        Some((key, [])) => key,
+        _ => return Response {
             version: request.version,
             command: request.command,
             status_code: StatusCode::InvalidRequest,
@@ -32,16 +35,31 @@ pub(super) async fn handle_get_request(request: Request, db: SharedRepository) -
         }
     };
 
-    // it is safe to assume, that all 4 bytes are here
-    let id = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+    match db.get(key).await {
+        Some((data, version)) => {
+            let value = if request.version >= PROTOCOL_VERSION_ENTRY_VERSION {
+                let mut value = version.to_be_bytes().to_vec();
+                value.extend_from_slice(&data);
+                value
+            } else {
+                data
+            };
+
+            if value.len() > max_content_length(request.version) as usize {
+                return Response {
+                    version: request.version,
+                    command: request.command,
+                    status_code: StatusCode::ContentTooLarge,
+                    content_length: 0,
+                    content: None,
+                };
+            }
 
-    match db.get(id).await {
-        Some(value) => {
             Response {
                 version: request.version,
                 command: request.command,
                 status_code: StatusCode::Ok,
-                content_length: value.len() as u16,
+                content_length: value.len() as u32,
                 content: Some(value),
             }
         }
@@ -62,9 +80,9 @@ mod tests {
     use super::*;
     use std::sync::Arc;
     use crate::repository::MockRepository;
-    use crate::types::{Command, Request};
+    use crate::types::{Command, Request, PROTOCOL_VERSION_KEY, PROTOCOL_VERSION_LARGE_CONTENT};
 
-    fn make_request(content_length: u16, content: Option<Vec<u8>>) -> Request {
+    fn make_request(content_length: u32, content: Option<Vec<u8>>) -> Request {
         Request {
             version: 1,
             command: Command::Get,
@@ -108,7 +126,7 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_get()
-            .with(mockall::predicate::eq(42u32))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
             .times(1)
             .returning(|_| None);
 
@@ -125,9 +143,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_get()
-            .with(mockall::predicate::eq(42u32))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
             .times(1)
-            .returning(|_| Some(b"hello".to_vec()));
+            .returning(|_| Some((b"hello".to_vec(), 7)));
 
         let mock = Arc::new(mock);
 
@@ -138,4 +156,119 @@ mod tests {
         assert_eq!(response.content_length, 5);
         assert_eq!(response.content, Some(b"hello".to_vec()));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn valid_request_with_key() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .with(mockall::predicate::eq(b"session:abc123".to_vec()))
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 7)));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Get,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_get_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn invalid_request_when_key_shorter_than_key_length() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get().never();
+
+        let mock = Arc::new(mock);
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Get,
+            content_length: 4,
+            content: Some(vec![0, 3, b'k', b'e']),
+        };
+        let response = handle_get_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn valid_request_with_entry_version() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .with(mockall::predicate::eq(b"session:abc123".to_vec()))
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 7)));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_ENTRY_VERSION,
+            command: Command::Get,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_get_request(request, mock).await;
+
+        let mut expected = 7u64.to_be_bytes().to_vec();
+        expected.extend_from_slice(b"hello");
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 13);
+        assert_eq!(response.content, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn content_too_large_when_data_does_not_fit_version() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(1)
+            .returning(|_| Some((vec![0; 70_000], 7)));
+
+        let mock = Arc::new(mock);
+
+        let request = make_request(4, Some(vec![0,0,0,42]));
+        let response = handle_get_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::ContentTooLarge);
+        assert_eq!(response.content_length, 0);
+        assert_eq!(response.content, None);
+    }
+
+    #[tokio::test]
+    async fn valid_request_with_large_content() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(1)
+            .returning(|_| Some((vec![0; 70_000], 7)));
+
+        let mock = Arc::new(mock);
+
+        let request = Request {
+            version: PROTOCOL_VERSION_LARGE_CONTENT,
+            command: Command::Get,
+            content_length: 4,
+            content: Some(vec![0, 2, b'h', b'i']),
+        };
+        let response = handle_get_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 8 + 70_000);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::repository::SharedRepository;
+use crate::types::{max_content_length, split_key, split_u64, Request, Response, StatusCode};
+
+/// GETRANGE REQUEST
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// 8 bytes u64 offset of the first byte
+/// 8 bytes u64 length
+///
+/// Responses:
+/// 200 with up to length bytes of the data starting at the offset as body,
+///     the bytes after the end of the data are left out and at most 65535 bytes (since version 5 4294967295 bytes) are returned
+/// 400 invalid request
+/// 404 not found
+pub(super) async fn handle_get_range_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the content has to be only the key, the offset and the length
+    let (key, offset, length) = match split_key(request.version, &content) {
+        Some((key, rest)) => match split_u64(rest) {
+            Some((offset, rest)) => match split_u64(rest) {
+                Some((length, [])) => (key, offset, length),
+                _ => return Response {
+                    version: request.version,
+                    command: request.command,
+                    status_code: StatusCode::InvalidRequest,
+                    content_length: 0,
+                    content: None,
+                }
+            },
+            None => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the range has to fit into the content of a response
+    match db.get_range(key, offset, length.min(max_content_length(request.version) as u64)).await {
+        Some(data) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: data.len() as u32,
+                content: Some(data),
+            }
+        }
+        None => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::NotFound,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(id: u32, offset: u64, length: u64) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(&offset.to_be_bytes());
+        content.extend_from_slice(&length.to_be_bytes());
+
+        Request {
+            version: 1,
+            command: Command::GetRange,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_length_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get_range().never();
+
+        let mock = Arc::new(mock);
+
+        let mut request = make_request(42, 6, 5);
+        request.content.as_mut().unwrap().truncate(12);
+        let response = handle_get_range_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn not_found_when_entry_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get_range()
+            .times(1)
+            .returning(|_, _, _| None);
+
+        let mock = Arc::new(mock);
+
+        let response = handle_get_range_request(make_request(42, 6, 5), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get_range()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(6), mockall::predicate::eq(5))
+            .times(1)
+            .returning(|_, _, _| Some(b"world".to_vec()));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_get_range_request(make_request(42, 6, 5), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 5);
+        assert_eq!(response.content, Some(b"world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn length_limited_to_response() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get_range()
+            .with(mockall::predicate::always(), mockall::predicate::eq(0), mockall::predicate::eq(u16::MAX as u64))
+            .times(1)
+            .returning(|_, _, _| Some(vec![]));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_get_range_request(make_request(42, 0, u64::MAX), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::types::{Request, Response, StatusCode, PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN};
+
+/// HELLO REQUEST
+///
+/// Negotiates the protocol version: the highest version supported by both the client and the server.
+/// The client uses it for the following requests, the HELLO request itself can be sent with any supported version
+/// (version 1 is understood by every server).
+///
+/// Request Body:
+/// u8 lowest protocol version supported by the client
+/// u8 highest protocol version supported by the client
+///
+/// Responses:
+/// 200 with 1 byte u8 negotiated version as body
+/// 400 invalid request
+/// 505 version not supported: no version is supported by both,
+///     with u8 lowest and u8 highest version supported by the server as body
+pub(super) fn handle_hello_request(request: Request) -> Response {
+    let (client_min, client_max) = match request.content.as_deref() {
+        Some(&[client_min, client_max]) if client_min <= client_max => (client_min, client_max),
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    let version = client_max.min(PROTOCOL_VERSION_MAX);
+    if version < client_min.max(PROTOCOL_VERSION_MIN) {
+        let content = vec![PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_MAX];
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::VersionNotSupported,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+    }
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code: StatusCode::Ok,
+        content_length: 1,
+        content: Some(vec![version]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Command, Request};
+
+    fn make_request(content: Option<Vec<u8>>) -> Request {
+        Request {
+            version: 1,
+            command: Command::Hello,
+            content_length: content.as_ref().map_or(0, |content| content.len() as u32),
+            content,
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[test]
+    fn invalid_request_when_content_missing() {
+        let response = handle_hello_request(make_request(None));
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[test]
+    fn invalid_request_when_content_not_2_bytes() {
+        let response = handle_hello_request(make_request(Some(vec![1, 2, 3])));
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[test]
+    fn invalid_request_when_lowest_above_highest() {
+        let response = handle_hello_request(make_request(Some(vec![3, 2])));
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[test]
+    fn highest_version_of_the_client() {
+        let response = handle_hello_request(make_request(Some(vec![1, 3])));
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 1);
+        assert_eq!(response.content, Some(vec![3]));
+    }
+
+    #[test]
+    fn highest_version_of_the_server() {
+        let response = handle_hello_request(make_request(Some(vec![2, 200])));
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(vec![PROTOCOL_VERSION_MAX]));
+    }
+
+    #[test]
+    fn version_not_supported_when_client_too_new() {
+        let response = handle_hello_request(make_request(Some(vec![PROTOCOL_VERSION_MAX + 1, 200])));
+
+        assert_eq!(response.status_code, StatusCode::VersionNotSupported);
+        assert_eq!(response.content, Some(vec![PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_MAX]));
+    }
+
+    #[test]
+    fn version_not_supported_when_client_too_old() {
+        let response = handle_hello_request(make_request(Some(vec![0, 0])));
+
+        assert_eq!(response.status_code, StatusCode::VersionNotSupported);
+    }
+}
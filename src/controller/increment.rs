@@ -0,0 +1,217 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::integer::IntegerEncoding;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, split_u64, Command, Request, Response, StatusCode};
+
+/// INCR REQUEST / DECR REQUEST / INCRBY REQUEST
+///
+/// Adds 1 (INCR), -1 (DECR) or the delta (INCRBY) to the integer stored in the entry.
+/// A missing entry counts as 0 and is inserted, the expiry of an existing entry is kept.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// u8 encoding of the integer in the entry (0: 8 bytes i64 big endian, 1: ASCII decimal)
+/// INCRBY only: 8 bytes i64 delta
+///
+/// Responses:
+/// 200 with 8 bytes i64 new integer as body
+/// 400 invalid request
+/// 422 unprocessable content: the entry isn't an integer in the encoding or the integer would overflow
+/// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
+pub(super) async fn handle_increment_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    let (key, encoding, rest) = match split_key(request.version, &content) {
+        Some((key, [encoding, rest @ ..])) => match IntegerEncoding::try_from(*encoding) {
+            Ok(encoding) => (key, encoding, rest),
+            Err(_) => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // only INCRBY has a delta after the encoding
+    let delta = match (request.command, rest) {
+        (Command::Incr, []) => 1,
+        (Command::Decr, []) => -1,
+        (Command::IncrBy, rest) => match split_u64(rest) {
+            Some((delta, [])) => delta as i64,
+            _ => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.increment(key, delta, encoding).await {
+        Ok(value) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 8,
+                content: Some(value.to_be_bytes().to_vec()),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::NotAnInteger(_) | DatabaseError::IntegerOverflow(_) => StatusCode::UnprocessableContent,
+                DatabaseError::OutOfMemory => StatusCode::InsufficientStorage,
+                _ => {
+                    eprintln!("increment failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+
+    fn make_request(command: Command, id: u32, rest: &[u8]) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(rest);
+
+        Request {
+            version: 1,
+            command,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_encoding_missing_or_unknown() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_increment().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_increment_request(make_request(Command::Incr, 42, &[]), mock.clone()).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        let response = handle_increment_request(make_request(Command::Incr, 42, &[2]), mock).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_when_delta_missing_or_unexpected() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_increment().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_increment_request(make_request(Command::IncrBy, 42, &[0, 0, 0, 5]), mock.clone()).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        let response = handle_increment_request(make_request(Command::Incr, 42, &[0, 5]), mock).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn unprocessable_when_not_an_integer() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_increment()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::NotAnInteger(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_increment_request(make_request(Command::Incr, 42, &[1]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::UnprocessableContent);
+    }
+
+    #[tokio::test]
+    async fn valid_requests() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_increment()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(1),
+                mockall::predicate::eq(IntegerEncoding::Ascii),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(1));
+        mock.expect_increment()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(-1),
+                mockall::predicate::eq(IntegerEncoding::BigEndian),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(0));
+        mock.expect_increment()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(-10),
+                mockall::predicate::eq(IntegerEncoding::BigEndian),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(-10));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_increment_request(make_request(Command::Incr, 42, &[1]), mock.clone()).await;
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(1i64.to_be_bytes().to_vec()));
+
+        let response = handle_increment_request(make_request(Command::Decr, 42, &[0]), mock.clone()).await;
+        assert_eq!(response.content, Some(0i64.to_be_bytes().to_vec()));
+
+        let mut rest = vec![0];
+        rest.extend_from_slice(&(-10i64).to_be_bytes());
+        let response = handle_increment_request(make_request(Command::IncrBy, 42, &rest), mock).await;
+        assert_eq!(response.content_length, 8);
+        assert_eq!(response.content, Some((-10i64).to_be_bytes().to_vec()));
+    }
+}
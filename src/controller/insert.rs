@@ -1,31 +1,32 @@
 use crate::repository::error::DatabaseError;
 use crate::repository::SharedRepository;
-use crate::types::{Request, Response, StatusCode};
+use crate::types::{split_key, split_ttl, Command, Request, Response, StatusCode};
 
 /// SET REQUEST
 ///
 /// Request Body:
-/// 4 bytes u32 id to read
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
 /// content (at least 1 byte)
 ///
 /// Responses:
 /// 200 with content as body
 /// 400 invalid request
 /// 409 conflict: entry with id already exists
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
 pub(super) async fn handle_insert_request(request: Request, db: SharedRepository) -> Response {
-    // if no id or content empty (smaller than 1 byte)
-    if request.content_length <= 5 {
-        return Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
             version: request.version,
             command: request.command,
             status_code: StatusCode::InvalidRequest,
             content_length: 0,
             content: None,
-        };
-    }
+        }
+    };
 
-    let mut content = match request.content {
-        Some(content) => content,
+    let (key, rest) = match split_key(request.version, &content) {
+        Some((key, rest)) => (key, rest),
         None => return Response {
             version: request.version,
             command: request.command,
@@ -35,15 +36,33 @@ pub(super) async fn handle_insert_request(request: Request, db: SharedRepository
         }
     };
 
-    // since content_length is > 5 we don't need to check the length of content
-    let id = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+    // INSERT EX has the ttl between the key and the data
+    let (ttl, data) = match request.command {
+        Command::InsertEx => match split_ttl(rest) {
+            Some((ttl, data)) => (Some(ttl), data),
+            None => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        _ => (None, rest),
+    };
 
-    // remove the first 4 bytes from the content (id)
-    for _ in 0..4 {
-        content.remove(0);
+    // the data can't be empty
+    if data.is_empty() {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        };
     }
 
-    match db.insert(id, content).await {
+    match db.insert(key, data.to_vec(), ttl).await {
         Ok(()) => {
             Response {
                 version: request.version,
@@ -62,6 +81,13 @@ pub(super) async fn handle_insert_request(request: Request, db: SharedRepository
                     content_length: 0,
                     content: None,
                 },
+                DatabaseError::OutOfMemory => Response {
+                    version: request.version,
+                    command: request.command,
+                    status_code: StatusCode::InsufficientStorage,
+                    content_length: 0,
+                    content: None,
+                },
                 _ => Response {
                     version: request.version,
                     command: request.command,
@@ -79,9 +105,10 @@ pub(super) async fn handle_insert_request(request: Request, db: SharedRepository
 mod tests {
     use super::*;
     use std::sync::Arc;
+    use std::time::Duration;
     use crate::repository::error::DatabaseError;
     use crate::repository::{MockRepository};
-    use crate::types::{Command, Request};
+    use crate::types::PROTOCOL_VERSION_KEY;
 
     fn make_request(id: u32, data: Vec<u8>) -> Request {
         let mut content = id.to_be_bytes().to_vec();
@@ -90,7 +117,7 @@ mod tests {
         Request {
             version: 1,
             command: Command::Insert,
-            content_length: content.len() as u16,
+            content_length: content.len() as u32,
             content: Some(content),
         }
     }
@@ -140,9 +167,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_insert()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Err(DatabaseError::AlreadyExists(42)));
+            .returning(|_, _, _| Err(DatabaseError::AlreadyExists(42u32.to_be_bytes().to_vec())));
 
         let mock = Arc::new(mock);
 
@@ -152,14 +179,31 @@ mod tests {
         assert_eq!(StatusCode::Conflict, response.status_code);
     }
 
+    #[tokio::test]
+    async fn out_of_memory()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::OutOfMemory));
+
+        let mock = Arc::new(mock);
+
+        let request = make_request(42, b"hello".to_vec());
+        let response = handle_insert_request(request, mock).await;
+
+        assert_eq!(StatusCode::InsufficientStorage, response.status_code);
+    }
+
     #[tokio::test]
     async fn unknown_db_error()  {
         let mut mock = MockRepository::new();
 
         mock.expect_insert()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Err(DatabaseError::NotFound(42))); // Not Found should not be returned
+            .returning(|_, _, _| Err(DatabaseError::NotFound(42u32.to_be_bytes().to_vec()))); // Not Found should not be returned
 
         let mock = Arc::new(mock);
 
@@ -174,9 +218,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_insert()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Ok(()));
+            .returning(|_, _, _| Ok(()));
 
         let mock = Arc::new(mock);
 
@@ -186,4 +230,104 @@ mod tests {
         assert_eq!(response.status_code, StatusCode::Ok);
         assert_eq!(0, response.content_length);
     }
+
+    #[tokio::test]
+    async fn valid_request_with_key()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert()
+            .with(mockall::predicate::eq(b"session:abc123".to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+        content.extend_from_slice(b"hello");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Insert,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_insert_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_with_key_and_no_data()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert().never();
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Insert,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_insert_request(request, mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn valid_request_with_ttl()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(b"hello".to_vec()),
+                mockall::predicate::eq(Some(Duration::from_secs(60))),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&60_000u64.to_be_bytes());
+        content.extend_from_slice(b"hello");
+
+        let request = Request {
+            version: 1,
+            command: Command::InsertEx,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_insert_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_ttl_too_short()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert().never();
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&[0, 0, 0]);
+
+        let request = Request {
+            version: 1,
+            command: Command::InsertEx,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_insert_request(request, mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
 }
\ No newline at end of file
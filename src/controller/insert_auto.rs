@@ -0,0 +1,173 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{Request, Response, StatusCode};
+
+/// INSERT AUTO REQUEST
+///
+/// Inserts a new entry with an id assigned by the server, the key of the entry are the 4 big endian bytes of the id.
+///
+/// Request Body:
+/// content (at least 1 byte)
+///
+/// Responses:
+/// 200 with 4 bytes u32 assigned id as body
+/// 400 invalid request
+/// 409 conflict: all ids are already assigned
+/// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
+pub(super) async fn handle_insert_auto_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) if request.content_length > 0 => content,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.insert_auto(content).await {
+        Ok(id) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 4,
+                content: Some(id.to_be_bytes().to_vec()),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::IdsExhausted => StatusCode::Conflict,
+                DatabaseError::OutOfMemory => StatusCode::InsufficientStorage,
+                _ => {
+                    eprintln!("insert auto failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(data: Vec<u8>) -> Request {
+        Request {
+            version: 1,
+            command: Command::InsertAuto,
+            content_length: data.len() as u32,
+            content: Some(data),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_content_empty() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert_auto().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_insert_auto_request(make_request(vec![]), mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_content_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert_auto().never();
+
+        let mock = Arc::new(mock);
+
+        let request = Request {
+            version: 1,
+            command: Command::InsertAuto,
+            content_length: 5,
+            content: None,
+        };
+        let response = handle_insert_auto_request(request, mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn ids_exhausted() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert_auto()
+            .times(1)
+            .returning(|_| Err(DatabaseError::IdsExhausted));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_insert_auto_request(make_request(b"hello".to_vec()), mock).await;
+
+        assert_eq!(StatusCode::Conflict, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn out_of_memory() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert_auto()
+            .times(1)
+            .returning(|_| Err(DatabaseError::OutOfMemory));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_insert_auto_request(make_request(b"hello".to_vec()), mock).await;
+
+        assert_eq!(StatusCode::InsufficientStorage, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn unknown_db_error() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert_auto()
+            .times(1)
+            .returning(|_| Err(DatabaseError::Persistence("disk full".to_string())));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_insert_auto_request(make_request(b"hello".to_vec()), mock).await;
+
+        assert_eq!(StatusCode::InternalServerError, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert_auto()
+            .with(mockall::predicate::eq(b"hello".to_vec()))
+            .times(1)
+            .returning(|_| Ok(42));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_insert_auto_request(make_request(b"hello".to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 4);
+        assert_eq!(response.content, Some(42u32.to_be_bytes().to_vec()));
+    }
+}
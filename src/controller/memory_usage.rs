@@ -0,0 +1,138 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, Request, Response, StatusCode};
+
+/// MEMORY USAGE REQUEST
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+///
+/// Responses:
+/// 200 with 8 bytes u64 bytes used by the entry and 8 bytes u64 bytes used by all entries as body
+/// 400 invalid request
+/// 404 not found
+/// 500 internal server error
+pub(super) async fn handle_memory_usage_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the content has to be only the key
+    let key = match split_key(request.version, &content) {
+        Some((key, [])) => key,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.memory_usage(key).await {
+        Ok((entry, total)) => {
+            let mut content = entry.to_be_bytes().to_vec();
+            content.extend_from_slice(&total.to_be_bytes());
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: content.len() as u32,
+                content: Some(content),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::NotFound(_) => StatusCode::NotFound,
+                _ => {
+                    eprintln!("memory usage failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(content: Vec<u8>) -> Request {
+        Request {
+            version: 1,
+            command: Command::MemoryUsage,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_content_not_key() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_memory_usage().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_memory_usage_request(make_request(vec![0, 0, 42]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn not_found_when_entry_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_memory_usage()
+            .times(1)
+            .returning(|key| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_memory_usage_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_memory_usage()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|_| Ok((120, 4096)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_memory_usage_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        let mut content = 120u64.to_be_bytes().to_vec();
+        content.extend_from_slice(&4096u64.to_be_bytes());
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 16);
+        assert_eq!(response.content, Some(content));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::repository::SharedRepository;
+use crate::types::{append_result, max_content_length, split_keys, Request, Response, StatusCode, PROTOCOL_VERSION_ENTRY_VERSION};
+
+/// MGET REQUEST
+///
+/// Reads multiple entries in one request, every entry is read like with GET (not at a single point in time).
+///
+/// Request Body:
+/// one or more keys (version 1: 4 bytes u32 id each, version 2: u16 key length + key each)
+///
+/// Responses:
+/// 200 with the result of every key in order as body:
+///     u16 status code (200 or 404) + content length (u16, since version 5 u32) + content,
+///     the content is the same as for a GET request of the same version
+/// 400 invalid request
+/// 413 content too large: the results don't fit into the content length of the version
+pub(super) async fn handle_mget_request(request: Request, db: SharedRepository) -> Response {
+    let keys = match request.content.as_deref().and_then(|content| split_keys(request.version, content)) {
+        Some(keys) if !keys.is_empty() => keys,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    let mut content = Vec::new();
+    for key in keys {
+        match db.get(key).await {
+            Some((data, version)) if request.version >= PROTOCOL_VERSION_ENTRY_VERSION => {
+                let mut value = version.to_be_bytes().to_vec();
+                value.extend_from_slice(&data);
+                append_result(request.version, &mut content, StatusCode::Ok, &value);
+            }
+            Some((data, _)) => append_result(request.version, &mut content, StatusCode::Ok, &data),
+            None => append_result(request.version, &mut content, StatusCode::NotFound, &[]),
+        }
+
+        if content.len() > max_content_length(request.version) as usize {
+            return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::ContentTooLarge,
+                content_length: 0,
+                content: None,
+            };
+        }
+    }
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code: StatusCode::Ok,
+        content_length: content.len() as u32,
+        content: Some(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(ids: &[u32]) -> Request {
+        let content: Vec<u8> = ids.iter().flat_map(|id| id.to_be_bytes()).collect();
+
+        Request {
+            version: 1,
+            command: Command::MGet,
+            content_length: content.len() as u32,
+            content: if content.is_empty() { None } else { Some(content) },
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_keys_missing_or_incomplete() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mget_request(make_request(&[]), mock.clone()).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        let mut request = make_request(&[42, 7]);
+        request.content.as_mut().unwrap().pop();
+        let response = handle_mget_request(request, mock).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 3)));
+        mock.expect_get()
+            .with(mockall::predicate::eq(7u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|_| None);
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mget_request(make_request(&[42, 7]), mock).await;
+
+        let content = [&[0, 200, 0, 5][..], b"hello", &[0x01, 0x94, 0, 0]].concat();
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, content.len() as u32);
+        assert_eq!(response.content, Some(content));
+    }
+
+    #[tokio::test]
+    async fn valid_request_with_entry_version() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 3)));
+
+        let mock = Arc::new(mock);
+
+        let mut request = make_request(&[42]);
+        request.version = PROTOCOL_VERSION_ENTRY_VERSION;
+        request.content = Some([&[0, 2][..], b"hi"].concat());
+        let response = handle_mget_request(request, mock).await;
+
+        let content = [&[0, 200, 0, 13][..], &3u64.to_be_bytes(), b"hello"].concat();
+
+        assert_eq!(response.content, Some(content));
+    }
+
+    #[tokio::test]
+    async fn content_too_large() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(2)
+            .returning(|_| Some((vec![0; 40_000], 3)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mget_request(make_request(&[42, 7, 1]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::ContentTooLarge);
+        assert_eq!(response.content, None);
+    }
+}
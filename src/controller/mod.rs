@@ -2,20 +2,95 @@ mod get;
 mod set;
 mod remove;
 mod insert;
+mod rewrite_aof;
+mod save;
+mod background_save;
+mod insert_auto;
+mod expire;
+mod ttl;
+mod persist;
+mod memory_usage;
+mod compare_and_set;
+mod transaction;
+mod upsert;
+mod increment;
+mod append;
+mod get_range;
+mod set_range;
+mod mget;
+mod mset;
+mod mremove;
+mod hello;
 
 use get::handle_get_request;
 use remove::handle_remove_request;
 use set::handle_set_request;
 use crate::controller::insert::handle_insert_request;
+use crate::controller::rewrite_aof::handle_rewrite_aof_request;
+use crate::controller::save::handle_save_request;
+use crate::controller::background_save::handle_background_save_request;
+use crate::controller::insert_auto::handle_insert_auto_request;
+use crate::controller::expire::handle_expire_request;
+use crate::controller::ttl::handle_ttl_request;
+use crate::controller::persist::handle_persist_request;
+use crate::controller::memory_usage::handle_memory_usage_request;
+use crate::controller::compare_and_set::handle_compare_and_set_request;
+use crate::controller::upsert::handle_upsert_request;
+use crate::controller::increment::handle_increment_request;
+use crate::controller::append::handle_append_request;
+use crate::controller::get_range::handle_get_range_request;
+use crate::controller::set_range::handle_set_range_request;
+use crate::controller::mget::handle_mget_request;
+use crate::controller::mset::handle_mset_request;
+use crate::controller::mremove::handle_mremove_request;
+use crate::controller::hello::handle_hello_request;
+use crate::controller::transaction::{handle_queue_request, handle_transaction_request, handle_watch_request};
 use crate::repository::SharedRepository;
 use crate::types::{Command, Request, Response, StatusCode};
 
-pub(crate) async fn route_request(request: Request, db: SharedRepository) -> Response {
+pub(crate) use crate::controller::transaction::Transaction;
+
+/// Whether the request depends on or changes the transaction of the connection,
+/// otherwise it can be processed independently of the other requests of the connection.
+pub(crate) fn uses_transaction(command: Command, transaction: &Transaction) -> bool {
+    transaction.is_started() || is_transaction_command(command)
+}
+
+fn is_transaction_command(command: Command) -> bool {
+    matches!(command, Command::Multi | Command::Exec | Command::Discard | Command::Watch | Command::Unwatch)
+}
+
+pub(crate) async fn route_request(request: Request, db: SharedRepository, transaction: &mut Transaction) -> Response {
+    // while a transaction is started on the connection the requests are queued until EXEC or DISCARD
+    if transaction.is_started() && !is_transaction_command(request.command) {
+        return handle_queue_request(request, transaction);
+    }
+
     match request.command {
         Command::Get => handle_get_request(request, db).await,
-        Command::Set => handle_set_request(request, db).await,
-        Command::Insert => handle_insert_request(request, db).await,
+        Command::Set | Command::SetEx | Command::SetWait => handle_set_request(request, db).await,
+        Command::Insert | Command::InsertEx => handle_insert_request(request, db).await,
         Command::Remove => handle_remove_request(request, db).await,
+        Command::RewriteAof => handle_rewrite_aof_request(request, db).await,
+        Command::Save => handle_save_request(request, db).await,
+        Command::BackgroundSave => handle_background_save_request(request, db).await,
+        Command::InsertAuto => handle_insert_auto_request(request, db).await,
+        Command::Expire => handle_expire_request(request, db).await,
+        Command::Ttl => handle_ttl_request(request, db).await,
+        Command::Persist => handle_persist_request(request, db).await,
+        Command::MemoryUsage => handle_memory_usage_request(request, db).await,
+        Command::CompareAndSet => handle_compare_and_set_request(request, db).await,
+        Command::Upsert | Command::UpsertEx => handle_upsert_request(request, db).await,
+        Command::Incr | Command::Decr | Command::IncrBy => handle_increment_request(request, db).await,
+        Command::Append => handle_append_request(request, db).await,
+        Command::GetRange => handle_get_range_request(request, db).await,
+        Command::SetRange => handle_set_range_request(request, db).await,
+        Command::MGet => handle_mget_request(request, db).await,
+        Command::MSet => handle_mset_request(request, db).await,
+        Command::MRemove => handle_mremove_request(request, db).await,
+        Command::Multi | Command::Exec | Command::Discard => handle_transaction_request(request, db, transaction).await,
+        Command::Watch | Command::Unwatch => handle_watch_request(request, db, transaction).await,
+        Command::Hello => handle_hello_request(request),
         Command::Invalid => Response {
             version: request.version,
             command: request.command,
@@ -0,0 +1,140 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{append_result, empty_result_length, max_content_length, split_keys, Request, Response, StatusCode};
+
+/// MREMOVE REQUEST
+///
+/// Removes multiple entries in one request, every entry is removed like with REMOVE (not atomically).
+///
+/// Request Body:
+/// one or more keys (version 1: 4 bytes u32 id each, version 2: u16 key length + key each)
+///
+/// Responses:
+/// 200 with the result of every key in order as body:
+///     u16 status code (200, 404 or 500) + content length (0, u16, since version 5 u32)
+/// 400 invalid request
+/// 413 content too large: the results wouldn't fit into the content length of the version, nothing is changed
+pub(super) async fn handle_mremove_request(request: Request, db: SharedRepository) -> Response {
+    let keys = match request.content.as_deref().and_then(|content| split_keys(request.version, content)) {
+        Some(keys) if !keys.is_empty() => keys,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // checked before any entry is removed, so a response that doesn't fit doesn't hide the changes
+    if keys.len() * empty_result_length(request.version) > max_content_length(request.version) as usize {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::ContentTooLarge,
+            content_length: 0,
+            content: None,
+        };
+    }
+
+    let mut content = Vec::with_capacity(keys.len() * empty_result_length(request.version));
+    for key in keys {
+        let status_code = match db.remove(key).await {
+            Ok(()) => StatusCode::Ok,
+            Err(DatabaseError::NotFound(_)) => StatusCode::NotFound,
+            Err(err) => {
+                eprintln!("remove failed: {}", err);
+                StatusCode::InternalServerError
+            }
+        };
+
+        append_result(request.version, &mut content, status_code, &[]);
+    }
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code: StatusCode::Ok,
+        content_length: content.len() as u32,
+        content: Some(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(ids: &[u32]) -> Request {
+        let content: Vec<u8> = ids.iter().flat_map(|id| id.to_be_bytes()).collect();
+
+        Request {
+            version: 1,
+            command: Command::MRemove,
+            content_length: content.len() as u32,
+            content: if content.is_empty() { None } else { Some(content) },
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_keys_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_remove().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mremove_request(make_request(&[]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_remove()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_remove()
+            .with(mockall::predicate::eq(7u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|key| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mremove_request(make_request(&[42, 7]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 8);
+        assert_eq!(response.content, Some(vec![0, 200, 0, 0, 0x01, 0x94, 0, 0]));
+    }
+
+    #[tokio::test]
+    async fn content_too_large_when_results_dont_fit() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_remove().never();
+
+        let mock = Arc::new(mock);
+
+        // 3 bytes per key in the request, but 4 bytes per result in the response
+        let content = [0u8, 1, b'k'].repeat(20_000);
+        let request = Request {
+            version: 2,
+            command: Command::MRemove,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+
+        let response = handle_mremove_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::ContentTooLarge);
+        assert_eq!(response.content, None);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::{Key, SharedRepository};
+use crate::types::{append_result, empty_result_length, split_content_length, split_key, Request, Response, StatusCode};
+
+/// MSET REQUEST
+///
+/// Replaces the data of multiple entries in one request, every entry is replaced like with SET (not atomically).
+///
+/// Request Body:
+/// one or more of:
+///     key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+///     content length (u16, since version 5 u32)
+///     content of specified length (at least 1 byte)
+///
+/// Responses:
+/// 200 with the result of every key in order as body:
+///     u16 status code (200, 404, 409, 500 or 507, like SET) + content length (0, u16, since version 5 u32)
+/// 400 invalid request
+///
+/// The results always fit into the content length, every entry of the request is longer than its result.
+pub(super) async fn handle_mset_request(request: Request, db: SharedRepository) -> Response {
+    let entries = match request.content.as_deref().and_then(|content| split_entries(request.version, content)) {
+        Some(entries) if !entries.is_empty() => entries,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    let mut content = Vec::with_capacity(entries.len() * empty_result_length(request.version));
+    for (key, data) in entries {
+        let status_code = match db.set(key, data, None).await {
+            Ok(()) => StatusCode::Ok,
+            Err(DatabaseError::NotFound(_)) => StatusCode::NotFound,
+            Err(DatabaseError::WriteBlocked(_)) => StatusCode::Conflict,
+            Err(DatabaseError::OutOfMemory) => StatusCode::InsufficientStorage,
+            Err(err) => {
+                eprintln!("set failed: {}", err);
+                StatusCode::InternalServerError
+            }
+        };
+
+        append_result(request.version, &mut content, status_code, &[]);
+    }
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code: StatusCode::Ok,
+        content_length: content.len() as u32,
+        content: Some(content),
+    }
+}
+
+/// Splits the content into keys and their data, None if it isn't a sequence of valid keys with data
+fn split_entries(version: u8, mut content: &[u8]) -> Option<Vec<(Key, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    while !content.is_empty() {
+        let (key, rest) = split_key(version, content)?;
+        let (length, rest) = split_content_length(version, rest)?;
+
+        if length == 0 || rest.len() < length {
+            return None;
+        }
+
+        let (data, rest) = rest.split_at(length);
+        entries.push((key, data.to_vec()));
+        content = rest;
+    }
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(entries: &[(u32, &[u8])]) -> Request {
+        let mut content = Vec::new();
+        for (id, data) in entries {
+            content.extend_from_slice(&id.to_be_bytes());
+            content.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            content.extend_from_slice(data);
+        }
+
+        Request {
+            version: 1,
+            command: Command::MSet,
+            content_length: content.len() as u32,
+            content: if content.is_empty() { None } else { Some(content) },
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[test]
+    fn test_split_entries() {
+        let content = [&[0, 0, 0, 42, 0, 2][..], b"hi", &[0, 0, 0, 7, 0, 1], b"!"].concat();
+        assert_eq!(split_entries(1, &content), Some(vec![(vec![0, 0, 0, 42], b"hi".to_vec()), (vec![0, 0, 0, 7], b"!".to_vec())]));
+
+        // data shorter than its length
+        assert_eq!(split_entries(1, &content[..content.len() - 1]), None);
+        // empty data
+        assert_eq!(split_entries(1, &[0, 0, 0, 42, 0, 0]), None);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_when_entries_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mset_request(make_request(&[]), mock.clone()).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        let response = handle_mset_request(make_request(&[(42, b"hello"), (7, b"")]), mock).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        mock.expect_set()
+            .with(mockall::predicate::eq(7u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"world".to_vec()), mockall::predicate::eq(None))
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_mset_request(make_request(&[(42, b"hello"), (7, b"world")]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(vec![0, 200, 0, 0, 0x01, 0x94, 0, 0]));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, Request, Response, StatusCode};
+
+/// PERSIST REQUEST
+///
+/// Removes the expiry of the entry, so it never expires.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+///
+/// Responses:
+/// 200 ok
+/// 400 invalid request
+/// 404 not found
+/// 409 conflict: returned if someone is currently reading or writing to the same entry
+/// 500 internal server error
+pub(super) async fn handle_persist_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the content has to be only the key
+    let key = match split_key(request.version, &content) {
+        Some((key, [])) => key,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.persist(key).await {
+        Ok(()) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 0,
+                content: None,
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::NotFound(_) => StatusCode::NotFound,
+                DatabaseError::WriteBlocked(_) => StatusCode::Conflict,
+                _ => {
+                    eprintln!("persist failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(content: Vec<u8>) -> Request {
+        Request {
+            version: 1,
+            command: Command::Persist,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_content_not_key() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_persist().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_persist_request(make_request(vec![0, 42]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn not_found_when_entry_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_persist()
+            .times(1)
+            .returning(|key| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_persist_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_persist()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_persist_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+}
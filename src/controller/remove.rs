@@ -1,11 +1,11 @@
 use crate::repository::error::DatabaseError;
 use crate::repository::SharedRepository;
-use crate::types::{Request, Response, StatusCode};
+use crate::types::{split_key, Request, Response, StatusCode};
 
 /// REMOVE REQUEST
 ///
 /// Request Body:
-/// 4 bytes u32 id to read
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
 ///
 /// Responses:
 /// 200 ok
@@ -13,19 +13,21 @@ use crate::types::{Request, Response, StatusCode};
 /// 404 not found
 /// 500 internal server error
 pub(super) async fn handle_remove_request(request: Request, db: SharedRepository) -> Response {
-    if request.content_length != 4 {
-        return Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
             version: request.version,
             command: request.command,
             status_code: StatusCode::InvalidRequest,
             content_length: 0,
             content: None,
         }
-    }
+    };
 
-    let content = match request.content {
-        Some(content) => content,
-        None => return Response {
+    // the content has to be only the key
+    let key = match split_key(request.version, &content) {
+        Some((key, [])) => key,
+        _ => return Response {
             version: request.version,
             command: request.command,
             status_code: StatusCode::InvalidRequest,
@@ -34,10 +36,7 @@ pub(super) async fn handle_remove_request(request: Request, db: SharedRepository
         }
     };
 
-    // it is safe to assume, that all 4 bytes are here, since content_length is 4
-    let id = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
-
-    match db.remove(id).await {
+    match db.remove(key).await {
         Ok(()) => {
             Response {
                 version: request.version,
@@ -74,9 +73,9 @@ mod tests {
     use super::*;
     use std::sync::Arc;
     use crate::repository::MockRepository;
-    use crate::types::{Command, Request};
+    use crate::types::{Command, Request, PROTOCOL_VERSION_KEY};
 
-    fn make_request(content_length: u16, content: Option<Vec<u8>>) -> Request {
+    fn make_request(content_length: u32, content: Option<Vec<u8>>) -> Request {
         Request {
             version: 1,
             command: Command::Get,
@@ -120,9 +119,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_remove()
-            .with(mockall::predicate::eq(42u32))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
             .times(1)
-            .returning(|_| Err(DatabaseError::NotFound(42)));
+            .returning(|_| Err(DatabaseError::NotFound(42u32.to_be_bytes().to_vec())));
 
         let mock = Arc::new(mock);
 
@@ -137,9 +136,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_remove()
-            .with(mockall::predicate::eq(42u32))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
             .times(1)
-            .returning(|_| Err(DatabaseError::AlreadyExists(42))); // Already Exists should not be returned
+            .returning(|_| Err(DatabaseError::AlreadyExists(42u32.to_be_bytes().to_vec()))); // Already Exists should not be returned
 
         let mock = Arc::new(mock);
 
@@ -154,7 +153,7 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_remove()
-            .with(mockall::predicate::eq(42u32))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
             .times(1)
             .returning(|_| Ok(()));
 
@@ -165,4 +164,29 @@ mod tests {
 
         assert_eq!(response.status_code, StatusCode::Ok);
     }
+
+    #[tokio::test]
+    async fn valid_request_with_key() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_remove()
+            .with(mockall::predicate::eq(b"session:abc123".to_vec()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Remove,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_remove_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
 }
\ No newline at end of file
@@ -1,33 +1,37 @@
 use crate::repository::error::DatabaseError;
 use crate::repository::SharedRepository;
-use crate::types::{Request, Response, StatusCode};
+use crate::types::{split_key, split_ttl, Command, Request, Response, StatusCode};
 
-/// SET REQUEST
+/// SET REQUEST / SET EX REQUEST / SET WAIT REQUEST
 ///
 /// Request Body:
-/// 4 bytes u32 id to read
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// SET EX only: 8 bytes u64 ttl in milliseconds, after which the entry expires
+/// SET WAIT only: 8 bytes u64 timeout in milliseconds, how long to wait if someone else is using the entry
 /// content (at least 1 byte)
 ///
 /// Responses:
 /// 200 ok
 /// 400 invalid request
 /// 404 not found
-/// 409 conflict: returned if someone is currently reading or writing to the same entry
+/// 408 timeout: SET WAIT only, someone else used the entry until the timeout
+/// 409 conflict: returned if someone is currently reading or writing to the same entry (not for SET WAIT)
 /// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
 pub(super) async fn handle_set_request(request: Request, db: SharedRepository) -> Response {
-    // if no id or content empty (smaller than 1 byte)
-    if request.content_length <= 5 {
-        return Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
             version: request.version,
             command: request.command,
             status_code: StatusCode::InvalidRequest,
             content_length: 0,
             content: None,
-        };
-    }
+        }
+    };
 
-    let mut content = match request.content {
-        Some(content) => content,
+    let (key, rest) = match split_key(request.version, &content) {
+        Some((key, rest)) => (key, rest),
         None => return Response {
             version: request.version,
             command: request.command,
@@ -37,15 +41,38 @@ pub(super) async fn handle_set_request(request: Request, db: SharedRepository) -
         }
     };
 
-    // since content_length is > 5 we don't need to check the length of content
-    let id = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+    // SET EX has the ttl and SET WAIT the timeout between the key and the data
+    let (duration, data) = match request.command {
+        Command::SetEx | Command::SetWait => match split_ttl(rest) {
+            Some((duration, data)) => (Some(duration), data),
+            None => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        _ => (None, rest),
+    };
 
-    // remove the first 4 bytes from the content (id)
-    for _ in 0..4 {
-        content.remove(0);
+    // the data can't be empty
+    if data.is_empty() {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        };
     }
 
-    match db.set(id, content).await {
+    let result = match (request.command, duration) {
+        (Command::SetWait, Some(timeout)) => db.set_wait(key, data.to_vec(), timeout).await,
+        (_, ttl) => db.set(key, data.to_vec(), ttl).await,
+    };
+
+    match result {
         Ok(()) => {
             Response {
                 version: request.version,
@@ -71,6 +98,20 @@ pub(super) async fn handle_set_request(request: Request, db: SharedRepository) -
                     content_length: 0,
                     content: None,
                 },
+                DatabaseError::Timeout(_) => Response {
+                    version: request.version,
+                    command: request.command,
+                    status_code: StatusCode::Timeout,
+                    content_length: 0,
+                    content: None,
+                },
+                DatabaseError::OutOfMemory => Response {
+                    version: request.version,
+                    command: request.command,
+                    status_code: StatusCode::InsufficientStorage,
+                    content_length: 0,
+                    content: None,
+                },
                 _ => Response {
                     version: request.version,
                     command: request.command,
@@ -87,9 +128,10 @@ pub(super) async fn handle_set_request(request: Request, db: SharedRepository) -
 mod tests {
     use super::*;
     use std::sync::Arc;
+    use std::time::Duration;
     use crate::repository::error::DatabaseError;
     use crate::repository::{MockRepository};
-    use crate::types::{Command, Request};
+    use crate::types::PROTOCOL_VERSION_KEY;
 
     fn make_request(id: u32, data: Vec<u8>) -> Request {
         let mut content = id.to_be_bytes().to_vec();
@@ -98,7 +140,7 @@ mod tests {
         Request {
             version: 1,
             command: Command::Set,
-            content_length: content.len() as u16,
+            content_length: content.len() as u32,
             content: Some(content),
         }
     }
@@ -148,9 +190,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_set()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Err(DatabaseError::NotFound(42)));
+            .returning(|_, _, _| Err(DatabaseError::NotFound(42u32.to_be_bytes().to_vec())));
 
         let mock = Arc::new(mock);
 
@@ -165,9 +207,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_set()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Err(DatabaseError::WriteBlocked(42)));
+            .returning(|_, _, _| Err(DatabaseError::WriteBlocked(42u32.to_be_bytes().to_vec())));
 
         let mock = Arc::new(mock);
 
@@ -177,14 +219,31 @@ mod tests {
         assert_eq!(StatusCode::Conflict, response.status_code);
     }
 
+    #[tokio::test]
+    async fn out_of_memory()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::OutOfMemory));
+
+        let mock = Arc::new(mock);
+
+        let request = make_request(42, b"hello".to_vec());
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(StatusCode::InsufficientStorage, response.status_code);
+    }
+
     #[tokio::test]
     async fn unknown_db_error()  {
         let mut mock = MockRepository::new();
 
         mock.expect_set()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Err(DatabaseError::AlreadyExists(42))); // Already Exists should not be returned
+            .returning(|_, _, _| Err(DatabaseError::AlreadyExists(42u32.to_be_bytes().to_vec()))); // Already Exists should not be returned
 
         let mock = Arc::new(mock);
 
@@ -199,9 +258,9 @@ mod tests {
         let mut mock = MockRepository::new();
 
         mock.expect_set()
-            .with(mockall::predicate::eq(42u32), mockall::predicate::eq(b"hello".to_vec()))
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
             .times(1)
-            .returning(|_, _| Ok(()));
+            .returning(|_, _, _| Ok(()));
 
         let mock = Arc::new(mock);
 
@@ -211,4 +270,160 @@ mod tests {
         assert_eq!(response.status_code, StatusCode::Ok);
         assert_eq!(0, response.content_length);
     }
+
+    #[tokio::test]
+    async fn valid_request_with_key()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set()
+            .with(mockall::predicate::eq(b"session:abc123".to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(None))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+        content.extend_from_slice(b"hello");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Set,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_with_key_and_no_data()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set().never();
+
+        let mock = Arc::new(mock);
+
+        let mut content = 14u16.to_be_bytes().to_vec();
+        content.extend_from_slice(b"session:abc123");
+
+        let request = Request {
+            version: PROTOCOL_VERSION_KEY,
+            command: Command::Set,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn valid_request_with_ttl()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(b"hello".to_vec()),
+                mockall::predicate::eq(Some(Duration::from_secs(60))),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&60_000u64.to_be_bytes());
+        content.extend_from_slice(b"hello");
+
+        let request = Request {
+            version: 1,
+            command: Command::SetEx,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_ttl_too_short()  {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set().never();
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&[0, 0, 0]);
+
+        let request = Request {
+            version: 1,
+            command: Command::SetEx,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(StatusCode::InvalidRequest, response.status_code);
+    }
+
+    #[tokio::test]
+    async fn valid_request_wait() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set().never();
+        mock.expect_set_wait()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(b"hello".to_vec()),
+                mockall::predicate::eq(Duration::from_millis(250)),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&250u64.to_be_bytes());
+        content.extend_from_slice(b"hello");
+
+        let request = Request {
+            version: 1,
+            command: Command::SetWait,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn wait_timed_out() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set_wait()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::Timeout(key)));
+
+        let mock = Arc::new(mock);
+
+        let mut content = 42u32.to_be_bytes().to_vec();
+        content.extend_from_slice(&250u64.to_be_bytes());
+        content.extend_from_slice(b"hello");
+
+        let request = Request {
+            version: 1,
+            command: Command::SetWait,
+            content_length: content.len() as u32,
+            content: Some(content),
+        };
+        let response = handle_set_request(request, mock).await;
+
+        assert_eq!(StatusCode::Timeout, response.status_code);
+    }
 }
\ No newline at end of file
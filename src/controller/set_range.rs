@@ -0,0 +1,150 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::{SharedRepository, MAX_DATA_LENGTH};
+use crate::types::{split_key, split_u64, Request, Response, StatusCode};
+
+/// SETRANGE REQUEST
+///
+/// Overwrites the data of the entry starting at the offset with the content. The data is extended with
+/// zero bytes if it ends before the offset, a missing entry is inserted as if its data was empty.
+/// The expiry of an existing entry is kept.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// 8 bytes u64 offset
+/// content (at least 1 byte)
+///
+/// Responses:
+/// 200 with 8 bytes u64 new length of the data of the entry as body
+/// 400 invalid request: also if the content would end after 512 MiB
+/// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
+pub(super) async fn handle_set_range_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the offset is between the key and the data, the data can't be empty
+    let (key, offset, data) = match split_key(request.version, &content) {
+        Some((key, rest)) => match split_u64(rest) {
+            Some((offset, data)) if !data.is_empty() && offset.saturating_add(data.len() as u64) <= MAX_DATA_LENGTH => (key, offset, data),
+            _ => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.set_range(key, offset, data.to_vec()).await {
+        Ok(length) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 8,
+                content: Some(length.to_be_bytes().to_vec()),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::OutOfMemory => StatusCode::InsufficientStorage,
+                _ => {
+                    eprintln!("set range failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(id: u32, offset: u64, data: &[u8]) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(&offset.to_be_bytes());
+        content.extend_from_slice(data);
+
+        Request {
+            version: 1,
+            command: Command::SetRange,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_data_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set_range().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_set_range_request(make_request(42, 6, b""), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_when_range_too_large() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set_range().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_set_range_request(make_request(42, MAX_DATA_LENGTH, b"hello"), mock.clone()).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        let response = handle_set_range_request(make_request(42, u64::MAX, b"hello"), mock).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set_range()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()), mockall::predicate::eq(6), mockall::predicate::eq(b"there".to_vec()))
+            .times(1)
+            .returning(|_, _, _| Ok(11));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_set_range_request(make_request(42, 6, b"there"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(11u64.to_be_bytes().to_vec()));
+    }
+}
@@ -0,0 +1,665 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::transaction::{TransactionOperation, TransactionOutcome};
+use crate::repository::{Key, SharedRepository};
+use crate::types::{append_result, empty_result_length, max_content_length, split_key, split_keys, Command, Request, Response, StatusCode, PROTOCOL_VERSION_ENTRY_VERSION};
+
+/// most requests queued by a transaction, the index of a failed request is sent as u16
+const MAX_QUEUED_REQUESTS: usize = u16::MAX as usize + 1;
+
+/// The transaction state of a connection
+#[derive(Debug, Default)]
+pub(crate) struct Transaction {
+    // the versions of the watched entries when they were watched, None if there was no entry
+    watched: Vec<(Key, Option<u64>)>,
+    // the operations queued since MULTI, None if no transaction is started
+    operations: Option<Vec<TransactionOperation>>,
+    // a request couldn't be queued, so EXEC aborts the transaction
+    invalid: bool,
+}
+
+impl Transaction {
+    /// Whether a transaction is started by MULTI, so the requests are queued
+    pub(crate) fn is_started(&self) -> bool {
+        self.operations.is_some()
+    }
+}
+
+/// MULTI REQUEST / EXEC REQUEST / DISCARD REQUEST
+///
+/// MULTI starts a transaction on the connection, the following GET, SET, INSERT and REMOVE requests
+/// are queued until EXEC executes them atomically or DISCARD drops them.
+/// EXEC and DISCARD forget the watched entries (see WATCH).
+///
+/// Request Body:
+/// none
+///
+/// Responses:
+/// MULTI:
+/// 200 ok
+/// 400 invalid request: a transaction is already started
+///
+/// EXEC:
+/// 200 with the result of every queued request in order as body:
+///     u16 status code (200 or GET only 404 or 413) + content length (u16, since version 5 u32) + content,
+///     the content of GET is the same as for a GET request of the version of the EXEC request,
+///     GET has status 413 and no content if its content doesn't fit into the rest of the response
+/// 400 invalid request: no transaction is started or a request couldn't be queued, nothing is changed
+/// 409 conflict: a queued request failed, nothing is changed,
+///     with u16 index of the request and u16 status code of its failure (404 or 409) as body
+/// 412 precondition failed: a watched entry was changed, nothing is changed
+/// 413 content too large: the results without content wouldn't fit into the content length of the version, nothing is changed
+/// 500 internal server error
+/// 507 insufficient storage: the changes don't fit into the memory limit, transactions don't evict entries
+///
+/// DISCARD:
+/// 200 ok
+/// 400 invalid request: no transaction is started
+pub(super) async fn handle_transaction_request(request: Request, db: SharedRepository, transaction: &mut Transaction) -> Response {
+    if request.content.is_some() {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        };
+    }
+
+    let status_code = match (request.command, transaction.is_started()) {
+        (Command::Multi, false) => {
+            transaction.operations = Some(Vec::new());
+            StatusCode::Ok
+        }
+        (Command::Discard, true) => {
+            *transaction = Transaction::default();
+            StatusCode::Ok
+        }
+        (Command::Exec, true) => {
+            let Transaction { watched, operations, invalid } = std::mem::take(transaction);
+            if !invalid {
+                return execute(request, db, watched, operations.unwrap_or_default()).await;
+            }
+            StatusCode::InvalidRequest
+        }
+        // nested transactions aren't supported, the started one stays open
+        _ => StatusCode::InvalidRequest,
+    };
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code,
+        content_length: 0,
+        content: None,
+    }
+}
+
+/// WATCH REQUEST / UNWATCH REQUEST
+///
+/// WATCH remembers the versions of the entries, the next EXEC of the connection only executes the transaction
+/// if none of them was changed since (by any connection, also this one). Changes of the data and of the expiry count,
+/// an entry that didn't exist counts as unchanged as long as it doesn't exist.
+/// UNWATCH forgets all watched entries.
+///
+/// Request Body:
+/// WATCH: one or more keys (version 1: 4 bytes u32 id each, version 2: u16 key length + key each)
+/// UNWATCH: none
+///
+/// Responses:
+/// 200 ok
+/// 400 invalid request: a transaction is started or the body is invalid
+pub(super) async fn handle_watch_request(request: Request, db: SharedRepository, transaction: &mut Transaction) -> Response {
+    // the entries have to be watched before the transaction is started
+    if transaction.is_started() {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        };
+    }
+
+    let status_code = match (request.command, request.content) {
+        (Command::Watch, Some(content)) => match split_keys(request.version, &content) {
+            Some(keys) => {
+                for key in keys {
+                    let version = db.version(key.clone()).await;
+                    transaction.watched.push((key, version));
+                }
+                StatusCode::Ok
+            }
+            None => StatusCode::InvalidRequest,
+        },
+        (Command::Unwatch, None) => {
+            transaction.watched.clear();
+            StatusCode::Ok
+        }
+        _ => StatusCode::InvalidRequest,
+    };
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code,
+        content_length: 0,
+        content: None,
+    }
+}
+
+/// QUEUED REQUEST
+///
+/// Any request other than MULTI, EXEC, DISCARD, WATCH or UNWATCH while a transaction is started on the connection.
+/// Only GET, SET, INSERT and REMOVE can be queued, their request body is the same as without a transaction.
+///
+/// Responses:
+/// 202 queued
+/// 400 invalid request: the request can't be queued (also after 65536 queued requests), EXEC will abort the transaction
+pub(super) fn handle_queue_request(request: Request, transaction: &mut Transaction) -> Response {
+    let status_code = match (parse_operation(&request), transaction.operations.as_mut()) {
+        (Some(operation), Some(operations)) if operations.len() < MAX_QUEUED_REQUESTS => {
+            operations.push(operation);
+            StatusCode::Queued
+        }
+        _ => {
+            transaction.invalid = true;
+            StatusCode::InvalidRequest
+        }
+    };
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code,
+        content_length: 0,
+        content: None,
+    }
+}
+
+/// Returns the operation of a queueable request, None if the request is invalid or can't be queued
+fn parse_operation(request: &Request) -> Option<TransactionOperation> {
+    let content = request.content.as_ref()?;
+    let (key, rest) = split_key(request.version, content)?;
+
+    // the data of SET and INSERT can't be empty
+    match request.command {
+        Command::Get if rest.is_empty() => Some(TransactionOperation::Get(key)),
+        Command::Set if !rest.is_empty() => Some(TransactionOperation::Set(key, rest.to_vec())),
+        Command::Insert if !rest.is_empty() => Some(TransactionOperation::Insert(key, rest.to_vec())),
+        Command::Remove if rest.is_empty() => Some(TransactionOperation::Remove(key)),
+        _ => None,
+    }
+}
+
+async fn execute(request: Request, db: SharedRepository, watched: Vec<(Key, Option<u64>)>, operations: Vec<TransactionOperation>) -> Response {
+    // checked before the transaction is committed, so the results of committed changes are never lost
+    let max_content_length = max_content_length(request.version) as usize;
+    let results_length = operations.len() * empty_result_length(request.version);
+    if results_length > max_content_length {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::ContentTooLarge,
+            content_length: 0,
+            content: None,
+        };
+    }
+
+    match db.execute(watched, operations).await {
+        Ok(outcomes) => {
+            // the content of the GETs has to fit into what is left after the results without content
+            let mut remaining = max_content_length - results_length;
+            let mut content = Vec::with_capacity(results_length);
+            for outcome in outcomes {
+                let (status_code, value) = match outcome {
+                    TransactionOutcome::Found(data, version) if request.version >= PROTOCOL_VERSION_ENTRY_VERSION => {
+                        let mut value = version.to_be_bytes().to_vec();
+                        value.extend_from_slice(&data);
+                        (StatusCode::Ok, value)
+                    }
+                    TransactionOutcome::Found(data, _) => (StatusCode::Ok, data),
+                    TransactionOutcome::NotFound => (StatusCode::NotFound, vec![]),
+                    TransactionOutcome::Done => (StatusCode::Ok, vec![]),
+                };
+
+                if value.len() > remaining {
+                    append_result(request.version, &mut content, StatusCode::ContentTooLarge, &[]);
+                    continue;
+                }
+                remaining -= value.len();
+
+                append_result(request.version, &mut content, status_code, &value);
+            }
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: content.len() as u32,
+                content: Some(content),
+            }
+        }
+        Err(DatabaseError::TransactionFailed(index, err)) => {
+            let status_code = match *err {
+                DatabaseError::NotFound(_) => StatusCode::NotFound,
+                _ => StatusCode::Conflict,
+            };
+
+            // at most MAX_QUEUED_REQUESTS are queued, so the index fits
+            let mut content = (index as u16).to_be_bytes().to_vec();
+            content.extend_from_slice(&(status_code as u16).to_be_bytes());
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Conflict,
+                content_length: content.len() as u32,
+                content: Some(content),
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::WatchedEntryChanged(_) => StatusCode::PreconditionFailed,
+                DatabaseError::OutOfMemory => StatusCode::InsufficientStorage,
+                _ => {
+                    eprintln!("exec failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request, PROTOCOL_VERSION_KEY};
+
+    fn make_request(command: Command) -> Request {
+        Request {
+            version: PROTOCOL_VERSION_KEY,
+            command,
+            content_length: 0,
+            content: None,
+        }
+    }
+
+    fn make_key_request(command: Command, key: &[u8], data: &[u8]) -> Request {
+        let mut content = (key.len() as u16).to_be_bytes().to_vec();
+        content.extend_from_slice(key);
+        content.extend_from_slice(data);
+
+        Request {
+            version: PROTOCOL_VERSION_KEY,
+            command,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    fn started() -> Transaction {
+        Transaction { operations: Some(Vec::new()), ..Transaction::default() }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn multi_starts_transaction() {
+        let mock = Arc::new(MockRepository::new());
+        let mut transaction = Transaction::default();
+
+        let response = handle_transaction_request(make_request(Command::Multi), mock.clone(), &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert!(transaction.is_started());
+
+        // nested
+        let response = handle_transaction_request(make_request(Command::Multi), mock, &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+        assert!(transaction.is_started());
+    }
+
+    #[tokio::test]
+    async fn invalid_request_without_transaction() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute().never();
+
+        let mock = Arc::new(mock);
+        let mut transaction = Transaction::default();
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock.clone(), &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        let response = handle_transaction_request(make_request(Command::Discard), mock, &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn discard_drops_transaction() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute().never();
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+
+        let response = handle_transaction_request(make_request(Command::Discard), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert!(!transaction.is_started());
+    }
+
+    #[test]
+    fn queue_requests() {
+        let mut transaction = started();
+
+        assert_eq!(handle_queue_request(make_key_request(Command::Get, b"old", b""), &mut transaction).status_code, StatusCode::Queued);
+        assert_eq!(handle_queue_request(make_key_request(Command::Insert, b"new", b"hello"), &mut transaction).status_code, StatusCode::Queued);
+        assert_eq!(handle_queue_request(make_key_request(Command::Set, b"new", b"world"), &mut transaction).status_code, StatusCode::Queued);
+        assert_eq!(handle_queue_request(make_key_request(Command::Remove, b"old", b""), &mut transaction).status_code, StatusCode::Queued);
+
+        assert_eq!(transaction.operations, Some(vec![
+            TransactionOperation::Get(b"old".to_vec()),
+            TransactionOperation::Insert(b"new".to_vec(), b"hello".to_vec()),
+            TransactionOperation::Set(b"new".to_vec(), b"world".to_vec()),
+            TransactionOperation::Remove(b"old".to_vec()),
+        ]));
+        assert!(!transaction.invalid);
+    }
+
+    #[test]
+    fn queue_invalid_requests() {
+        for request in [
+            make_key_request(Command::Set, b"key", b""),
+            make_key_request(Command::Get, b"key", b"data"),
+            make_key_request(Command::SetEx, b"key", b"data"),
+            make_request(Command::Save),
+        ] {
+            let mut transaction = started();
+
+            let response = handle_queue_request(request, &mut transaction);
+
+            assert_eq!(response.status_code, StatusCode::InvalidRequest);
+            assert!(transaction.invalid);
+        }
+    }
+
+    #[test]
+    fn queue_at_most_max_queued_requests() {
+        let mut transaction = Transaction {
+            operations: Some(vec![TransactionOperation::Remove(b"key".to_vec()); MAX_QUEUED_REQUESTS]),
+            ..Transaction::default()
+        };
+
+        let response = handle_queue_request(make_key_request(Command::Remove, b"key", b""), &mut transaction);
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+        assert!(transaction.invalid);
+    }
+
+    #[tokio::test]
+    async fn exec_aborts_invalid_transaction() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute().never();
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+        handle_queue_request(make_request(Command::Save), &mut transaction);
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+        assert!(!transaction.is_started());
+    }
+
+    #[tokio::test]
+    async fn exec_with_results() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute()
+            .with(mockall::predicate::eq(vec![]), mockall::predicate::eq(vec![
+                TransactionOperation::Get(b"old".to_vec()),
+                TransactionOperation::Remove(b"old".to_vec()),
+                TransactionOperation::Get(b"old".to_vec()),
+            ]))
+            .times(1)
+            .returning(|_, _| Ok(vec![
+                TransactionOutcome::Found(b"hello".to_vec(), 7),
+                TransactionOutcome::Done,
+                TransactionOutcome::NotFound,
+            ]));
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+        for request in [
+            make_key_request(Command::Get, b"old", b""),
+            make_key_request(Command::Remove, b"old", b""),
+            make_key_request(Command::Get, b"old", b""),
+        ] {
+            handle_queue_request(request, &mut transaction);
+        }
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        let content = [
+            &[0, 200, 0, 5][..], b"hello",
+            &[0, 200, 0, 0],
+            &[0x01, 0x94, 0, 0],
+        ].concat();
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, content.len() as u32);
+        assert_eq!(response.content, Some(content));
+        assert!(!transaction.is_started());
+    }
+
+    #[tokio::test]
+    async fn exec_content_too_large_before_commit() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute().never();
+
+        let mock = Arc::new(mock);
+        // 4 bytes per result don't fit into 65535 bytes of a version 2 response
+        let mut transaction = Transaction {
+            operations: Some(vec![TransactionOperation::Remove(b"key".to_vec()); 20_000]),
+            ..Transaction::default()
+        };
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::ContentTooLarge);
+        assert!(!transaction.is_started());
+    }
+
+    #[tokio::test]
+    async fn exec_get_content_too_large() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute()
+            .times(1)
+            .returning(|_, _| Ok(vec![
+                TransactionOutcome::Found(vec![0; 40_000], 1),
+                TransactionOutcome::Found(vec![0; 40_000], 2),
+                TransactionOutcome::Done,
+            ]));
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+        for request in [
+            make_key_request(Command::Get, b"a", b""),
+            make_key_request(Command::Get, b"b", b""),
+            make_key_request(Command::Remove, b"c", b""),
+        ] {
+            handle_queue_request(request, &mut transaction);
+        }
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        // the second GET doesn't fit anymore, the transaction is still reported as committed
+        let content = [
+            &[0, 200, 0x9C, 0x40][..], &vec![0; 40_000],
+            &[0x01, 0x9D, 0, 0],
+            &[0, 200, 0, 0],
+        ].concat();
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(content));
+    }
+
+    #[tokio::test]
+    async fn exec_with_entry_version() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute()
+            .times(1)
+            .returning(|_, _| Ok(vec![TransactionOutcome::Found(b"hello".to_vec(), 7)]));
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+        handle_queue_request(make_key_request(Command::Get, b"key", b""), &mut transaction);
+
+        let mut request = make_request(Command::Exec);
+        request.version = PROTOCOL_VERSION_ENTRY_VERSION;
+        let response = handle_transaction_request(request, mock, &mut transaction).await;
+
+        let content = [&[0, 200, 0, 13][..], &7u64.to_be_bytes(), b"hello"].concat();
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content, Some(content));
+    }
+
+    #[tokio::test]
+    async fn exec_conflict_with_failed_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute()
+            .times(1)
+            .returning(|_, _| Err(DatabaseError::TransactionFailed(1, Box::new(DatabaseError::AlreadyExists(b"new".to_vec())))));
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+        handle_queue_request(make_key_request(Command::Remove, b"old", b""), &mut transaction);
+        handle_queue_request(make_key_request(Command::Insert, b"new", b"hello"), &mut transaction);
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::Conflict);
+        assert_eq!(response.content, Some(vec![0, 1, 0x01, 0x99]));
+    }
+
+    #[tokio::test]
+    async fn exec_insufficient_storage() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute()
+            .times(1)
+            .returning(|_, _| Err(DatabaseError::OutOfMemory));
+
+        let mock = Arc::new(mock);
+        let mut transaction = started();
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::InsufficientStorage);
+    }
+
+    #[tokio::test]
+    async fn watch_keys() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_version()
+            .with(mockall::predicate::eq(b"old".to_vec()))
+            .times(1)
+            .returning(|_| Some(7));
+        mock.expect_version()
+            .with(mockall::predicate::eq(b"new".to_vec()))
+            .times(1)
+            .returning(|_| None);
+
+        let mock = Arc::new(mock);
+        let mut transaction = Transaction::default();
+
+        let mut request = make_key_request(Command::Watch, b"old", &[0, 3]);
+        request.content.as_mut().unwrap().extend_from_slice(b"new");
+        let response = handle_watch_request(request, mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(transaction.watched, vec![(b"old".to_vec(), Some(7)), (b"new".to_vec(), None)]);
+    }
+
+    #[tokio::test]
+    async fn watch_invalid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_version().never();
+
+        let mock = Arc::new(mock);
+
+        // incomplete second key
+        let mut transaction = Transaction::default();
+        let response = handle_watch_request(make_key_request(Command::Watch, b"old", &[0, 3]), mock.clone(), &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+        assert!(transaction.watched.is_empty());
+
+        let response = handle_watch_request(make_request(Command::Watch), mock.clone(), &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+
+        // inside of a transaction
+        let mut transaction = started();
+        let response = handle_watch_request(make_key_request(Command::Watch, b"old", b""), mock, &mut transaction).await;
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+        assert!(transaction.is_started());
+    }
+
+    #[tokio::test]
+    async fn unwatch_forgets_keys() {
+        let mock = Arc::new(MockRepository::new());
+        let mut transaction = Transaction { watched: vec![(b"old".to_vec(), Some(7))], ..Transaction::default() };
+
+        let response = handle_watch_request(make_request(Command::Unwatch), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert!(transaction.watched.is_empty());
+    }
+
+    #[tokio::test]
+    async fn exec_with_watched_keys() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_execute()
+            .with(mockall::predicate::eq(vec![(b"old".to_vec(), Some(7))]), mockall::predicate::eq(vec![]))
+            .times(1)
+            .returning(|_, _| Err(DatabaseError::WatchedEntryChanged(b"old".to_vec())));
+
+        let mock = Arc::new(mock);
+        let mut transaction = Transaction { watched: vec![(b"old".to_vec(), Some(7))], ..started() };
+
+        let response = handle_transaction_request(make_request(Command::Exec), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::PreconditionFailed);
+        assert!(transaction.watched.is_empty());
+    }
+
+    #[tokio::test]
+    async fn discard_forgets_watched_keys() {
+        let mock = Arc::new(MockRepository::new());
+        let mut transaction = Transaction { watched: vec![(b"old".to_vec(), Some(7))], ..started() };
+
+        let response = handle_transaction_request(make_request(Command::Discard), mock, &mut transaction).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert!(transaction.watched.is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, Request, Response, StatusCode};
+
+/// TTL REQUEST
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+///
+/// Responses:
+/// 200 with 8 bytes u64 remaining ttl in milliseconds as body, empty if the entry never expires
+/// 400 invalid request
+/// 404 not found
+/// 500 internal server error
+pub(super) async fn handle_ttl_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // the content has to be only the key
+    let key = match split_key(request.version, &content) {
+        Some((key, [])) => key,
+        _ => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    match db.ttl(key).await {
+        Ok(Some(ttl)) => {
+            let ttl = (ttl.as_millis().min(u64::MAX as u128) as u64).to_be_bytes().to_vec();
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: ttl.len() as u32,
+                content: Some(ttl),
+            }
+        }
+        Ok(None) => {
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::Ok,
+                content_length: 0,
+                content: None,
+            }
+        }
+        Err(err) => {
+            let status_code = match err {
+                DatabaseError::NotFound(_) => StatusCode::NotFound,
+                _ => {
+                    eprintln!("ttl failed: {}", err);
+                    StatusCode::InternalServerError
+                }
+            };
+
+            Response {
+                version: request.version,
+                command: request.command,
+                status_code,
+                content_length: 0,
+                content: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::repository::MockRepository;
+    use crate::types::{Command, Request};
+
+    fn make_request(content: Vec<u8>) -> Request {
+        Request {
+            version: 1,
+            command: Command::Ttl,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_content_not_key() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_ttl().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_ttl_request(make_request(vec![0, 0, 0, 42, 0]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn not_found_when_entry_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_ttl()
+            .times(1)
+            .returning(|key| Err(DatabaseError::NotFound(key)));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_ttl_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn empty_when_entry_never_expires() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_ttl()
+            .times(1)
+            .returning(|_| Ok(None));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_ttl_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 0);
+    }
+
+    #[tokio::test]
+    async fn valid_request() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_ttl()
+            .with(mockall::predicate::eq(42u32.to_be_bytes().to_vec()))
+            .times(1)
+            .returning(|_| Ok(Some(Duration::from_millis(1500))));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_ttl_request(make_request(42u32.to_be_bytes().to_vec()), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.content_length, 8);
+        assert_eq!(response.content, Some(1500u64.to_be_bytes().to_vec()));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::SharedRepository;
+use crate::types::{split_key, split_ttl, Command, Request, Response, StatusCode};
+
+/// UPSERT REQUEST / UPSERT EX REQUEST
+///
+/// Replaces the data of the entry like SET, or inserts the entry like INSERT if it doesn't exist,
+/// without another client being able to insert or remove the entry in between.
+///
+/// Request Body:
+/// key (version 1: 4 bytes u32 id, version 2: u16 key length + key)
+/// UPSERT EX only: 8 bytes u64 ttl in milliseconds, after which the entry expires
+/// content (at least 1 byte)
+///
+/// Responses:
+/// 200 ok: the entry was replaced
+/// 201 created: the entry was inserted
+/// 400 invalid request
+/// 500 internal server error
+/// 507 insufficient storage: the memory limit is reached and no entry can be evicted
+pub(super) async fn handle_upsert_request(request: Request, db: SharedRepository) -> Response {
+    let content = match request.content {
+        Some(content) => content,
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    let (key, rest) = match split_key(request.version, &content) {
+        Some((key, rest)) => (key, rest),
+        None => return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        }
+    };
+
+    // UPSERT EX has the ttl between the key and the data
+    let (ttl, data) = match request.command {
+        Command::UpsertEx => match split_ttl(rest) {
+            Some((ttl, data)) => (Some(ttl), data),
+            None => return Response {
+                version: request.version,
+                command: request.command,
+                status_code: StatusCode::InvalidRequest,
+                content_length: 0,
+                content: None,
+            }
+        },
+        _ => (None, rest),
+    };
+
+    // the data can't be empty
+    if data.is_empty() {
+        return Response {
+            version: request.version,
+            command: request.command,
+            status_code: StatusCode::InvalidRequest,
+            content_length: 0,
+            content: None,
+        };
+    }
+
+    let status_code = match db.upsert(key, data.to_vec(), ttl).await {
+        Ok(true) => StatusCode::Created,
+        Ok(false) => StatusCode::Ok,
+        Err(DatabaseError::OutOfMemory) => StatusCode::InsufficientStorage,
+        Err(err) => {
+            eprintln!("upsert failed: {}", err);
+            StatusCode::InternalServerError
+        }
+    };
+
+    Response {
+        version: request.version,
+        command: request.command,
+        status_code,
+        content_length: 0,
+        content: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::repository::MockRepository;
+
+    fn make_request(command: Command, id: u32, data: &[u8]) -> Request {
+        let mut content = id.to_be_bytes().to_vec();
+        content.extend_from_slice(data);
+
+        Request {
+            version: 1,
+            command,
+            content_length: content.len() as u32,
+            content: Some(content),
+        }
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn invalid_request_when_data_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_upsert_request(make_request(Command::Upsert, 42, b""), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn invalid_request_when_ttl_too_short() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert().never();
+
+        let mock = Arc::new(mock);
+
+        let response = handle_upsert_request(make_request(Command::UpsertEx, 42, &[0, 0, 3]), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn created_when_inserted() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(b"hello".to_vec()),
+                mockall::predicate::eq(None),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_upsert_request(make_request(Command::Upsert, 42, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Created);
+    }
+
+    #[tokio::test]
+    async fn ok_when_replaced_with_ttl() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .with(
+                mockall::predicate::eq(42u32.to_be_bytes().to_vec()),
+                mockall::predicate::eq(b"hello".to_vec()),
+                mockall::predicate::eq(Some(Duration::from_secs(60))),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(false));
+
+        let mock = Arc::new(mock);
+
+        let mut data = 60_000u64.to_be_bytes().to_vec();
+        data.extend_from_slice(b"hello");
+        let response = handle_upsert_request(make_request(Command::UpsertEx, 42, &data), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn out_of_memory() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::OutOfMemory));
+
+        let mock = Arc::new(mock);
+
+        let response = handle_upsert_request(make_request(Command::Upsert, 42, b"hello"), mock).await;
+
+        assert_eq!(response.status_code, StatusCode::InsufficientStorage);
+    }
+}
@@ -0,0 +1,336 @@
+// the decoding of the persistence files is shared with the server, the rest is unused here
+#[allow(dead_code)]
+mod persistence;
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use anyhow::Context;
+use crate::persistence::{Operation, Record};
+use crate::persistence::snapshot::{read_records, write_snapshot, HEADER_LENGTH};
+
+const USAGE: &str = "usage: inspect <print|verify|repair> <path>
+
+  print   print every record (offset, operation, key, data length, a preview of the data and the expiry)
+  verify  check the checksums of all records, exits with 1 if the file is invalid
+  repair  drop everything after the last valid record, a backup is written to <path>.bak";
+
+/// how many bytes of the key and the data are shown by print
+const PREVIEW_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq)]
+enum FileKind {
+    Aof,
+    Snapshot,
+}
+
+/// The result of reading a persistence file
+struct Inspection {
+    kind: FileKind,
+    /// the valid records with their offset in the file
+    records: Vec<(u64, Record)>,
+    /// number of bytes from the start of the file containing valid records
+    valid_length: u64,
+    length: u64,
+    /// number of records stated in the header of a snapshot
+    expected_records: Option<u32>,
+    /// why reading stopped before the end of the file
+    error: Option<anyhow::Error>,
+}
+
+impl Inspection {
+    fn is_valid(&self) -> bool {
+        self.error.is_none()
+            && self.expected_records.is_none_or(|expected| expected as usize == self.records.len())
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let (action, path) = match args.as_slice() {
+        [action, path] => (action.as_str(), PathBuf::from(path)),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = match action {
+        "print" => print(&path),
+        "verify" => verify(&path),
+        "repair" => repair(&path),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Reads the append only file or snapshot, the kind is detected by the magic bytes of snapshots
+fn inspect(bytes: &[u8]) -> Result<Inspection, anyhow::Error> {
+    let (kind, offset, expected_records) = if bytes.starts_with(b"RCDB") {
+        let (count, _) = read_records(bytes)?;
+        (FileKind::Snapshot, HEADER_LENGTH as u64, Some(count))
+    } else {
+        (FileKind::Aof, 0, None)
+    };
+
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(offset);
+
+    let mut records = Vec::new();
+    let mut valid_length = offset;
+    let mut error = None;
+
+    loop {
+        match Record::decode(&mut cursor) {
+            Ok(Some(record)) => {
+                records.push((valid_length, record));
+                valid_length = cursor.position();
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+
+    Ok(Inspection {
+        kind,
+        records,
+        valid_length,
+        length: bytes.len() as u64,
+        expected_records,
+        error,
+    })
+}
+
+/// Shows the data as text if it is printable UTF-8, otherwise as hex
+fn preview(data: &[u8]) -> String {
+    let shown = &data[..data.len().min(PREVIEW_LENGTH)];
+    let ellipsis = if data.len() > PREVIEW_LENGTH { "..." } else { "" };
+
+    match std::str::from_utf8(shown) {
+        Ok(text) if !text.chars().any(|c| c.is_control()) => format!("\"{}\"{}", text, ellipsis),
+        _ => {
+            let hex: Vec<String> = shown.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{}{}", hex.join(" "), ellipsis)
+        }
+    }
+}
+
+fn operation_name(operation: Operation) -> &'static str {
+    match operation {
+        Operation::Set => "SET",
+        Operation::Insert => "INSERT",
+        Operation::Remove => "REMOVE",
+        Operation::InsertAuto => "INSERT AUTO",
+        Operation::Expire => "EXPIRE",
+        Operation::Persist => "PERSIST",
+        Operation::NextId => "NEXT ID",
+        Operation::Transaction => "TRANSACTION",
+    }
+}
+
+fn read(path: &Path) -> Result<Inspection, anyhow::Error> {
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    inspect(&bytes)
+        .with_context(|| format!("failed to inspect {}", path.display()))
+}
+
+fn summary(inspection: &Inspection) -> String {
+    let mut summary = format!("{:?} with {} valid records", inspection.kind, inspection.records.len());
+
+    if let Some(expected) = inspection.expected_records {
+        summary.push_str(&format!(" of {} expected", expected));
+    }
+
+    if let Some(e) = &inspection.error {
+        summary.push_str(&format!(
+            ", invalid at offset {} ({:#}), {} bytes after it",
+            inspection.valid_length,
+            e,
+            inspection.length - inspection.valid_length,
+        ));
+    }
+
+    summary
+}
+
+fn print(path: &Path) -> Result<bool, anyhow::Error> {
+    let inspection = read(path)?;
+
+    for (offset, record) in &inspection.records {
+        let expiry = match record.expires_at {
+            Some(expires_at) => format!("  expires at {} ms", expires_at),
+            None => String::new(),
+        };
+
+        println!(
+            "{:>10}  {:<11}  key {:<20}  {:>8} bytes  {}{}",
+            offset,
+            operation_name(record.operation),
+            preview(&record.key),
+            record.data.len(),
+            preview(&record.data),
+            expiry,
+        );
+    }
+
+    println!("{}", summary(&inspection));
+
+    Ok(inspection.is_valid())
+}
+
+fn verify(path: &Path) -> Result<bool, anyhow::Error> {
+    let inspection = read(path)?;
+
+    println!("{}", summary(&inspection));
+
+    Ok(inspection.is_valid())
+}
+
+fn repair(path: &Path) -> Result<bool, anyhow::Error> {
+    let inspection = read(path)?;
+
+    println!("{}", summary(&inspection));
+
+    if inspection.is_valid() {
+        println!("nothing to repair");
+        return Ok(true);
+    }
+
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(".bak");
+    std::fs::copy(path, &backup_path)
+        .context("failed to write backup")?;
+    println!("wrote backup to {}", PathBuf::from(backup_path).display());
+
+    match inspection.kind {
+        FileKind::Aof => {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .context("failed to open append only file")?;
+
+            file.set_len(inspection.valid_length)
+                .and_then(|_| file.sync_all())
+                .context("failed to truncate append only file")?;
+
+            println!("truncated to {} bytes", inspection.valid_length);
+        }
+        FileKind::Snapshot => {
+            let records: Vec<Record> = inspection.records
+                .into_iter()
+                .map(|(_, record)| record)
+                .collect();
+
+            write_snapshot(path, &records)?;
+
+            println!("rewrote snapshot with {} records", records.len());
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::temp_path;
+
+    fn record(id: u32, data: &[u8]) -> Record {
+        Record { operation: Operation::Insert, key: id.to_be_bytes().to_vec(), expires_at: None, data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_inspect_aof() {
+        let mut bytes = record(1, b"hello").encode().unwrap();
+        bytes.extend_from_slice(&record(2, b"world").encode().unwrap());
+
+        let inspection = inspect(&bytes).unwrap();
+
+        assert_eq!(inspection.kind, FileKind::Aof);
+        assert_eq!(inspection.records, vec![(0, record(1, b"hello")), (29, record(2, b"world"))]);
+        assert!(inspection.is_valid());
+    }
+
+    #[test]
+    fn test_inspect_aof_torn_write() {
+        let mut bytes = record(1, b"hello").encode().unwrap();
+        bytes.extend_from_slice(&record(2, b"world").encode().unwrap()[..4]);
+
+        let inspection = inspect(&bytes).unwrap();
+
+        assert_eq!(inspection.records.len(), 1);
+        assert_eq!(inspection.valid_length, 29);
+        assert!(!inspection.is_valid());
+    }
+
+    #[test]
+    fn test_inspect_snapshot() {
+        let path = temp_path("inspect-snapshot.rdb");
+        write_snapshot(&path, &[record(1, b"hello")]).unwrap();
+
+        let inspection = inspect(&std::fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(inspection.kind, FileKind::Snapshot);
+        assert_eq!(inspection.records, vec![(HEADER_LENGTH as u64, record(1, b"hello"))]);
+        assert_eq!(inspection.expected_records, Some(1));
+        assert!(inspection.is_valid());
+    }
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview(b"hello"), "\"hello\"");
+        assert_eq!(preview(&[0, 1, 0xFF]), "00 01 ff");
+        assert_eq!(preview(&[b'a'; 40]), format!("\"{}\"...", "a".repeat(32)));
+    }
+
+    #[test]
+    fn test_repair_aof() {
+        let path = temp_path("inspect-repair.aof");
+
+        let mut bytes = record(1, b"hello").encode().unwrap();
+        bytes.extend_from_slice(&record(2, b"world").encode().unwrap()[..4]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(!verify(&path).unwrap());
+        assert!(repair(&path).unwrap());
+        assert!(verify(&path).unwrap());
+
+        assert_eq!(std::fs::read(&path).unwrap(), record(1, b"hello").encode().unwrap());
+        assert_eq!(std::fs::read(format!("{}.bak", path.display())).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_repair_snapshot() {
+        let path = temp_path("inspect-repair.rdb");
+        write_snapshot(&path, &[record(1, b"hello"), record(2, b"world")]).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.pop();
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(!verify(&path).unwrap());
+        assert!(repair(&path).unwrap());
+        assert!(verify(&path).unwrap());
+
+        let inspection = read(&path).unwrap();
+        assert_eq!(inspection.records.len(), 1);
+        assert_eq!(inspection.expected_records, Some(1));
+    }
+}
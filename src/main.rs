@@ -1,18 +1,38 @@
+mod config;
 mod connection;
 mod controller;
+mod persistence;
 mod types;
 mod repository;
+mod resp;
 
 use std::sync::Arc;
 use tokio::net::{TcpListener};
+use crate::config::Config;
 use crate::connection::listen_for_connections;
-use crate::repository::{Repository, SharedRepository};
+use crate::resp::listen_for_resp_connections;
+use crate::repository::{spawn_aof_rewrite_task, spawn_expiry_task, Repository, SharedRepository};
 
 #[tokio::main]
 async fn main() {
-    let db: SharedRepository = Arc::new(Repository::new());
+    // panics if the arguments are invalid
+    let config = Config::from_args(std::env::args().skip(1)).expect("invalid arguments");
+
+    // panics if the persisted data can't be loaded, so no data is lost by overwriting it
+    let repository = Arc::new(Repository::open(&config).expect("loading persisted data failed"));
+    if config.appendonly {
+        spawn_aof_rewrite_task(Arc::downgrade(&repository));
+    }
+    spawn_expiry_task(Arc::downgrade(&repository));
+    let db: SharedRepository = repository;
+
     // panics if bind fails
+    if let Some(port) = config.resp_port {
+        let resp_listener = TcpListener::bind(("127.0.0.1", port)).await.expect("bind of the resp listener failed");
+        tokio::spawn(listen_for_resp_connections(resp_listener, db.clone(), config.max_frame_size));
+    }
+
     let listener = TcpListener::bind("127.0.0.1:6379").await.expect("bind failed");
 
-    listen_for_connections(listener, db).await;
-}
\ No newline at end of file
+    listen_for_connections(listener, db, config.max_frame_size).await;
+}
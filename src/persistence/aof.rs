@@ -0,0 +1,663 @@
+use anyhow::Context;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Weak};
+use std::time::Duration;
+use crate::persistence::Record;
+
+/// When the append only file is flushed to the disk (fsync)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum FsyncPolicy {
+    /// after every record, no acknowledged change is lost on a crash
+    Always,
+    /// once per second by a background task, up to one second of changes is lost on a crash
+    EverySec,
+    /// never, the operating system decides when the data is written to the disk
+    No,
+}
+
+/// When the append only file is rewritten automatically
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct AutoRewrite {
+    /// growth in percent since the last rewrite (or startup), 0 disables the automatic rewrite
+    pub(crate) percentage: u64,
+    /// the file is never rewritten automatically while it is smaller than this
+    pub(crate) min_size: u64,
+}
+
+/// The file records are written to
+///
+/// Only the data written before the last sync is guaranteed to survive a crash.
+pub(crate) trait AofWriter: Write + Send {
+    fn sync(&mut self) -> std::io::Result<()>;
+    /// Drops everything after the size, the next write continues at the new end.
+    fn truncate(&mut self, size: u64) -> std::io::Result<()>;
+    /// Another handle to the same file, syncing it syncs everything written through this one.
+    fn try_clone(&self) -> std::io::Result<Box<dyn AofWriter>>;
+}
+
+impl AofWriter for File {
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.sync_data()
+    }
+
+    fn truncate(&mut self, size: u64) -> std::io::Result<()> {
+        // opened in append mode, so the next write starts at the new end
+        self.set_len(size)
+    }
+
+    fn try_clone(&self) -> std::io::Result<Box<dyn AofWriter>> {
+        Ok(Box::new(File::try_clone(self)?))
+    }
+}
+
+/// Append Only File
+///
+/// Every change to the repository is appended as a record, replaying all records
+/// in order restores the state of the repository.
+pub(crate) struct Aof {
+    path: PathBuf,
+    writer: Box<dyn AofWriter>,
+    fsync_policy: FsyncPolicy,
+    auto_rewrite: AutoRewrite,
+    // true if records were written since the last sync
+    dirty: bool,
+    // current size of the file and its size after the last rewrite (or at startup)
+    size: u64,
+    base_size: u64,
+    // records appended while a rewrite is running, they are added to the end of the rewritten file
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl Aof {
+    pub(crate) fn new(path: PathBuf, writer: Box<dyn AofWriter>, size: u64, fsync_policy: FsyncPolicy, auto_rewrite: AutoRewrite) -> Self {
+        Aof {
+            path,
+            writer,
+            fsync_policy,
+            auto_rewrite,
+            dirty: false,
+            size,
+            base_size: size,
+            rewrite_buffer: None,
+        }
+    }
+
+    /// Opens the append only file (creates it if it doesn't exist yet) and
+    /// returns it together with all records already stored in it.
+    ///
+    /// If the file contains an invalid record (e.g. a torn write at the end), it is
+    /// truncated to the last valid record before it.
+    pub(crate) fn open(path: &Path, fsync_policy: FsyncPolicy, auto_rewrite: AutoRewrite) -> Result<(Aof, Vec<Record>), anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open append only file {}", path.display()))?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)
+            .with_context(|| format!("failed to read append only file {}", path.display()))?;
+
+        if !Record::starts_with_supported_format(&bytes) {
+            return Err(anyhow::anyhow!(
+                "append only file {} has the unsupported record format version {}",
+                path.display(),
+                bytes[0],
+            ));
+        }
+
+        let decoded = Record::decode_all(&bytes);
+
+        if let Some(e) = decoded.error {
+            eprintln!(
+                "append only file {} is invalid after record {} ({:#}), dropping the last {} bytes",
+                path.display(),
+                decoded.records.len(),
+                e,
+                bytes.len() as u64 - decoded.valid_length,
+            );
+
+            file.set_len(decoded.valid_length)
+                .and_then(|_| file.sync_all())
+                .context("failed to truncate append only file")?;
+        }
+
+        Ok((Aof::new(path.to_path_buf(), Box::new(file), decoded.valid_length, fsync_policy, auto_rewrite), decoded.records))
+    }
+
+    /// Appends the record, with the fsync policy always it is synced to the disk before returning.
+    ///
+    /// If the record can't be written (or synced), the file is truncated to the records before it,
+    /// otherwise a torn record would hide all records after it on replay, or a rejected change would be replayed.
+    pub(crate) fn append(&mut self, record: &Record) -> Result<(), anyhow::Error> {
+        let bytes = record.encode()?;
+
+        if let Err(e) = self.write(&bytes) {
+            if let Err(truncate_error) = self.writer.truncate(self.size) {
+                return Err(e.context(format!("failed to truncate append only file to {} bytes: {}", self.size, truncate_error)));
+            }
+            // the truncation isn't synced yet
+            self.dirty = true;
+            return Err(e);
+        }
+
+        self.size += bytes.len() as u64;
+
+        if let Some(rewrite_buffer) = &mut self.rewrite_buffer {
+            rewrite_buffer.extend_from_slice(&bytes);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        self.writer.write_all(bytes)
+            .context("failed to write to append only file")?;
+        self.dirty = true;
+
+        if self.fsync_policy == FsyncPolicy::Always {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes all records written since the last sync to the disk
+    pub(crate) fn sync(&mut self) -> Result<(), anyhow::Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        self.writer.flush()
+            .and_then(|_| self.writer.sync())
+            .context("failed to sync append only file")?;
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    /// Flushes the records written since the last sync and returns a handle to sync them to the disk with,
+    /// None if there is nothing to sync.
+    ///
+    /// The handle is synced without holding the lock of the aof, so appends don't wait for the disk.
+    /// If syncing it fails, sync_failed has to be called, so the records are synced again.
+    pub(crate) fn start_sync(&mut self) -> Result<Option<Box<dyn AofWriter>>, anyhow::Error> {
+        if !self.dirty {
+            return Ok(None);
+        }
+
+        self.writer.flush()
+            .context("failed to sync append only file")?;
+        let handle = self.writer.try_clone()
+            .context("failed to sync append only file")?;
+        self.dirty = false;
+
+        Ok(Some(handle))
+    }
+
+    pub(crate) fn sync_failed(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Returns true if the file grew enough since the last rewrite to be rewritten automatically
+    pub(crate) fn needs_rewrite(&self) -> bool {
+        if self.auto_rewrite.percentage == 0 || self.rewrite_buffer.is_some() || self.size < self.auto_rewrite.min_size {
+            return false;
+        }
+
+        let growth = self.size.saturating_sub(self.base_size);
+        // the base size is 0 for a new file, every growth is infinite percent then
+        growth * 100 >= self.base_size * self.auto_rewrite.percentage
+    }
+
+    /// The path the rewritten file is written to, before it replaces the append only file
+    pub(crate) fn rewrite_path(&self) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(".rewrite");
+        PathBuf::from(path)
+    }
+
+    /// Starts collecting the appended records for the rewritten file.
+    ///
+    /// Returns false if a rewrite is already running.
+    pub(crate) fn start_rewrite(&mut self) -> bool {
+        if self.rewrite_buffer.is_some() {
+            return false;
+        }
+
+        self.rewrite_buffer = Some(Vec::new());
+        true
+    }
+
+    pub(crate) fn abort_rewrite(&mut self) {
+        self.rewrite_buffer = None;
+        let _ = std::fs::remove_file(self.rewrite_path());
+    }
+
+    /// Appends the records collected since the rewrite started to the rewritten file
+    /// and replaces the append only file with it.
+    ///
+    /// The file must already contain the state of the repository at the start of the rewrite.
+    pub(crate) fn finish_rewrite(&mut self, mut file: File) -> Result<(), anyhow::Error> {
+        let rewrite_buffer = self.rewrite_buffer.take().unwrap_or_default();
+
+        file.write_all(&rewrite_buffer)
+            .and_then(|_| file.sync_data())
+            .context("failed to write rewritten append only file")?;
+
+        std::fs::rename(self.rewrite_path(), &self.path)
+            .context("failed to replace append only file")?;
+
+        let size = file.metadata()
+            .context("failed to read size of append only file")?
+            .len();
+
+        self.writer = Box::new(file);
+        self.dirty = false;
+        self.size = size;
+        self.base_size = size;
+
+        Ok(())
+    }
+}
+
+/// Writes the records to a new file at path and syncs it to the disk.
+///
+/// Returns the file, so more records can be written to it.
+pub(crate) fn write_rewrite_file(path: &Path, records: &[Record]) -> Result<File, anyhow::Error> {
+    let file = File::create(path)
+        .with_context(|| format!("failed to create rewritten append only file {}", path.display()))?;
+
+    let mut writer = BufWriter::new(file);
+    for record in records {
+        writer.write_all(&record.encode()?)
+            .context("failed to write rewritten append only file")?;
+    }
+
+    let file = writer.into_inner()
+        .context("failed to write rewritten append only file")?;
+    file.sync_data()
+        .context("failed to sync rewritten append only file")?;
+
+    Ok(file)
+}
+
+/// Runs blocking file I/O from an async worker thread.
+///
+/// On the multi threaded runtime the worker hands its other tasks to the other workers while it blocks,
+/// the current thread runtime (tests) has no other workers, so it just blocks there.
+pub(crate) fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+/// Spawns the task that syncs the append only file once per second.
+///
+/// The file is synced without holding the lock of the aof, so appends aren't blocked by the fsync.
+/// The task stops as soon as the append only file is dropped.
+pub(crate) fn spawn_fsync_task(aof: Weak<Mutex<Aof>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            let Some(aof) = aof.upgrade() else {
+                break;
+            };
+
+            // fsync blocks, so it shouldn't run on the async worker threads
+            let result = tokio::task::spawn_blocking(move || {
+                let handle = aof.lock().expect("aof mutex poisoned").start_sync()?;
+                let Some(mut handle) = handle else {
+                    return Ok(());
+                };
+
+                let result = handle.sync().context("failed to sync append only file");
+                if result.is_err() {
+                    aof.lock().expect("aof mutex poisoned").sync_failed();
+                }
+                result
+            }).await;
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("{:#}", e),
+                Err(e) => eprintln!("fsync task failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::persistence::{temp_path, Operation};
+
+    /// Simulates a disk, only the synced data survives a crash
+    #[derive(Clone)]
+    struct SimulatedDisk {
+        synced: Arc<Mutex<Vec<u8>>>,
+        written: Arc<Mutex<Vec<u8>>>,
+        failure: FailureHandle,
+    }
+
+    type FailureHandle = Arc<Mutex<Option<Failure>>>;
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Failure {
+        /// only half of the data is written
+        Write,
+        Sync,
+    }
+
+    impl Write for SimulatedDisk {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if *self.failure.lock().unwrap() == Some(Failure::Write) {
+                self.written.lock().unwrap().extend_from_slice(&buf[..buf.len() / 2]);
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AofWriter for SimulatedDisk {
+        fn sync(&mut self) -> std::io::Result<()> {
+            if *self.failure.lock().unwrap() == Some(Failure::Sync) {
+                return Err(std::io::Error::other("I/O error"));
+            }
+            self.synced.lock().unwrap().clone_from(&self.written.lock().unwrap());
+            Ok(())
+        }
+
+        fn truncate(&mut self, size: u64) -> std::io::Result<()> {
+            self.written.lock().unwrap().truncate(size as usize);
+            Ok(())
+        }
+
+        fn try_clone(&self) -> std::io::Result<Box<dyn AofWriter>> {
+            Ok(Box::new(self.clone()))
+        }
+    }
+
+    /// Returns the aof and a handle to the data that would survive a crash
+    fn simulated_aof(fsync_policy: FsyncPolicy) -> (Aof, Arc<Mutex<Vec<u8>>>) {
+        let (aof, synced, _) = failing_aof(fsync_policy);
+        (aof, synced)
+    }
+
+    /// Returns the aof, a handle to the data that would survive a crash and a handle to let the disk fail
+    fn failing_aof(fsync_policy: FsyncPolicy) -> (Aof, Arc<Mutex<Vec<u8>>>, FailureHandle) {
+        let synced = Arc::new(Mutex::new(Vec::new()));
+        let failure = Arc::new(Mutex::new(None));
+        let disk = SimulatedDisk { synced: synced.clone(), written: Arc::new(Mutex::new(Vec::new())), failure: failure.clone() };
+
+        (Aof::new(PathBuf::from("simulated.aof"), Box::new(disk), 0, fsync_policy, NO_AUTO_REWRITE), synced, failure)
+    }
+
+    fn records_after_crash(synced: &Mutex<Vec<u8>>) -> Vec<Record> {
+        Record::decode_all(&synced.lock().unwrap()).records
+    }
+
+    const NO_AUTO_REWRITE: AutoRewrite = AutoRewrite { percentage: 0, min_size: 0 };
+
+    fn record(id: u32) -> Record {
+        Record { operation: Operation::Insert, key: id.to_be_bytes().to_vec(), expires_at: None, data: b"hello".to_vec() }
+    }
+
+    #[test]
+    fn test_open_creates_file() {
+        let path = temp_path("aof-open-creates-file.aof");
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert!(records.is_empty());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let path = temp_path("aof-append-and-reopen.aof");
+
+        let insert = Record { operation: Operation::Insert, key: 1u32.to_be_bytes().to_vec(), expires_at: None, data: b"hello".to_vec() };
+        let remove = Record { operation: Operation::Remove, key: 1u32.to_be_bytes().to_vec(), expires_at: None, data: vec![] };
+
+        let (mut aof, _) = Aof::open(&path, FsyncPolicy::No, NO_AUTO_REWRITE).unwrap();
+        aof.append(&insert).unwrap();
+        aof.append(&remove).unwrap();
+        drop(aof);
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::No, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![insert, remove]);
+    }
+
+    #[test]
+    fn test_open_truncates_torn_write() {
+        let path = temp_path("aof-open-truncates-torn-write.aof");
+
+        let valid = record(1).encode().unwrap();
+        let mut bytes = valid.clone();
+        bytes.extend_from_slice(&record(2).encode().unwrap()[..5]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let (mut aof, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![record(1)]);
+        assert_eq!(std::fs::read(&path).unwrap(), valid);
+
+        // new records are appended after the last valid record
+        aof.append(&record(3)).unwrap();
+        drop(aof);
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![record(1), record(3)]);
+    }
+
+    #[test]
+    fn test_open_rejects_unsupported_format() {
+        let path = temp_path("aof-open-rejects-unsupported-format.aof");
+
+        let mut bytes = record(1).encode().unwrap();
+        bytes[0] = 2;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let err = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).err().unwrap();
+
+        assert!(err.to_string().contains("unsupported record format version 2"));
+        // the file is left as it is
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_open_truncates_corrupted_record() {
+        let path = temp_path("aof-open-truncates-corrupted-record.aof");
+
+        let mut bytes = record(1).encode().unwrap();
+        let mut corrupted = record(2).encode().unwrap();
+        corrupted[12] ^= 0xFF;
+        bytes.extend_from_slice(&corrupted);
+        bytes.extend_from_slice(&record(3).encode().unwrap());
+        std::fs::write(&path, bytes).unwrap();
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![record(1)]);
+        assert_eq!(std::fs::read(&path).unwrap(), record(1).encode().unwrap());
+    }
+
+    #[test]
+    fn test_crash_with_fsync_always() {
+        let (mut aof, synced) = simulated_aof(FsyncPolicy::Always);
+
+        aof.append(&record(1)).unwrap();
+        aof.append(&record(2)).unwrap();
+        drop(aof); // crash
+
+        assert_eq!(records_after_crash(&synced), vec![record(1), record(2)]);
+    }
+
+    #[test]
+    fn test_failed_write_is_truncated() {
+        for failed in [Failure::Write, Failure::Sync] {
+            let (mut aof, synced, failure) = failing_aof(FsyncPolicy::Always);
+
+            aof.append(&record(1)).unwrap();
+
+            *failure.lock().unwrap() = Some(failed);
+            assert!(aof.append(&record(2)).is_err());
+
+            *failure.lock().unwrap() = None;
+            aof.append(&record(3)).unwrap();
+            drop(aof); // crash
+
+            // neither a torn record nor the rejected record is left between the others
+            assert_eq!(records_after_crash(&synced), vec![record(1), record(3)]);
+        }
+    }
+
+    #[test]
+    fn test_crash_with_fsync_no() {
+        let (mut aof, synced) = simulated_aof(FsyncPolicy::No);
+
+        aof.append(&record(1)).unwrap();
+        aof.append(&record(2)).unwrap();
+        drop(aof); // crash
+
+        assert_eq!(records_after_crash(&synced), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_crash_with_fsync_everysec() {
+        let (aof, synced) = simulated_aof(FsyncPolicy::EverySec);
+        let aof = Arc::new(Mutex::new(aof));
+        spawn_fsync_task(Arc::downgrade(&aof));
+
+        aof.lock().unwrap().append(&record(1)).unwrap();
+        assert_eq!(records_after_crash(&synced), vec![]);
+
+        // the background task syncs the first record
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        aof.lock().unwrap().append(&record(2)).unwrap();
+        drop(aof); // crash
+
+        assert_eq!(records_after_crash(&synced), vec![record(1)]);
+    }
+
+    #[test]
+    fn test_start_sync() {
+        let (mut aof, synced) = simulated_aof(FsyncPolicy::EverySec);
+
+        assert!(aof.start_sync().unwrap().is_none());
+
+        aof.append(&record(1)).unwrap();
+        let mut handle = aof.start_sync().unwrap().unwrap();
+        assert!(aof.start_sync().unwrap().is_none());
+
+        // appended while the handle is synced, without the lock of the aof
+        aof.append(&record(2)).unwrap();
+        handle.sync().unwrap();
+        assert_eq!(records_after_crash(&synced), vec![record(1), record(2)]);
+
+        aof.sync_failed();
+        assert!(aof.start_sync().unwrap().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_blocking() {
+        assert_eq!(blocking(|| 42), 42);
+    }
+
+    #[test]
+    fn test_needs_rewrite() {
+        let auto_rewrite = AutoRewrite { percentage: 100, min_size: 10 };
+        let (mut aof, _) = simulated_aof(FsyncPolicy::No);
+        aof.auto_rewrite = auto_rewrite;
+
+        // smaller than min size
+        assert!(!aof.needs_rewrite());
+
+        aof.append(&record(1)).unwrap();
+        assert!(aof.needs_rewrite());
+
+        aof.base_size = aof.size;
+        aof.append(&record(2)).unwrap();
+        // grew by exactly 100 percent
+        assert!(aof.needs_rewrite());
+
+        aof.base_size = aof.size;
+        aof.append(&record(3)).unwrap();
+        // grew by 50 percent
+        assert!(!aof.needs_rewrite());
+    }
+
+    #[test]
+    fn test_needs_rewrite_disabled() {
+        let (mut aof, _) = simulated_aof(FsyncPolicy::No);
+
+        aof.append(&record(1)).unwrap();
+
+        assert!(!aof.needs_rewrite());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let path = temp_path("aof-rewrite.aof");
+
+        let (mut aof, _) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+        aof.append(&record(1)).unwrap();
+        aof.append(&Record { operation: Operation::Remove, key: 1u32.to_be_bytes().to_vec(), expires_at: None, data: vec![] }).unwrap();
+        aof.append(&record(2)).unwrap();
+
+        assert!(aof.start_rewrite());
+        assert!(!aof.start_rewrite());
+
+        // appended while the rewritten file is written
+        aof.append(&record(3)).unwrap();
+
+        let file = write_rewrite_file(&aof.rewrite_path(), &[record(2)]).unwrap();
+        aof.finish_rewrite(file).unwrap();
+
+        // appended after the rewrite
+        aof.append(&record(4)).unwrap();
+        drop(aof);
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![record(2), record(3), record(4)]);
+    }
+
+    #[test]
+    fn test_abort_rewrite() {
+        let path = temp_path("aof-abort-rewrite.aof");
+
+        let (mut aof, _) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+        aof.append(&record(1)).unwrap();
+
+        assert!(aof.start_rewrite());
+        write_rewrite_file(&aof.rewrite_path(), &[]).unwrap();
+        aof.abort_rewrite();
+
+        aof.append(&record(2)).unwrap();
+        drop(aof);
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![record(1), record(2)]);
+        assert!(!PathBuf::from(format!("{}.rewrite", path.display())).exists());
+    }
+}
@@ -0,0 +1,13 @@
+pub(crate) mod aof;
+pub(crate) mod record;
+pub(crate) mod snapshot;
+
+pub(crate) use record::{Operation, Record};
+
+/// Returns a path in the temp directory that is unique for this test process
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("redis-clone-rust-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
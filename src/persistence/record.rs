@@ -0,0 +1,350 @@
+use anyhow::Context;
+use std::io::{Cursor, ErrorKind, Read};
+
+/// version of the record format, stored in every record
+///
+/// Files of earlier versions (without keys or expiries) are rejected like snapshots of earlier versions,
+/// they were only written during development.
+const FORMAT_VERSION: u8 = 3;
+
+/// Example Record Structure
+///
+/// u8 format version
+/// u8 operation
+/// u16 key length
+/// key of specified length
+/// u64 expiry as unix time in milliseconds, 0 if the entry doesn't expire
+/// u32 data length
+/// data of specified length
+/// u32 crc32 checksum of all previous bytes of the record
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Record {
+    pub(crate) operation: Operation,
+    pub(crate) key: Vec<u8>,
+    /// unix time in milliseconds, only used by SET, INSERT and EXPIRE
+    pub(crate) expires_at: Option<u64>,
+    pub(crate) data: Vec<u8>,
+}
+
+// u8 in a record, uses the same values as the matching Command
+// operations without a matching Command start at 0x80
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Operation {
+    Set = 1,
+    Insert = 2,
+    Remove = 3,
+    InsertAuto = 7,
+    Expire = 8,
+    Persist = 10,
+    NextId = 0x80, // the key is empty, the data holds the next u32 id assigned by INSERT AUTO
+    Transaction = 0x81, // the key is empty, the data holds the encoded records of the changes of a transaction
+}
+
+impl TryFrom<u8> for Operation {
+    type Error = anyhow::Error;
+
+    fn try_from(i: u8) -> Result<Self, Self::Error> {
+        match i {
+            1 => Ok(Operation::Set),
+            2 => Ok(Operation::Insert),
+            3 => Ok(Operation::Remove),
+            7 => Ok(Operation::InsertAuto),
+            8 => Ok(Operation::Expire),
+            10 => Ok(Operation::Persist),
+            0x80 => Ok(Operation::NextId),
+            0x81 => Ok(Operation::Transaction),
+            _ => Err(anyhow::anyhow!("unknown operation {}", i)),
+        }
+    }
+}
+
+/// The records that could be decoded and the first invalid part of the bytes (if any)
+#[derive(Debug)]
+pub(crate) struct Decoded {
+    pub(crate) records: Vec<Record>,
+    /// number of bytes containing the valid records
+    pub(crate) valid_length: u64,
+    /// why decoding stopped before the end of the bytes
+    pub(crate) error: Option<anyhow::Error>,
+}
+
+/// Reads exactly length bytes and adds them to the checksum
+fn read_checked<R>(reader: &mut R, hasher: &mut crc32fast::Hasher, length: usize) -> Result<Vec<u8>, anyhow::Error>
+where
+    R: Read,
+{
+    // read with take, so a corrupted length doesn't allocate gigabytes upfront
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+
+    if bytes.len() < length {
+        return Err(anyhow::anyhow!("smaller than expected"));
+    }
+
+    hasher.update(&bytes);
+    Ok(bytes)
+}
+
+impl Record {
+    /// Combines the records into a single TRANSACTION record, so they are replayed all or nothing
+    pub(crate) fn transaction(records: &[Record]) -> Result<Record, anyhow::Error> {
+        let mut data = Vec::new();
+        for record in records {
+            data.extend_from_slice(&record.encode()?);
+        }
+
+        Ok(Record {
+            operation: Operation::Transaction,
+            key: vec![],
+            expires_at: None,
+            data,
+        })
+    }
+
+    /// Returns the key length and data length of the record, an error if they don't fit into the record format
+    pub(crate) fn lengths(&self) -> Result<(u16, u32), anyhow::Error> {
+        let key_length = u16::try_from(self.key.len())
+            .map_err(|_| anyhow::anyhow!("key of {} bytes is too long for a record", self.key.len()))?;
+        let data_length = u32::try_from(self.data.len())
+            .map_err(|_| anyhow::anyhow!("data of {} bytes is too long for a record", self.data.len()))?;
+
+        Ok((key_length, data_length))
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let (key_length, data_length) = self.lengths()?;
+
+        let mut bytes = Vec::with_capacity(24 /* Header and checksum */ + self.key.len() + self.data.len());
+
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.operation as u8);
+        bytes.extend_from_slice(&key_length.to_be_bytes());
+        bytes.extend_from_slice(&self.key);
+        bytes.extend_from_slice(&self.expires_at.unwrap_or(0).to_be_bytes());
+        bytes.extend_from_slice(&data_length.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_be_bytes());
+
+        Ok(bytes)
+    }
+
+    /// Whether the bytes start with a record of the current format version (or are empty).
+    ///
+    /// An invalid record is normally truncated as a torn write, but a file of an earlier
+    /// format version would be dropped completely, so it has to be rejected instead.
+    pub(crate) fn starts_with_supported_format(bytes: &[u8]) -> bool {
+        bytes.first().is_none_or(|version| *version == FORMAT_VERSION)
+    }
+
+    /// Reads the next record from the reader.
+    ///
+    /// Returns None if the reader is at the end, before the first byte of a record.
+    pub(crate) fn decode<R>(reader: &mut R) -> Result<Option<Record>, anyhow::Error>
+    where
+        R: Read,
+    {
+        let mut version = [0u8; 1];
+        match reader.read_exact(&mut version) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context("read format version failed"),
+        }
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&version);
+
+        if version[0] != FORMAT_VERSION {
+            return Err(anyhow::anyhow!("unsupported record format version {}", version[0]));
+        }
+
+        // u8 operation + u16 key length
+        let header = read_checked(reader, &mut hasher, 3)
+            .context("record header too short")?;
+        let operation = header[0];
+        let key_length = u16::from_be_bytes([header[1], header[2]]) as usize;
+
+        let key = read_checked(reader, &mut hasher, key_length)
+            .context("record key smaller than expected")?;
+
+        let expires_at = read_checked(reader, &mut hasher, 8)
+            .context("record header too short")?;
+        let expires_at = u64::from_be_bytes(expires_at.try_into().expect("8 bytes were read"));
+        // 0 means the entry doesn't expire
+        let expires_at = Some(expires_at).filter(|expires_at| *expires_at != 0);
+
+        let data_length = read_checked(reader, &mut hasher, 4)
+            .context("record header too short")?;
+        let data_length = u32::from_be_bytes([data_length[0], data_length[1], data_length[2], data_length[3]]) as usize;
+
+        let data = read_checked(reader, &mut hasher, data_length)
+            .context("record data smaller than expected")?;
+
+        let mut checksum = [0u8; 4];
+        reader.read_exact(&mut checksum)
+            .context("record checksum missing")?;
+
+        if hasher.finalize() != u32::from_be_bytes(checksum) {
+            return Err(anyhow::anyhow!("record checksum mismatch"));
+        }
+
+        let operation = Operation::try_from(operation)?;
+
+        Ok(Some(Record { operation, key, expires_at, data }))
+    }
+
+    /// Decodes records until the end of the bytes or the first invalid record.
+    ///
+    /// A record can't be skipped, since its length can't be trusted once it is invalid.
+    pub(crate) fn decode_all(bytes: &[u8]) -> Decoded {
+        let mut cursor = Cursor::new(bytes);
+        let mut records = Vec::new();
+        let mut valid_length = 0;
+
+        loop {
+            match Record::decode(&mut cursor) {
+                Ok(Some(record)) => {
+                    records.push(record);
+                    valid_length = cursor.position();
+                }
+                Ok(None) => return Decoded { records, valid_length, error: None },
+                Err(e) => return Decoded { records, valid_length, error: Some(e) },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(operation: Operation, key: &[u8], data: &[u8]) -> Record {
+        Record { operation, key: key.to_vec(), expires_at: None, data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_encode() {
+        let encoded = record(Operation::Set, b"key", b"hello").encode().unwrap();
+
+        let expected = vec![
+            3,              // format version
+            1,              // operation
+            0, 3,           // key length
+            b'k', b'e', b'y',
+            0, 0, 0, 0, 0, 0, 0, 0, // expires at
+            0, 0, 0, 5,     // data length
+            b'h', b'e', b'l', b'l', b'o',
+        ];
+
+        assert_eq!(encoded[..encoded.len() - 4], expected);
+        assert_eq!(encoded[encoded.len() - 4..], crc32fast::hash(&expected).to_be_bytes());
+    }
+
+    #[test]
+    fn test_encode_too_long() {
+        let key = vec![b'k'; u16::MAX as usize + 1];
+
+        let err = record(Operation::Set, &key, b"hello").encode().unwrap_err();
+        assert!(err.to_string().contains("key of 65536 bytes is too long"));
+        assert!(Record::transaction(&[record(Operation::Set, &key, b"hello")]).is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut bytes = record(Operation::Insert, b"key", b"hello").encode().unwrap();
+        bytes.append(&mut record(Operation::Remove, b"key", b"").encode().unwrap());
+        let mut cursor = Cursor::new(bytes);
+
+        assert_eq!(Record::decode(&mut cursor).unwrap(), Some(record(Operation::Insert, b"key", b"hello")));
+        assert_eq!(Record::decode(&mut cursor).unwrap(), Some(record(Operation::Remove, b"key", b"")));
+        assert_eq!(Record::decode(&mut cursor).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_expiry() {
+        let record = Record { operation: Operation::Set, key: b"key".to_vec(), expires_at: Some(1_700_000_000_000), data: b"hello".to_vec() };
+        let mut cursor = Cursor::new(record.encode().unwrap());
+
+        assert_eq!(Record::decode(&mut cursor).unwrap(), Some(record));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let mut bytes = record(Operation::Set, b"key", b"hello").encode().unwrap();
+        bytes.truncate(bytes.len() - 6);
+        let mut cursor = Cursor::new(bytes);
+
+        let err = Record::decode(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("record data smaller than expected"));
+    }
+
+    #[test]
+    fn test_decode_truncated_key() {
+        let mut bytes = record(Operation::Set, b"key", b"hello").encode().unwrap();
+        bytes.truncate(5);
+        let mut cursor = Cursor::new(bytes);
+
+        let err = Record::decode(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("record key smaller than expected"));
+    }
+
+    #[test]
+    fn test_decode_checksum_mismatch() {
+        let mut bytes = record(Operation::Set, b"key", b"hello").encode().unwrap();
+        bytes[20] = b'j';
+        let mut cursor = Cursor::new(bytes);
+
+        let err = Record::decode(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("record checksum mismatch"));
+    }
+
+    #[test]
+    fn test_decode_unsupported_version() {
+        let mut cursor = Cursor::new(vec![0x10, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+
+        let err = Record::decode(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("unsupported record format version 16"));
+
+        // the formats without keys (1) and without expiries (2) of earlier development versions
+        for version in [1, 2] {
+            let mut cursor = Cursor::new(vec![version, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+            assert!(Record::decode(&mut cursor).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_operation() {
+        let mut bytes = vec![3, 0x10, 0, 1, b'k', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&crc32fast::hash(&bytes).to_be_bytes());
+        let mut cursor = Cursor::new(bytes);
+
+        let err = Record::decode(&mut cursor).unwrap_err();
+        assert!(err.to_string().contains("unknown operation"));
+    }
+
+    #[test]
+    fn test_decode_all_stops_at_invalid_record() {
+        let valid = record(Operation::Insert, b"key", b"hello").encode().unwrap();
+
+        let mut bytes = valid.clone();
+        bytes.extend_from_slice(&valid[..valid.len() - 1]);
+
+        let decoded = Record::decode_all(&bytes);
+
+        assert_eq!(decoded.records.len(), 1);
+        assert_eq!(decoded.valid_length, valid.len() as u64);
+        assert!(decoded.error.unwrap().to_string().contains("record checksum missing"));
+    }
+
+    #[test]
+    fn test_transaction() {
+        let records = vec![record(Operation::Insert, b"new", b"hello"), record(Operation::Remove, b"old", b"")];
+
+        let transaction = Record::transaction(&records).unwrap();
+        let decoded = Record::decode(&mut Cursor::new(transaction.encode().unwrap())).unwrap().unwrap();
+
+        assert_eq!(decoded.operation, Operation::Transaction);
+        assert_eq!(Record::decode_all(&decoded.data).records, records);
+    }
+}
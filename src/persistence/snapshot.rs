@@ -0,0 +1,211 @@
+use anyhow::Context;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use crate::persistence::record::Decoded;
+use crate::persistence::Record;
+
+const MAGIC: &[u8; 4] = b"RCDB";
+const FORMAT_VERSION: u8 = 2;
+
+/// magic + u8 format version + u32 number of records + u32 checksum
+pub(crate) const HEADER_LENGTH: usize = 13;
+
+/// Example Snapshot Structure
+///
+/// 4 bytes magic "RCDB"
+/// u8 format version
+/// u32 number of records
+/// u32 crc32 checksum of the previous header bytes
+/// one INSERT record for every entry and one NEXT ID record (see Record)
+///
+/// The snapshot is written to a temporary file first, which replaces the old snapshot
+/// once it is complete, so there is always a complete snapshot on the disk.
+pub(crate) fn write_snapshot(path: &Path, records: &[Record]) -> Result<(), anyhow::Error> {
+    let temp_path = temp_path(path);
+
+    let file = File::create(&temp_path)
+        .with_context(|| format!("failed to create snapshot file {}", temp_path.display()))?;
+
+    let mut writer = BufWriter::new(file);
+    write_records(&mut writer, records)
+        .context("failed to write snapshot file")?;
+
+    let file = writer.into_inner()
+        .context("failed to write snapshot file")?;
+    file.sync_all()
+        .context("failed to sync snapshot file")?;
+
+    std::fs::rename(&temp_path, path)
+        .context("failed to replace snapshot file")?;
+
+    Ok(())
+}
+
+/// Reads all records of the snapshot at path.
+///
+/// Returns no records if there is no snapshot yet. If a record is invalid, the records
+/// before it are returned.
+pub(crate) fn read_snapshot(path: &Path) -> Result<Vec<Record>, anyhow::Error> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read snapshot file {}", path.display())),
+    };
+
+    let (count, decoded) = read_records(&bytes)
+        .with_context(|| format!("failed to read snapshot file {}", path.display()))?;
+
+    if let Some(e) = &decoded.error {
+        eprintln!(
+            "snapshot file {} is invalid after record {} ({:#}), dropping the last {} of {} records",
+            path.display(),
+            decoded.records.len(),
+            e,
+            count.saturating_sub(decoded.records.len() as u32),
+            count,
+        );
+    } else if decoded.records.len() as u32 != count {
+        eprintln!(
+            "snapshot file {} contains {} records, expected {}",
+            path.display(),
+            decoded.records.len(),
+            count,
+        );
+    }
+
+    Ok(decoded.records)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = OsString::from(path.as_os_str());
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+fn write_records<W>(writer: &mut W, records: &[Record]) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let mut header = Vec::with_capacity(HEADER_LENGTH);
+    header.extend_from_slice(MAGIC);
+    header.push(FORMAT_VERSION);
+    header.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    header.extend_from_slice(&crc32fast::hash(&header).to_be_bytes());
+
+    writer.write_all(&header)?;
+
+    for record in records {
+        writer.write_all(&record.encode().map_err(std::io::Error::other)?)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the header and decodes the records after it.
+///
+/// Returns the number of records stated in the header together with the decoded records.
+pub(crate) fn read_records(bytes: &[u8]) -> Result<(u32, Decoded), anyhow::Error> {
+    if bytes.len() < HEADER_LENGTH {
+        return Err(anyhow::anyhow!("snapshot header too short"));
+    }
+
+    let header = &bytes[..HEADER_LENGTH];
+
+    if &header[0..4] != MAGIC {
+        return Err(anyhow::anyhow!("not a snapshot file"));
+    }
+
+    if header[4] != FORMAT_VERSION {
+        return Err(anyhow::anyhow!("unsupported snapshot format version {}", header[4]));
+    }
+
+    let checksum = u32::from_be_bytes([header[9], header[10], header[11], header[12]]);
+    if crc32fast::hash(&header[..9]) != checksum {
+        return Err(anyhow::anyhow!("snapshot header checksum mismatch"));
+    }
+
+    let count = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+
+    Ok((count, Record::decode_all(&bytes[HEADER_LENGTH..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::temp_path as test_path;
+    use crate::persistence::Operation;
+
+    fn record(id: u32, data: &[u8]) -> Record {
+        Record { operation: Operation::Insert, key: id.to_be_bytes().to_vec(), expires_at: None, data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = test_path("snapshot-write-and-read.rdb");
+
+        let records = vec![record(1, b"hello"), record(2, b"world"), Record { operation: Operation::NextId, key: vec![], expires_at: None, data: 3u32.to_be_bytes().to_vec() }];
+        write_snapshot(&path, &records).unwrap();
+
+        assert_eq!(read_snapshot(&path).unwrap(), records);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_write_replaces_old_snapshot() {
+        let path = test_path("snapshot-write-replaces-old-snapshot.rdb");
+
+        write_snapshot(&path, &[record(1, b"hello")]).unwrap();
+        write_snapshot(&path, &[record(2, b"world")]).unwrap();
+
+        assert_eq!(read_snapshot(&path).unwrap(), vec![record(2, b"world")]);
+    }
+
+    #[test]
+    fn test_read_missing_file() {
+        let path = test_path("snapshot-read-missing-file.rdb");
+
+        assert_eq!(read_snapshot(&path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let path = test_path("snapshot-read-truncated.rdb");
+
+        let mut bytes = Vec::new();
+        write_records(&mut bytes, &[record(1, b"hello"), record(2, b"world")]).unwrap();
+        bytes.pop();
+        std::fs::write(&path, bytes).unwrap();
+
+        assert_eq!(read_snapshot(&path).unwrap(), vec![record(1, b"hello")]);
+    }
+
+    #[test]
+    fn test_read_corrupted_entry() {
+        let path = test_path("snapshot-read-corrupted-entry.rdb");
+
+        let mut bytes = Vec::new();
+        write_records(&mut bytes, &[record(1, b"hello"), record(2, b"world")]).unwrap();
+        bytes[HEADER_LENGTH + 12] ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert_eq!(read_snapshot(&path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_read_header_checksum_mismatch() {
+        let mut bytes = Vec::new();
+        write_records(&mut bytes, &[record(1, b"hello")]).unwrap();
+        bytes[5] ^= 0xFF;
+
+        let err = read_records(&bytes).unwrap_err();
+        assert!(err.to_string().contains("snapshot header checksum mismatch"));
+    }
+
+    #[test]
+    fn test_read_not_a_snapshot() {
+        let err = read_records(b"HELLO WORLD, NOT A SNAPSHOT").unwrap_err();
+        assert!(err.to_string().contains("not a snapshot file"));
+    }
+}
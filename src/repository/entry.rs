@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use crate::repository::Key;
+
+/// the access frequency of an entry is halved for every period it isn't accessed
+const FREQUENCY_DECAY_PERIOD: u64 = 60 * 1000;
+
+/// The data of an entry, when it expires and how it is accessed
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) data: Vec<u8>,
+    /// unix time in milliseconds, the entry never expires if there is none
+    pub(crate) expires_at: Option<u64>,
+    /// changes whenever the data changes, a new version is always larger than the previous one
+    pub(crate) version: u64,
+    /// unix time in milliseconds, atomic since reads only hold the read lock of the entry
+    last_access: AtomicU64,
+    /// number of accesses, see frequency
+    hits: AtomicU32,
+}
+
+impl Entry {
+    pub(crate) fn new(data: Vec<u8>, expires_at: Option<u64>, version: u64) -> Self {
+        Entry {
+            data,
+            expires_at,
+            version,
+            last_access: AtomicU64::new(now()),
+            hits: AtomicU32::new(1),
+        }
+    }
+
+    /// An expired entry is treated as if it doesn't exist, until it is removed
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Time until the entry expires, None if it never expires
+    pub(crate) fn ttl(&self, now: u64) -> Option<Duration> {
+        self.expires_at.map(|expires_at| Duration::from_millis(expires_at.saturating_sub(now)))
+    }
+
+    /// Records an access for the eviction policies
+    pub(crate) fn touch(&self, now: u64) {
+        let hits = self.frequency(now).saturating_add(1);
+        self.hits.store(hits, Ordering::Relaxed);
+        self.last_access.store(now, Ordering::Relaxed);
+    }
+
+    pub(crate) fn last_access(&self) -> u64 {
+        self.last_access.load(Ordering::Relaxed)
+    }
+
+    /// Number of accesses, halved for every decay period since the last access,
+    /// so entries that were used a lot in the past can be evicted eventually
+    pub(crate) fn frequency(&self, now: u64) -> u32 {
+        let periods = now.saturating_sub(self.last_access()) / FREQUENCY_DECAY_PERIOD;
+        self.hits.load(Ordering::Relaxed).checked_shr(periods.min(32) as u32).unwrap_or(0)
+    }
+}
+
+/// memory of the hashmap slot of an entry (key, lock and entry) and its control byte,
+/// empty slots of the hashmap aren't counted
+const ENTRY_OVERHEAD: usize = size_of::<(Key, RwLock<Entry>)>() + 1;
+
+/// Memory used by an entry with the key and the data, including the overhead of the repository
+pub(crate) fn memory_usage(key: &[u8], data: &[u8]) -> usize {
+    ENTRY_OVERHEAD + key.len() + data.len()
+}
+
+/// Current unix time in milliseconds
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis().min(u64::MAX as u128) as u64)
+        .unwrap_or(0)
+}
+
+/// Unix time in milliseconds when an entry with the ttl expires
+pub(crate) fn expires_at(ttl: Duration) -> u64 {
+    now().saturating_add(ttl.as_millis().min(u64::MAX as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_expired() {
+        assert!(!Entry::new(vec![], None, 1).is_expired(1000));
+        assert!(!Entry::new(vec![], Some(1001), 1).is_expired(1000));
+        assert!(Entry::new(vec![], Some(1000), 1).is_expired(1000));
+    }
+
+    #[test]
+    fn test_ttl() {
+        assert_eq!(Entry::new(vec![], None, 1).ttl(1000), None);
+        assert_eq!(Entry::new(vec![], Some(1500), 1).ttl(1000), Some(Duration::from_millis(500)));
+        assert_eq!(Entry::new(vec![], Some(500), 1).ttl(1000), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_expires_at_saturates() {
+        assert_eq!(expires_at(Duration::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_frequency() {
+        let entry = Entry::new(vec![], None, 1);
+        let now = entry.last_access();
+
+        entry.touch(now);
+        entry.touch(now);
+        assert_eq!(entry.frequency(now), 3);
+
+        // halved for every period without an access
+        assert_eq!(entry.frequency(now + FREQUENCY_DECAY_PERIOD), 1);
+        assert_eq!(entry.frequency(now + 40 * FREQUENCY_DECAY_PERIOD), 0);
+
+        entry.touch(now + FREQUENCY_DECAY_PERIOD);
+        assert_eq!(entry.frequency(now + FREQUENCY_DECAY_PERIOD), 2);
+        assert_eq!(entry.last_access(), now + FREQUENCY_DECAY_PERIOD);
+    }
+
+    #[test]
+    fn test_memory_usage_includes_overhead() {
+        assert_eq!(memory_usage(b"key", b"hello") - memory_usage(b"", b""), 8);
+        assert!(memory_usage(b"", b"") > size_of::<Entry>());
+    }
+}
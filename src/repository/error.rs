@@ -1,13 +1,78 @@
 use thiserror::Error;
+use crate::repository::Key;
+
+/// Shows the key as text if it is printable UTF-8, otherwise as hex
+pub(crate) fn display_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(text) if !text.chars().any(|c| c.is_control()) => format!("\"{}\"", text),
+        _ => {
+            let hex: Vec<String> = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("0x{}", hex.concat())
+        }
+    }
+}
 
 #[derive(Debug, Error, PartialEq)]
 pub(crate) enum DatabaseError {
-    #[error("entry with id {0} not found")]
-    NotFound(u32),
+    #[error("entry with key {} not found", display_key(.0))]
+    NotFound(Key),
+
+    #[error("entry with key {} already exists", display_key(.0))]
+    AlreadyExists(Key),
+
+    #[error("someone else is currently using the entry with key {}", display_key(.0))]
+    WriteBlocked(Key),
+
+    #[error("timed out waiting for the entry with key {}", display_key(.0))]
+    Timeout(Key),
+
+    #[error("the entry has version {0}, not the expected version")]
+    VersionMismatch(u64),
+
+    #[error("operation {0} of the transaction failed: {1}")]
+    TransactionFailed(usize, Box<DatabaseError>),
+
+    #[error("the watched entry with key {} was changed", display_key(.0))]
+    WatchedEntryChanged(Key),
+
+    #[error("the entry with key {} isn't an integer", display_key(.0))]
+    NotAnInteger(Key),
+
+    #[error("the integer of the entry with key {} would overflow", display_key(.0))]
+    IntegerOverflow(Key),
+
+    #[error("the data of the entry with key {} would be longer than 512 MiB", display_key(.0))]
+    DataTooLarge(Key),
+
+    #[error("all ids are already assigned")]
+    IdsExhausted,
+
+    #[error("not enough memory left and no entry can be evicted")]
+    OutOfMemory,
+
+    #[error("failed to persist change: {0}")]
+    Persistence(String),
+
+    #[error("the append only file is disabled")]
+    AofDisabled,
+
+    #[error("the append only file is already being rewritten")]
+    RewriteInProgress,
+
+    #[error("snapshots are disabled")]
+    SnapshotDisabled,
+
+    #[error("a snapshot is already being saved")]
+    SaveInProgress,
+}
 
-    #[error("entry with id {0} already exists")]
-    AlreadyExists(u32),
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    #[error("someone else is currently using the entry with id {0}")]
-    WriteBlocked(u32),
-}
\ No newline at end of file
+    #[test]
+    fn test_display_key() {
+        assert_eq!(DatabaseError::NotFound(b"session:abc".to_vec()).to_string(), "entry with key \"session:abc\" not found");
+        assert_eq!(DatabaseError::NotFound(vec![0, 0, 0, 42]).to_string(), "entry with key 0x0000002a not found");
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, Hasher};
+use crate::repository::shard::Shard;
+use crate::repository::Key;
+
+/// number of entries compared to find the entry to evict, like maxmemory-samples of redis
+const SAMPLES: usize = 5;
+
+/// Which entries are removed once the memory limit is reached
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum EvictionPolicy {
+    /// nothing is removed, changes that need more memory are rejected
+    NoEviction,
+    /// the least recently used entry
+    AllKeysLru,
+    /// the least frequently used entry
+    AllKeysLfu,
+    /// the entry with an expiry that expires first, entries without an expiry are never removed
+    VolatileTtl,
+    /// any entry
+    Random,
+}
+
+/// Returns a random number, the hasher of std is seeded randomly
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Chooses the entry that is removed next, None if nothing can be removed.
+///
+/// LRU, LFU and random compare a few entries at random positions of the shard instead of all entries.
+/// The excluded key is never chosen, it is the key of the entry that needs the memory.
+/// VolatileTtl takes the first key of the expiring entries of the shard.
+/// Must be called while holding the write lock of the shard, so no entry is locked by someone else.
+pub(crate) fn choose_victim(
+    policy: EvictionPolicy,
+    shard: &Shard,
+    expiring: &BTreeSet<(u64, Key)>,
+    exclude: &[u8],
+    now: u64,
+) -> Option<Key> {
+    let len = shard.len();
+
+    // small shards are compared completely, otherwise twice the samples are drawn,
+    // so the excluded entry doesn't reduce the samples
+    let positions: Vec<usize> = if len <= 2 * SAMPLES {
+        (0..len).collect()
+    } else {
+        (0..2 * SAMPLES).map(|_| (random() % len as u64) as usize).collect()
+    };
+
+    let mut samples = positions.into_iter()
+        .filter_map(|index| shard.get_index(index))
+        .filter(|(key, _)| key.as_slice() != exclude)
+        .filter_map(|(key, rw_lock)| Some((key, rw_lock.try_read().ok()?)))
+        .take(SAMPLES);
+
+    let victim = match policy {
+        EvictionPolicy::NoEviction => None,
+        EvictionPolicy::AllKeysLru => samples.min_by_key(|(_, entry)| entry.last_access()),
+        EvictionPolicy::AllKeysLfu => samples.min_by_key(|(_, entry)| (entry.frequency(now), entry.last_access())),
+        EvictionPolicy::Random => samples.next(),
+        EvictionPolicy::VolatileTtl => {
+            return expiring.iter()
+                .map(|(_, key)| key)
+                .find(|key| key.as_slice() != exclude && shard.contains_key(key))
+                .cloned();
+        }
+    };
+
+    victim.map(|(key, _)| key.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::RwLock;
+    use crate::repository::entry::Entry;
+
+    fn entries(count: u32) -> Shard {
+        let mut shard = Shard::default();
+        for id in 0..count {
+            shard.insert(id.to_be_bytes().to_vec(), RwLock::new(Entry::new(vec![0; 10], None, 1)));
+        }
+        shard
+    }
+
+    #[test]
+    fn test_no_eviction() {
+        let entries = entries(3);
+
+        assert_eq!(choose_victim(EvictionPolicy::NoEviction, &entries, &BTreeSet::new(), b"", 0), None);
+    }
+
+    #[test]
+    fn test_lru() {
+        // with at most SAMPLES entries all of them are compared
+        let mut entries = entries(SAMPLES as u32);
+        for (i, (_, rw_lock)) in entries.iter_mut().enumerate() {
+            rw_lock.get_mut().touch(1000 + i as u64);
+        }
+        entries.get_mut(2u32.to_be_bytes().as_slice()).unwrap().get_mut().touch(1);
+
+        assert_eq!(choose_victim(EvictionPolicy::AllKeysLru, &entries, &BTreeSet::new(), b"", 2000), Some(2u32.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_lfu() {
+        let mut entries = entries(SAMPLES as u32);
+        let now = crate::repository::entry::now();
+        for (key, rw_lock) in entries.iter_mut() {
+            if key.as_slice() != 2u32.to_be_bytes() {
+                rw_lock.get_mut().touch(now);
+            }
+        }
+
+        assert_eq!(choose_victim(EvictionPolicy::AllKeysLfu, &entries, &BTreeSet::new(), b"", now), Some(2u32.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_volatile_ttl() {
+        let entries = entries(3);
+        let expiring = BTreeSet::from([(2000, 1u32.to_be_bytes().to_vec()), (1000, 2u32.to_be_bytes().to_vec())]);
+
+        assert_eq!(choose_victim(EvictionPolicy::VolatileTtl, &entries, &expiring, b"", 0), Some(2u32.to_be_bytes().to_vec()));
+        // the entry that needs the memory isn't removed
+        assert_eq!(choose_victim(EvictionPolicy::VolatileTtl, &entries, &expiring, &2u32.to_be_bytes(), 0), Some(1u32.to_be_bytes().to_vec()));
+        assert_eq!(choose_victim(EvictionPolicy::VolatileTtl, &entries, &BTreeSet::new(), b"", 0), None);
+    }
+
+    #[test]
+    fn test_random_excludes_key() {
+        let single = entries(1);
+        let entries = entries(2);
+
+        for _ in 0..10 {
+            assert_eq!(choose_victim(EvictionPolicy::Random, &entries, &BTreeSet::new(), &0u32.to_be_bytes(), 0), Some(1u32.to_be_bytes().to_vec()));
+        }
+        assert_eq!(choose_victim(EvictionPolicy::Random, &single, &BTreeSet::new(), &0u32.to_be_bytes(), 0), None);
+    }
+
+    #[test]
+    fn test_samples_large_shard() {
+        let mut entries = entries(100_000);
+        for (_, rw_lock) in entries.iter_mut() {
+            rw_lock.get_mut().touch(1000);
+        }
+
+        // the samples are spread over the shard, not the first entries after a position
+        let victims: BTreeSet<Key> = (0..20)
+            .filter_map(|_| choose_victim(EvictionPolicy::Random, &entries, &BTreeSet::new(), b"", 0))
+            .collect();
+        assert!(victims.len() > 1);
+        assert!(choose_victim(EvictionPolicy::AllKeysLru, &entries, &BTreeSet::new(), b"", 2000).is_some());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::repository::error::DatabaseError;
+use crate::repository::error::DatabaseError::{IntegerOverflow, NotAnInteger};
+use crate::repository::Key;
+
+/// How the data of an entry is read and written as a signed 64 bit integer
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum IntegerEncoding {
+    /// 8 bytes big endian two's complement
+    BigEndian = 0,
+    /// decimal digits with an optional leading minus, e.g. "-42"
+    Ascii = 1,
+}
+
+impl TryFrom<u8> for IntegerEncoding {
+    type Error = anyhow::Error;
+
+    fn try_from(i: u8) -> Result<Self, Self::Error> {
+        match i {
+            0 => Ok(IntegerEncoding::BigEndian),
+            1 => Ok(IntegerEncoding::Ascii),
+            _ => Err(anyhow::anyhow!("unknown integer encoding {}", i)),
+        }
+    }
+}
+
+impl IntegerEncoding {
+    /// Returns the integer of the data, None if the data isn't an integer in this encoding
+    pub(crate) fn decode(self, data: &[u8]) -> Option<i64> {
+        match self {
+            IntegerEncoding::BigEndian => Some(i64::from_be_bytes(data.try_into().ok()?)),
+            IntegerEncoding::Ascii => {
+                let text = std::str::from_utf8(data).ok()?;
+                // parse accepts a leading plus, which wouldn't be written back the same way
+                if text.starts_with('+') {
+                    return None;
+                }
+                text.parse().ok()
+            }
+        }
+    }
+
+    pub(crate) fn encode(self, value: i64) -> Vec<u8> {
+        match self {
+            IntegerEncoding::BigEndian => value.to_be_bytes().to_vec(),
+            IntegerEncoding::Ascii => value.to_string().into_bytes(),
+        }
+    }
+
+    /// Adds the delta to the integer of the data of the entry with the key,
+    /// returns the sum and the data it is written as.
+    pub(crate) fn add(self, key: &Key, data: &[u8], delta: i64) -> Result<(i64, Vec<u8>), DatabaseError> {
+        let value = self.decode(data).ok_or_else(|| NotAnInteger(key.clone()))?;
+        let sum = value.checked_add(delta).ok_or_else(|| IntegerOverflow(key.clone()))?;
+        Ok((sum, self.encode(sum)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_big_endian() {
+        assert_eq!(IntegerEncoding::BigEndian.encode(-2), vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        assert_eq!(IntegerEncoding::BigEndian.decode(&[0, 0, 0, 0, 0, 0, 0x01, 0x02]), Some(0x0102));
+        assert_eq!(IntegerEncoding::BigEndian.decode(&[0, 0, 0, 7]), None);
+    }
+
+    #[test]
+    fn test_ascii() {
+        assert_eq!(IntegerEncoding::Ascii.encode(-42), b"-42".to_vec());
+        assert_eq!(IntegerEncoding::Ascii.decode(b"-42"), Some(-42));
+        assert_eq!(IntegerEncoding::Ascii.decode(b"9223372036854775807"), Some(i64::MAX));
+        assert_eq!(IntegerEncoding::Ascii.decode(b"9223372036854775808"), None);
+        assert_eq!(IntegerEncoding::Ascii.decode(b"+42"), None);
+        assert_eq!(IntegerEncoding::Ascii.decode(b" 42"), None);
+        assert_eq!(IntegerEncoding::Ascii.decode(b"hello"), None);
+    }
+
+    #[test]
+    fn test_add() {
+        let key = b"counter".to_vec();
+
+        assert_eq!(IntegerEncoding::Ascii.add(&key, b"9", 1), Ok((10, b"10".to_vec())));
+        assert_eq!(IntegerEncoding::BigEndian.add(&key, &5i64.to_be_bytes(), -7), Ok((-2, (-2i64).to_be_bytes().to_vec())));
+        assert_eq!(IntegerEncoding::Ascii.add(&key, b"hello", 1), Err(NotAnInteger(key.clone())));
+        assert_eq!(IntegerEncoding::BigEndian.add(&key, &i64::MAX.to_be_bytes(), 1), Err(IntegerOverflow(key)));
+    }
+
+    #[test]
+    fn test_try_from() {
+        assert_eq!(IntegerEncoding::try_from(1).unwrap(), IntegerEncoding::Ascii);
+        assert!(IntegerEncoding::try_from(2).is_err());
+    }
+}
@@ -1,161 +1,2184 @@
+pub(crate) mod entry;
 pub(crate) mod error;
+pub(crate) mod eviction;
+pub(crate) mod integer;
+pub(crate) mod shard;
+pub(crate) mod transaction;
 
-use std::collections::{HashMap};
-use std::sync::{Arc};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::BuildHasher;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 use mockall::automock;
 use tokio::sync::{RwLock};
+use crate::config::Config;
+use crate::persistence::aof::{blocking, spawn_fsync_task, write_rewrite_file, Aof, FsyncPolicy};
+use crate::persistence::snapshot::{read_snapshot, write_snapshot};
+use crate::persistence::{Operation, Record};
+use crate::repository::entry::{expires_at, memory_usage, now, Entry};
 use crate::repository::error::DatabaseError;
-use crate::repository::error::DatabaseError::{AlreadyExists, NotFound, WriteBlocked};
+use crate::repository::error::DatabaseError::{AlreadyExists, AofDisabled, DataTooLarge, IdsExhausted, NotFound, OutOfMemory, Persistence, RewriteInProgress, SaveInProgress, SnapshotDisabled, Timeout, VersionMismatch, WriteBlocked};
+use crate::repository::eviction::{choose_victim, EvictionPolicy};
+use crate::repository::integer::IntegerEncoding;
+use crate::repository::shard::Shard;
+use crate::repository::transaction::{TransactionOperation, TransactionOutcome};
 
 pub(crate) type SharedRepository = Arc<dyn RepositoryApi>;
 
+/// Binary safe key of an entry, u32 ids are stored as the key of their 4 big endian bytes
+pub(crate) type Key = Vec<u8>;
+
+/// The entries of a part of the keyspace
+/// number of shards if none are configured
+pub(crate) const DEFAULT_SHARDS: usize = 16;
+
+/// longest data APPEND and SETRANGE can create, like proto-max-bulk-len of redis
+pub(crate) const MAX_DATA_LENGTH: u64 = 512 * 1024 * 1024;
+
 pub(crate) struct Repository {
-    data: RwLock<HashMap<u32, RwLock<Vec<u8>>>>,
+    // the keyspace is split into shards by the hash of the keys, every shard is locked independently,
+    // so inserts and removes only block the entries of their shard.
+    // whoever locks multiple shards at once locks them in the order of their index
+    shards: Vec<RwLock<Shard>>,
+    hasher: RandomState,
+    // keys of the entries with an expiry of every shard (the same index as in shards), ordered by when they expire
+    // only changed while holding the lock that protects the entry
+    expiring: Vec<Mutex<BTreeSet<(u64, Key)>>>,
+    // the next id assigned by insert_auto, an id is only taken while holding the write lock of its shard
+    next_id: AtomicU32,
+    // the last version given to an entry, it starts at the unix time in milliseconds times 2^20,
+    // so the versions after a restart are larger than before as long as less than 2^20 versions per millisecond are given
+    last_version: AtomicU64,
+    // memory used by all entries (see memory_usage), only changed while holding the lock that protects the entry
+    used_memory: AtomicU64,
+    // 0 if there is no limit
+    max_memory: u64,
+    eviction_policy: EvictionPolicy,
+    // if set, every change is appended to the file before it is applied
+    aof: Option<Arc<Mutex<Aof>>>,
+    // if set, snapshots can be saved to the file
+    snapshot_path: Option<PathBuf>,
+    saving_snapshot: Arc<AtomicBool>,
 }
 
 #[async_trait::async_trait]
 pub(crate) trait RepositoryApi: Send + Sync {
-    async fn get(&self, id: u32) -> Option<Vec<u8>>;
-    async fn set(&self, id: u32, data: Vec<u8>) -> Result<(), DatabaseError>;
-    async fn insert(&self, id: u32, data: Vec<u8>) -> Result<(), DatabaseError>;
-    async fn remove(&self, id: u32) -> Result<(), DatabaseError>;
+    /// Returns the data and version of the entry, expired entries are treated as if they don't exist.
+    async fn get(&self, key: Key) -> Option<(Vec<u8>, u64)>;
+    /// Replaces the data of an existing entry.
+    ///
+    /// The entry expires after the ttl, without a ttl it never expires (an earlier expiry is removed).
+    async fn set(&self, key: Key, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), DatabaseError>;
+    /// Replaces the data of an existing entry and removes its expiry, like set.
+    ///
+    /// If someone else is using the entry, it waits for the entry up to the timeout instead of failing immediately.
+    async fn set_wait(&self, key: Key, data: Vec<u8>, timeout: Duration) -> Result<(), DatabaseError>;
+    /// Replaces the data of an existing entry and removes its expiry, only if the entry has the expected version.
+    ///
+    /// Returns the new version, or VersionMismatch with the current version.
+    async fn compare_and_set(&self, key: Key, expected_version: u64, data: Vec<u8>) -> Result<u64, DatabaseError>;
+    /// Inserts a new entry, that expires after the ttl (if there is one).
+    async fn insert(&self, key: Key, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), DatabaseError>;
+    /// Inserts a new entry with an id assigned by the repository and returns the id.
+    ///
+    /// The key of the entry are the 4 big endian bytes of the id. The ids are assigned in
+    /// increasing order and never reused, ids already inserted by insert are skipped.
+    async fn insert_auto(&self, data: Vec<u8>) -> Result<u32, DatabaseError>;
+    /// Replaces the data and expiry of the entry, or inserts it if it doesn't exist.
+    ///
+    /// Returns true if the entry was inserted.
+    async fn upsert(&self, key: Key, data: Vec<u8>, ttl: Option<Duration>) -> Result<bool, DatabaseError>;
+    /// Adds the delta to the integer stored in the entry and returns the sum.
+    ///
+    /// A missing entry counts as 0 and is inserted, the expiry of an existing entry is kept.
+    async fn increment(&self, key: Key, delta: i64, encoding: IntegerEncoding) -> Result<i64, DatabaseError>;
+    /// Appends the data to the data of the entry and returns the new length of its data.
+    ///
+    /// A missing entry is inserted with the data, the expiry of an existing entry is kept.
+    async fn append(&self, key: Key, data: Vec<u8>) -> Result<u64, DatabaseError>;
+    /// Returns the length of the data of the entry.
+    async fn length(&self, key: Key) -> Option<u64>;
+    /// Returns up to length bytes of the data of the entry starting at the offset,
+    /// the bytes after the end of the data are left out.
+    async fn get_range(&self, key: Key, offset: u64, length: u64) -> Option<Vec<u8>>;
+    /// Overwrites the data of the entry starting at the offset and returns the new length of its data.
+    ///
+    /// The data of the entry is extended with zero bytes if it ends before the offset. A missing entry
+    /// is inserted as if its data was empty, the expiry of an existing entry is kept.
+    async fn set_range(&self, key: Key, offset: u64, data: Vec<u8>) -> Result<u64, DatabaseError>;
+    async fn remove(&self, key: Key) -> Result<(), DatabaseError>;
+    /// Lets the entry expire after the ttl.
+    async fn expire(&self, key: Key, ttl: Duration) -> Result<(), DatabaseError>;
+    /// Returns the time until the entry expires, None if it never expires.
+    async fn ttl(&self, key: Key) -> Result<Option<Duration>, DatabaseError>;
+    /// Removes the expiry of the entry, so it never expires.
+    async fn persist(&self, key: Key) -> Result<(), DatabaseError>;
+    /// Returns the bytes used by the entry and by all entries.
+    async fn memory_usage(&self, key: Key) -> Result<(u64, u64), DatabaseError>;
+
+    /// Returns the current version of the entry, without counting as an access for the eviction policy.
+    async fn version(&self, key: Key) -> Option<u64>;
+
+    /// Executes the operations atomically, either all of them are applied or none.
+    ///
+    /// The operations are only executed if the watched entries still have the given versions (None if there was no entry),
+    /// otherwise WatchedEntryChanged is returned.
+    /// Returns the outcome of every operation in order, or TransactionFailed with the index of the first
+    /// operation that can't be applied.
+    async fn execute(&self, watched: Vec<(Key, Option<u64>)>, operations: Vec<TransactionOperation>) -> Result<Vec<TransactionOutcome>, DatabaseError>;
+    /// Rewrites the append only file with the minimal records to restore the current state.
+    ///
+    /// Changes are still accepted while the file is rewritten.
+    async fn rewrite_aof(&self) -> Result<(), DatabaseError>;
+    /// Saves a point-in-time snapshot of all entries to the snapshot file.
+    async fn save_snapshot(&self) -> Result<(), DatabaseError>;
+    /// Takes a point-in-time snapshot of all entries and saves it to the snapshot file in the background.
+    ///
+    /// Returns as soon as the snapshot is taken, before it is saved.
+    async fn background_save_snapshot(&self) -> Result<(), DatabaseError>;
+}
+
+/// Marks a snapshot as being saved, until it is dropped
+struct SavingSnapshotGuard(Arc<AtomicBool>);
+
+impl Drop for SavingSnapshotGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
 }
 
 impl Repository {
+    /// Creates an in memory repository with the default number of shards, nothing is persisted
+    #[cfg(test)]
     pub(crate) fn new() -> Self {
+        Repository::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates an in memory repository with the keyspace split into the number of shards (at least 1), nothing is persisted
+    pub(crate) fn with_shards(shards: usize) -> Self {
         Repository {
-            data: RwLock::new(HashMap::new()),
+            shards: (0..shards.max(1)).map(|_| RwLock::new(Shard::default())).collect(),
+            hasher: RandomState::new(),
+            expiring: (0..shards.max(1)).map(|_| Mutex::new(BTreeSet::new())).collect(),
+            next_id: AtomicU32::new(0),
+            last_version: AtomicU64::new(now() << 20),
+            used_memory: AtomicU64::new(0),
+            max_memory: 0,
+            eviction_policy: EvictionPolicy::NoEviction,
+            aof: None,
+            snapshot_path: None,
+            saving_snapshot: Arc::new(AtomicBool::new(false)),
         }
     }
-}
 
-#[automock]
-#[async_trait::async_trait]
-impl RepositoryApi for Repository {
-    async fn get(&self, id: u32) -> Option<Vec<u8>> {
-        let hash_map_guard = self.data.read().await;
-        let rw_lock = hash_map_guard.get(&id)?;
-        let data_guard = rw_lock.read().await;
-        Some(data_guard.clone())
+    /// Creates a repository and restores the persisted entries.
+    ///
+    /// If the append only file is enabled its records are replayed, otherwise the snapshot is loaded.
+    pub(crate) fn open(config: &Config) -> Result<Self, anyhow::Error> {
+        let mut repository = Repository::with_shards(config.shards);
+        repository.snapshot_path = Some(config.dbfilename.clone());
+        repository.max_memory = config.maxmemory;
+        repository.eviction_policy = config.maxmemory_policy;
+
+        if config.appendonly {
+            let (aof, records) = Aof::open(&config.appendfilename, config.appendfsync, config.auto_aof_rewrite)?;
+            repository.replay(records);
+
+            let aof = Arc::new(Mutex::new(aof));
+            if config.appendfsync == FsyncPolicy::EverySec {
+                spawn_fsync_task(Arc::downgrade(&aof));
+            }
+
+            repository.aof = Some(aof);
+        } else {
+            repository.replay(read_snapshot(&config.dbfilename)?);
+        }
+
+        Ok(repository)
+    }
+
+    /// Applies the persisted records in order
+    fn replay(&mut self, records: Vec<Record>) {
+        for record in records {
+            self.replay_record(record);
+        }
+
+        let now = now();
+        let used_memory = self.used_memory.get_mut();
+        for (shard, expiring) in self.shards.iter_mut().zip(self.expiring.iter_mut()) {
+            let data = shard.get_mut();
+            let expiring = expiring.get_mut().expect("expiring mutex poisoned");
+
+            // entries that expired while the server wasn't running
+            data.retain(|_, rw_lock| !rw_lock.get_mut().is_expired(now));
+
+            for (key, rw_lock) in data.iter_mut() {
+                let entry = rw_lock.get_mut();
+                if let Some(expires_at) = entry.expires_at {
+                    expiring.insert((expires_at, key.clone()));
+                }
+                *used_memory += memory_usage(key, &entry.data) as u64;
+            }
+        }
+
+        if self.max_memory != 0 && *used_memory > self.max_memory {
+            eprintln!("the restored entries use {} bytes, more than the limit of {} bytes", used_memory, self.max_memory);
+        }
+    }
+
+    /// Applies a single persisted record
+    fn replay_record(&mut self, record: Record) {
+        let index = self.shard_index(&record.key);
+        let data = self.shards[index].get_mut();
+        let next_id = self.next_id.get_mut();
+
+        match record.operation {
+            Operation::Set | Operation::Insert => {
+                let version = self.last_version.get_mut();
+                *version += 1;
+                data.insert(record.key, RwLock::new(Entry::new(record.data, record.expires_at, *version)));
+            }
+            Operation::InsertAuto => {
+                if let Ok(id) = <[u8; 4]>::try_from(record.key.as_slice()) {
+                    *next_id = (*next_id).max(u32::from_be_bytes(id).saturating_add(1));
+                }
+                let version = self.last_version.get_mut();
+                *version += 1;
+                data.insert(record.key, RwLock::new(Entry::new(record.data, None, *version)));
+            }
+            Operation::Remove => {
+                data.remove(&record.key);
+            }
+            Operation::Expire | Operation::Persist => {
+                if let Some(rw_lock) = data.get_mut(&record.key) {
+                    rw_lock.get_mut().expires_at = record.expires_at;
+                }
+            }
+            Operation::Transaction => {
+                // a torn transaction is not applied at all
+                let decoded = Record::decode_all(&record.data);
+                if let Some(err) = decoded.error {
+                    eprintln!("skipping transaction with invalid records: {}", err);
+                    return;
+                }
+                for record in decoded.records {
+                    self.replay_record(record);
+                }
+            }
+            Operation::NextId => {
+                if let Ok(id) = <[u8; 4]>::try_from(record.data.as_slice()) {
+                    *next_id = (*next_id).max(u32::from_be_bytes(id));
+                }
+            }
+        }
+    }
+
+    /// Returns a version that is larger than all versions returned before
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn shard_index(&self, key: &[u8]) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    /// The shard that holds the entry of the key
+    fn shard(&self, key: &[u8]) -> &RwLock<Shard> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// The index of the expiring entries of the shard that holds the entry of the key
+    fn expiring(&self, key: &[u8]) -> &Mutex<BTreeSet<(u64, Key)>> {
+        &self.expiring[self.shard_index(key)]
+    }
+
+    /// Whether the memory limit would be exceeded by the additional bytes
+    fn exceeds_memory_limit(&self, additional: usize) -> bool {
+        self.max_memory != 0
+            && additional != 0
+            && self.used_memory.load(Ordering::Relaxed).saturating_add(additional as u64) > self.max_memory
+    }
+
+    /// Removes entries according to the eviction policy until the additional bytes fit into the memory limit.
+    ///
+    /// The entries are chosen from the locked shard of the excluded key, which is the key of the entry
+    /// that needs the memory, so it isn't removed. Only if that shard has nothing to evict, the other shards
+    /// are tried, without waiting for their locks since that could deadlock with other writers.
+    /// Returns OutOfMemory if not enough entries can be removed.
+    fn evict(&self, shard: &mut Shard, additional: usize, exclude: &[u8]) -> Result<(), DatabaseError> {
+        while self.exceeds_memory_limit(additional) {
+            if self.evict_one(shard, self.shard_index(exclude), exclude)? {
+                continue;
+            }
+
+            let mut evicted = false;
+            for (index, other) in self.shards.iter().enumerate() {
+                // the own shard is already locked, so try_write fails for it
+                let Ok(mut other) = other.try_write() else {
+                    continue;
+                };
+
+                if self.evict_one(&mut other, index, exclude)? {
+                    evicted = true;
+                    break;
+                }
+            }
+
+            if !evicted {
+                return Err(OutOfMemory);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the entry of the shard chosen by the eviction policy, false if there is none.
+    ///
+    /// Must be called while holding the write lock of the shard at the index.
+    fn evict_one(&self, shard: &mut Shard, index: usize, exclude: &[u8]) -> Result<bool, DatabaseError> {
+        let victim = {
+            let expiring = self.expiring[index].lock().expect("expiring mutex poisoned");
+            choose_victim(self.eviction_policy, shard, &expiring, exclude, now())
+        };
+
+        let Some(key) = victim else {
+            return Ok(false);
+        };
+
+        // evictions are logged, otherwise the entries would be restored on replay
+        self.log(&Record { operation: Operation::Remove, key: key.clone(), expires_at: None, data: vec![] })?;
+
+        if let Some(old) = shard.remove(&key) {
+            self.forget_entry(&key, old.into_inner());
+        }
+
+        Ok(true)
+    }
+
+    /// Replaces the data and expiry of the entry and returns its new version.
+    ///
+    /// Must be called while holding the lock that protects the entry.
+    fn replace_entry(&self, key: Key, entry: &mut Entry, data: Vec<u8>, expires_at: Option<u64>) -> Result<u64, DatabaseError> {
+        let record = Record { operation: Operation::Set, key, expires_at, data };
+        self.log(&record)?;
+
+        Ok(self.apply_replace(&record.key, entry, record.data, expires_at))
+    }
+
+    /// Replaces the data and expiry of the entry without logging the change and returns its new version.
+    ///
+    /// Must be called while holding the lock that protects the entry.
+    fn apply_replace(&self, key: &Key, entry: &mut Entry, data: Vec<u8>, expires_at: Option<u64>) -> u64 {
+        self.track_expiry(key, entry.expires_at, expires_at);
+        self.used_memory.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.used_memory.fetch_sub(entry.data.len() as u64, Ordering::Relaxed);
+
+        entry.data = data;
+        entry.expires_at = expires_at;
+        entry.version = self.next_version();
+        entry.touch(now());
+        entry.version
+    }
+
+    /// Adds a new entry to the shard, entries are evicted if it doesn't fit into the memory limit.
+    ///
+    /// Must be called while holding the write lock of the shard, which has no entry with the key.
+    fn insert_entry(&self, shard: &mut Shard, key: Key, data: Vec<u8>, expires_at: Option<u64>) -> Result<(), DatabaseError> {
+        self.evict(shard, memory_usage(&key, &data), &key)?;
+
+        let record = Record { operation: Operation::Insert, key, expires_at, data };
+        self.log(&record)?;
+
+        self.track_expiry(&record.key, None, record.expires_at);
+        self.used_memory.fetch_add(memory_usage(&record.key, &record.data) as u64, Ordering::Relaxed);
+        shard.insert(record.key, RwLock::new(Entry::new(record.data, record.expires_at, self.next_version())));
+
+        Ok(())
+    }
+
+    /// Updates the expiry index and memory usage for a removed entry
+    fn forget_entry(&self, key: &Key, entry: Entry) {
+        self.track_expiry(key, entry.expires_at, None);
+        self.used_memory.fetch_sub(memory_usage(key, &entry.data) as u64, Ordering::Relaxed);
+    }
+
+    /// Updates the expiry of the key in the index of expiring entries.
+    ///
+    /// Must be called while holding the lock that protects the changed entry.
+    fn track_expiry(&self, key: &Key, old: Option<u64>, new: Option<u64>) {
+        if old == new {
+            return;
+        }
+
+        let mut expiring = self.expiring(key).lock().expect("expiring mutex poisoned");
+        if let Some(old) = old {
+            expiring.remove(&(old, key.clone()));
+        }
+        if let Some(new) = new {
+            expiring.insert((new, key.clone()));
+        }
+    }
+
+    /// Removes up to limit expired entries and returns how many were removed.
+    ///
+    /// The removals aren't logged, expired entries are dropped when the records are replayed.
+    async fn remove_expired(&self, limit: usize) -> usize {
+        let now = now();
+
+        let mut candidates: Vec<(u64, Key)> = Vec::new();
+        for expiring in &self.expiring {
+            let expiring = expiring.lock().expect("expiring mutex poisoned");
+            candidates.extend(expiring.iter()
+                .take_while(|(expires_at, _)| *expires_at <= now)
+                .take(limit - candidates.len())
+                .cloned());
+        }
+
+        if candidates.is_empty() {
+            return 0;
+        }
+
+        let mut removed = 0;
+        for candidate in candidates {
+            let mut hash_map_guard = self.shard(&candidate.1).write().await;
+            let mut expiring = self.expiring(&candidate.1).lock().expect("expiring mutex poisoned");
+
+            // the expiry could have changed before the write lock was acquired
+            if expiring.remove(&candidate) {
+                if let Some(old) = hash_map_guard.remove(&candidate.1) {
+                    let old = old.into_inner();
+                    self.used_memory.fetch_sub(memory_usage(&candidate.1, &old.data) as u64, Ordering::Relaxed);
+                }
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    /// Appends the record to the append only file (if there is one).
+    ///
+    /// Must be called while holding the lock that protects the changed entry,
+    /// so that the order of the records matches the order of the changes.
+    fn log(&self, record: &Record) -> Result<(), DatabaseError> {
+        // checked without the append only file too, so the entry can be saved to a snapshot later
+        record.lengths().map_err(|e| Persistence(format!("{:#}", e)))?;
+
+        // the write (and the fsync with appendfsync always) blocks while locks of the entries are held,
+        // the other tasks of the worker thread move to other workers meanwhile
+        if let Some(aof) = &self.aof {
+            blocking(|| aof.lock().expect("aof mutex poisoned").append(record))
+                .map_err(|e| Persistence(format!("{:#}", e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn aof_needs_rewrite(&self) -> bool {
+        match &self.aof {
+            Some(aof) => aof.lock().expect("aof mutex poisoned").needs_rewrite(),
+            None => false,
+        }
+    }
+
+    /// Replaces the data and expiry of an existing entry.
+    ///
+    /// Without a timeout WriteBlocked is returned if someone else is using the entry, otherwise it waits
+    /// for the entry up to the timeout. The shard stays read locked while waiting.
+    /// The timeout also covers waiting for the locks of the shard, Timeout is returned as well if the shard
+    /// stays locked (e.g. by inserts that evict entries) until the timeout.
+    /// With an expected version the entry is only replaced if it has that version.
+    /// Returns the new version of the entry.
+    async fn set_entry(
+        &self,
+        key: Key,
+        data: Vec<u8>,
+        expires_at: Option<u64>,
+        timeout: Option<Duration>,
+        expected_version: Option<u64>,
+    ) -> Result<u64, DatabaseError> {
+        // a timeout too long for a deadline (Duration::MAX) waits as long as it takes
+        let deadline = timeout.and_then(|timeout| tokio::time::Instant::now().checked_add(timeout));
+
+        {
+            let Some(hash_map_guard) = within(deadline, self.shard(&key).read()).await else {
+                return Err(Timeout(key));
+            };
+
+            let rw_lock = match hash_map_guard.get(&key) {
+                Some(rw_lock) => rw_lock,
+                None => return Err(NotFound(key)),
+            };
+
+            let mut guard = match timeout {
+                None => match rw_lock.try_write() {
+                    Ok(guard) => guard,
+                    Err(_) => return Err(WriteBlocked(key)),
+                },
+                Some(_) => match within(deadline, rw_lock.write()).await {
+                    Some(guard) => guard,
+                    None => return Err(Timeout(key)),
+                },
+            };
+
+            if guard.is_expired(now()) {
+                return Err(NotFound(key));
+            }
+
+            if expected_version.is_some_and(|expected| expected != guard.version) {
+                return Err(VersionMismatch(guard.version));
+            }
+
+            if !self.exceeds_memory_limit(data.len().saturating_sub(guard.data.len())) {
+                return self.replace_entry(key, &mut guard, data, expires_at);
+            }
+
+            if self.eviction_policy == EvictionPolicy::NoEviction {
+                return Err(OutOfMemory);
+            }
+        }
+
+        // other entries have to be evicted, which needs the write lock
+        let Some(mut hash_map_guard) = within(deadline, self.shard(&key).write()).await else {
+            return Err(Timeout(key));
+        };
+
+        let additional = match hash_map_guard.get_mut(&key).map(RwLock::get_mut) {
+            Some(entry) if !entry.is_expired(now()) => {
+                // the entry could have changed while no lock was held
+                if expected_version.is_some_and(|expected| expected != entry.version) {
+                    return Err(VersionMismatch(entry.version));
+                }
+                data.len().saturating_sub(entry.data.len())
+            }
+            _ => return Err(NotFound(key)),
+        };
+
+        self.evict(&mut hash_map_guard, additional, &key)?;
+
+        let entry = hash_map_guard.get_mut(&key)
+            .expect("the entry of the key is never evicted")
+            .get_mut();
+        self.replace_entry(key, entry, data, expires_at)
+    }
+
+    /// Replaces the data of the entry with the data computed from its current data, or inserts the entry with
+    /// the data computed from None if it doesn't exist. The expiry of an existing entry is kept.
+    ///
+    /// It waits for the entry if someone else is using it, since these changes are made by many clients at once.
+    /// The data is computed again if other entries have to be evicted first.
+    /// Returns the result of the computation.
+    async fn update_entry<T, F>(&self, key: Key, update: F) -> Result<T, DatabaseError>
+    where
+        F: Fn(Option<&[u8]>) -> Result<(T, Vec<u8>), DatabaseError> + Send + Sync,
+        T: Send,
+    {
+        {
+            let hash_map_guard = self.shard(&key).read().await;
+
+            if let Some(rw_lock) = hash_map_guard.get(&key) {
+                let mut guard = rw_lock.write().await;
+
+                if !guard.is_expired(now()) {
+                    let (result, data) = update(Some(&guard.data))?;
+
+                    if !self.exceeds_memory_limit(data.len().saturating_sub(guard.data.len())) {
+                        let expires_at = guard.expires_at;
+                        self.replace_entry(key, &mut guard, data, expires_at)?;
+                        return Ok(result);
+                    }
+
+                    if self.eviction_policy == EvictionPolicy::NoEviction {
+                        return Err(OutOfMemory);
+                    }
+                }
+            }
+        }
+
+        // the entry has to be inserted or other entries have to be evicted, which needs the write lock
+        let mut hash_map_guard = self.shard(&key).write().await;
+
+        let existing = match hash_map_guard.get_mut(&key).map(RwLock::get_mut) {
+            Some(entry) if !entry.is_expired(now()) => Some((entry.data.len(), update(Some(&entry.data))?)),
+            _ => None,
+        };
+
+        match existing {
+            Some((length, (result, data))) => {
+                self.evict(&mut hash_map_guard, data.len().saturating_sub(length), &key)?;
+
+                let entry = hash_map_guard.get_mut(&key)
+                    .expect("the entry of the key is never evicted")
+                    .get_mut();
+                let expires_at = entry.expires_at;
+                self.replace_entry(key, entry, data, expires_at)?;
+                Ok(result)
+            }
+            None => {
+                // an expired entry is replaced like with insert
+                if let Some(old) = hash_map_guard.remove(&key) {
+                    self.forget_entry(&key, old.into_inner());
+                }
+
+                let (result, data) = update(None)?;
+                self.insert_entry(&mut hash_map_guard, key, data, None)?;
+                Ok(result)
+            }
+        }
     }
 
-    /// Returns a Result with a boolean indicating if a new entry was created (true = created)
-    async fn set(&self, id: u32, mut data: Vec<u8>) -> Result<(), DatabaseError> {
-        let hash_map_guard = self.data.read().await;
+    /// Sets when the entry expires, the change is logged with the operation
+    async fn change_expiry(&self, key: Key, operation: Operation, expires_at: Option<u64>) -> Result<(), DatabaseError> {
+        let hash_map_guard = self.shard(&key).read().await;
 
-        let rw_lock = match hash_map_guard.get(&id) {
+        let rw_lock = match hash_map_guard.get(&key) {
             Some(rw_lock) => rw_lock,
-            None => return Err(NotFound(id)),
+            None => return Err(NotFound(key)),
         };
 
         let mut guard = match rw_lock.try_write() {
             Ok(guard) => guard,
-            Err(_) => return Err(WriteBlocked(id)),
+            Err(_) => return Err(WriteBlocked(key)),
         };
 
-        guard.clear();
-        guard.append(&mut data);
+        if guard.is_expired(now()) {
+            return Err(NotFound(key));
+        }
+
+        let record = Record { operation, key, expires_at, data: vec![] };
+        self.log(&record)?;
+
+        self.track_expiry(&record.key, guard.expires_at, expires_at);
+        guard.expires_at = expires_at;
+        // a changed expiry is a change of the entry for COMPARE AND SET and WATCH
+        guard.version = self.next_version();
         Ok(())
     }
 
-    async fn insert(&self, id: u32, data: Vec<u8>) -> Result<(), DatabaseError> {
-        let mut hash_map_guard = self.data.write().await;
-
-        if hash_map_guard.contains_key(&id) {
-            return Err(AlreadyExists(id));
+    /// Returns the records that restore the state at a single point in time
+    async fn dump(&self) -> Vec<Record> {
+        // nobody else can hold a lock on an entry while the write locks of all shards are held
+        let mut shard_guards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            shard_guards.push(shard.write().await);
         }
 
-        hash_map_guard.insert(id, RwLock::new(data));
+        let now = now();
+        let mut records: Vec<Record> = shard_guards.iter_mut()
+            .flat_map(|shard_guard| shard_guard.iter_mut())
+            .map(|(key, rw_lock)| (key, rw_lock.get_mut()))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| Record {
+                operation: Operation::Insert,
+                key: key.clone(),
+                expires_at: entry.expires_at,
+                data: entry.data.clone(),
+            })
+            .collect();
 
-        Ok(())
+        let next_id = self.next_id.load(Ordering::Relaxed);
+        records.push(Record { operation: Operation::NextId, key: vec![], expires_at: None, data: next_id.to_be_bytes().to_vec() });
+
+        records
     }
 
-    async fn remove(&self, id: u32) -> Result<(), DatabaseError> {
-        let mut hash_map_guard = self.data.write().await;
+    /// Takes the snapshot that has to be saved to the returned path.
+    ///
+    /// No other snapshot can be saved until the returned guard is dropped.
+    async fn take_snapshot(&self) -> Result<(PathBuf, Vec<Record>, SavingSnapshotGuard), DatabaseError> {
+        let path = self.snapshot_path.clone().ok_or(SnapshotDisabled)?;
 
-        match hash_map_guard.remove(&id) {
-            Some(_) => Ok(()),
-            None => Err(NotFound(id))
+        if self.saving_snapshot.swap(true, Ordering::AcqRel) {
+            return Err(SaveInProgress);
         }
+        let guard = SavingSnapshotGuard(self.saving_snapshot.clone());
+
+        Ok((path, self.dump().await, guard))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Waits for the lock until the deadline, None if it isn't acquired in time. Without a deadline it waits as long as it takes.
+async fn within<T>(deadline: Option<tokio::time::Instant>, lock: impl Future<Output = T>) -> Option<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, lock).await.ok(),
+        None => Some(lock.await),
+    }
+}
 
-    #[tokio::test]
-    async fn test_get() {
-        let db = Repository::new();
+/// Whether data of the length extended by more bytes would be longer than MAX_DATA_LENGTH
+fn exceeds_max_data_length(length: usize, additional: usize) -> bool {
+    (length as u64).saturating_add(additional as u64) > MAX_DATA_LENGTH
+}
+
+/// Spawns the task that rewrites the append only file, once it grew enough since the last rewrite.
+///
+/// The task stops as soon as the repository is dropped.
+pub(crate) fn spawn_aof_rewrite_task(db: Weak<Repository>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            let Some(db) = db.upgrade() else {
+                break;
+            };
+
+            if !db.aof_needs_rewrite() {
+                continue;
+            }
+
+            match db.rewrite_aof().await {
+                Ok(()) => println!("rewrote append only file"),
+                Err(e) => eprintln!("automatic rewrite of the append only file failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Spawns the task that removes expired entries, so they don't occupy memory until they are accessed.
+///
+/// Every cycle removes expired entries in small batches, the write lock is released between the batches
+/// and the cycle ends after a fixed time, even if there are expired entries left.
+/// The task stops as soon as the repository is dropped.
+pub(crate) fn spawn_expiry_task(db: Weak<Repository>) {
+    const INTERVAL: Duration = Duration::from_millis(100);
+    const BATCH_SIZE: usize = 20;
+    const TIME_LIMIT: Duration = Duration::from_millis(25);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let Some(db) = db.upgrade() else {
+                break;
+            };
+
+            let start = Instant::now();
+            while db.remove_expired(BATCH_SIZE).await == BATCH_SIZE && start.elapsed() < TIME_LIMIT {
+                // let the waiting requests acquire the lock
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+}
+
+#[automock]
+#[async_trait::async_trait]
+impl RepositoryApi for Repository {
+    async fn get(&self, key: Key) -> Option<(Vec<u8>, u64)> {
+        let hash_map_guard = self.shard(&key).read().await;
+        let rw_lock = hash_map_guard.get(&key)?;
+        let entry_guard = rw_lock.read().await;
 
-        db.data.write().await.insert(1, RwLock::new(b"hello".to_vec()));
-        db.data.write().await.insert(2, RwLock::new(b"world".to_vec()));
+        let now = now();
+        if entry_guard.is_expired(now) {
+            return None;
+        }
 
-        assert_eq!(db.get(1).await, Some(b"hello".to_vec()));
-        assert_eq!(db.get(2).await, Some(b"world".to_vec()));
-        assert_eq!(db.get(3).await, None);
+        entry_guard.touch(now);
+        Some((entry_guard.data.clone(), entry_guard.version))
     }
 
-    #[tokio::test]
-    async fn test_set() {
-        let db = Repository::new();
+    async fn set(&self, key: Key, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.set_entry(key, data, ttl.map(expires_at), None, None).await.map(|_| ())
+    }
 
-        db.data.write().await.insert(1, RwLock::new(b"hello".to_vec()));
+    async fn set_wait(&self, key: Key, data: Vec<u8>, timeout: Duration) -> Result<(), DatabaseError> {
+        self.set_entry(key, data, None, Some(timeout), None).await.map(|_| ())
+    }
+
+    async fn compare_and_set(&self, key: Key, expected_version: u64, data: Vec<u8>) -> Result<u64, DatabaseError> {
+        // the entry locks are only held for single changes, so it waits instead of failing with WriteBlocked
+        self.set_entry(key, data, None, Some(Duration::MAX), Some(expected_version)).await
+    }
+
+    async fn insert(&self, key: Key, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        let mut hash_map_guard = self.shard(&key).write().await;
 
-        db.set(1, b"updated hello".to_vec()).await.unwrap();
+        if let Some(rw_lock) = hash_map_guard.get_mut(&key) {
+            if !rw_lock.get_mut().is_expired(now()) {
+                return Err(AlreadyExists(key));
+            }
 
-        assert_eq!(b"updated hello".to_vec(), db.data.read().await.get(&1).unwrap().read().await.to_vec());
+            // an expired entry is replaced, it expires on replay as well
+            if let Some(old) = hash_map_guard.remove(&key) {
+                self.forget_entry(&key, old.into_inner());
+            }
+        }
+
+        self.insert_entry(&mut hash_map_guard, key, data, ttl.map(expires_at))
     }
 
-    #[tokio::test]
-    async fn test_set_not_found() {
-        let db = Repository::new();
+    async fn upsert(&self, key: Key, data: Vec<u8>, ttl: Option<Duration>) -> Result<bool, DatabaseError> {
+        // the write lock of the shard is held from the check to the change, so nobody can insert or remove the entry in between
+        let mut hash_map_guard = self.shard(&key).write().await;
+
+        let length = match hash_map_guard.get_mut(&key).map(RwLock::get_mut) {
+            Some(entry) if !entry.is_expired(now()) => Some(entry.data.len()),
+            _ => None,
+        };
+
+        match length {
+            Some(length) => {
+                self.evict(&mut hash_map_guard, data.len().saturating_sub(length), &key)?;
 
-        let err = db.set(1, b"updated hello".to_vec()).await.unwrap_err();
+                let entry = hash_map_guard.get_mut(&key)
+                    .expect("the entry of the key is never evicted")
+                    .get_mut();
+                self.replace_entry(key, entry, data, ttl.map(expires_at))?;
+                Ok(false)
+            }
+            None => {
+                // an expired entry is replaced like with insert
+                if let Some(old) = hash_map_guard.remove(&key) {
+                    self.forget_entry(&key, old.into_inner());
+                }
 
-        assert_eq!(err, NotFound(1));
+                self.insert_entry(&mut hash_map_guard, key, data, ttl.map(expires_at))?;
+                Ok(true)
+            }
+        }
     }
 
-    #[tokio::test]
-    async fn test_insert() {
-        let db = Repository::new();
+    async fn insert_auto(&self, data: Vec<u8>) -> Result<u32, DatabaseError> {
+        loop {
+            let id = self.next_id.load(Ordering::Acquire);
+            let next_id = id.checked_add(1).ok_or(IdsExhausted)?;
 
-        db.insert(1, b"hello".to_vec()).await.unwrap();
+            let key = id.to_be_bytes().to_vec();
+            let mut hash_map_guard = self.shard(&key).write().await;
 
-        assert_eq!(b"hello".to_vec(), db.data.read().await.get(&1).unwrap().read().await.to_vec());
+            // ids already used by insert are skipped
+            if hash_map_guard.contains_key(&key) {
+                let _ = self.next_id.compare_exchange(id, next_id, Ordering::AcqRel, Ordering::Acquire);
+                continue;
+            }
+
+            // another insert auto took the id in the meantime
+            if self.next_id.compare_exchange(id, next_id, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                continue;
+            }
+
+            self.evict(&mut hash_map_guard, memory_usage(&key, &data), &key)?;
+
+            let record = Record { operation: Operation::InsertAuto, key, expires_at: None, data };
+            self.log(&record)?;
+
+            self.used_memory.fetch_add(memory_usage(&record.key, &record.data) as u64, Ordering::Relaxed);
+            hash_map_guard.insert(record.key, RwLock::new(Entry::new(record.data, None, self.next_version())));
+
+            return Ok(id);
+        }
     }
 
-    #[tokio::test]
-    async fn test_insert_already_exists() {
-        let db = Repository::new();
+    async fn increment(&self, key: Key, delta: i64, encoding: IntegerEncoding) -> Result<i64, DatabaseError> {
+        let error_key = key.clone();
+        self.update_entry(key, |data| match data {
+            Some(data) => encoding.add(&error_key, data, delta),
+            // a missing entry counts as 0
+            None => Ok((delta, encoding.encode(delta))),
+        }).await
+    }
+
+    async fn append(&self, key: Key, data: Vec<u8>) -> Result<u64, DatabaseError> {
+        let appended_key = key.clone();
+        self.update_entry(key, |current| {
+            let current = current.unwrap_or_default();
+            if exceeds_max_data_length(current.len(), data.len()) {
+                return Err(DataTooLarge(appended_key.clone()));
+            }
+
+            let mut appended = current.to_vec();
+            appended.extend_from_slice(&data);
+            Ok((appended.len() as u64, appended))
+        }).await
+    }
+
+    async fn length(&self, key: Key) -> Option<u64> {
+        let hash_map_guard = self.shard(&key).read().await;
+        let entry_guard = hash_map_guard.get(&key)?.read().await;
 
-        db.data.write().await.insert(1, RwLock::new(b"hello".to_vec()));
+        let now = now();
+        if entry_guard.is_expired(now) {
+            return None;
+        }
 
-        let err = db.insert(1, b"new hello".to_vec()).await.unwrap_err();
+        entry_guard.touch(now);
 
-        assert_eq!(err, AlreadyExists(1));
+        Some(entry_guard.data.len() as u64)
     }
 
-    #[tokio::test]
-    async fn test_remove() {
-        let db = Repository::new();
+    async fn get_range(&self, key: Key, offset: u64, length: u64) -> Option<Vec<u8>> {
+        let hash_map_guard = self.shard(&key).read().await;
+        let entry_guard = hash_map_guard.get(&key)?.read().await;
 
-        db.data.write().await.insert(1, RwLock::new(b"hello".to_vec()));
+        let now = now();
+        if entry_guard.is_expired(now) {
+            return None;
+        }
 
-        db.remove(1).await.unwrap();
+        entry_guard.touch(now);
 
-        assert!(db.data.read().await.get(&1).is_none());
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(entry_guard.data.len());
+        let end = start.saturating_add(usize::try_from(length).unwrap_or(usize::MAX)).min(entry_guard.data.len());
+        Some(entry_guard.data[start..end].to_vec())
     }
 
-    #[tokio::test]
-    async fn test_remove_not_found() {
-        let db = Repository::new();
+    async fn set_range(&self, key: Key, offset: u64, data: Vec<u8>) -> Result<u64, DatabaseError> {
+        let offset = usize::try_from(offset).map_err(|_| DataTooLarge(key.clone()))?;
+        if exceeds_max_data_length(offset, data.len()) {
+            return Err(DataTooLarge(key));
+        }
+        let end = offset + data.len();
+
+        self.update_entry(key, |current| {
+            let mut changed = current.unwrap_or_default().to_vec();
+            // the gap after the end of the data is filled with zero bytes
+            if changed.len() < end {
+                changed.resize(end, 0);
+            }
+            changed[offset..end].copy_from_slice(&data);
+            Ok((changed.len() as u64, changed))
+        }).await
+    }
+
+    async fn remove(&self, key: Key) -> Result<(), DatabaseError> {
+        let mut hash_map_guard = self.shard(&key).write().await;
+
+        let expired = match hash_map_guard.get_mut(&key) {
+            Some(rw_lock) => rw_lock.get_mut().is_expired(now()),
+            None => return Err(NotFound(key)),
+        };
+
+        // an expired entry is already gone for the clients, it isn't logged since it expires on replay as well
+        if !expired {
+            self.log(&Record { operation: Operation::Remove, key: key.clone(), expires_at: None, data: vec![] })?;
+        }
+
+        if let Some(old) = hash_map_guard.remove(&key) {
+            self.forget_entry(&key, old.into_inner());
+        }
+
+        if expired {
+            Err(NotFound(key))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn expire(&self, key: Key, ttl: Duration) -> Result<(), DatabaseError> {
+        self.change_expiry(key, Operation::Expire, Some(expires_at(ttl))).await
+    }
+
+    async fn ttl(&self, key: Key) -> Result<Option<Duration>, DatabaseError> {
+        let hash_map_guard = self.shard(&key).read().await;
+
+        let rw_lock = match hash_map_guard.get(&key) {
+            Some(rw_lock) => rw_lock,
+            None => return Err(NotFound(key)),
+        };
+
+        let entry_guard = rw_lock.read().await;
+
+        let now = now();
+        if entry_guard.is_expired(now) {
+            return Err(NotFound(key));
+        }
+
+        Ok(entry_guard.ttl(now))
+    }
+
+    async fn persist(&self, key: Key) -> Result<(), DatabaseError> {
+        self.change_expiry(key, Operation::Persist, None).await
+    }
+
+    async fn memory_usage(&self, key: Key) -> Result<(u64, u64), DatabaseError> {
+        let hash_map_guard = self.shard(&key).read().await;
+
+        let rw_lock = match hash_map_guard.get(&key) {
+            Some(rw_lock) => rw_lock,
+            None => return Err(NotFound(key)),
+        };
+
+        let entry_guard = rw_lock.read().await;
+
+        if entry_guard.is_expired(now()) {
+            return Err(NotFound(key));
+        }
+
+        // the total is maintained by every change, so the entries don't have to be walked
+        Ok((memory_usage(&key, &entry_guard.data) as u64, self.used_memory.load(Ordering::Relaxed)))
+    }
+
+    async fn version(&self, key: Key) -> Option<u64> {
+        let hash_map_guard = self.shard(&key).read().await;
+        let entry_guard = hash_map_guard.get(&key)?.read().await;
+
+        if entry_guard.is_expired(now()) {
+            return None;
+        }
+
+        Some(entry_guard.version)
+    }
+
+    async fn execute(&self, watched: Vec<(Key, Option<u64>)>, operations: Vec<TransactionOperation>) -> Result<Vec<TransactionOutcome>, DatabaseError> {
+        self.execute_transaction(watched, operations).await
+    }
 
-        db.data.write().await.insert(1, RwLock::new(b"hello".to_vec()));
+    async fn rewrite_aof(&self) -> Result<(), DatabaseError> {
+        let aof = self.aof.as_ref().ok_or(AofDisabled)?;
 
-        let err = db.remove(2).await.unwrap_err();
+        let rewrite_path = {
+            let mut aof_guard = aof.lock().expect("aof mutex poisoned");
+            if !aof_guard.start_rewrite() {
+                return Err(RewriteInProgress);
+            }
+            aof_guard.rewrite_path()
+        };
+
+        // changes after this point are collected by the aof and appended to the rewritten file
+        let records = self.dump().await;
+
+        // writing the file blocks, so it shouldn't run on the async worker threads
+        let file = tokio::task::spawn_blocking(move || write_rewrite_file(&rewrite_path, &records))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+
+        blocking(|| {
+            let mut aof_guard = aof.lock().expect("aof mutex poisoned");
+            if let Err(e) = file.and_then(|file| aof_guard.finish_rewrite(file)) {
+                aof_guard.abort_rewrite();
+                return Err(Persistence(format!("{:#}", e)));
+            }
 
-        assert_eq!(err, NotFound(2));
-        assert!(db.data.read().await.get(&1).is_some());
+            Ok(())
+        })
     }
-}
\ No newline at end of file
+
+    async fn save_snapshot(&self) -> Result<(), DatabaseError> {
+        let (path, records, _guard) = self.take_snapshot().await?;
+
+        // writing the file blocks, so it shouldn't run on the async worker threads
+        tokio::task::spawn_blocking(move || write_snapshot(&path, &records))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result)
+            .map_err(|e| Persistence(format!("{:#}", e)))
+    }
+
+    async fn background_save_snapshot(&self) -> Result<(), DatabaseError> {
+        let (path, records, guard) = self.take_snapshot().await?;
+
+        tokio::task::spawn_blocking(move || {
+            match write_snapshot(&path, &records) {
+                Ok(()) => println!("saved snapshot to {}", path.display()),
+                Err(e) => eprintln!("background save of snapshot failed: {:#}", e),
+            }
+            drop(guard);
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::temp_path;
+    use crate::repository::error::DatabaseError::{IntegerOverflow, NotAnInteger};
+
+    use crate::persistence::aof::AutoRewrite;
+
+    const NO_AUTO_REWRITE: AutoRewrite = AutoRewrite { percentage: 0, min_size: 0 };
+
+    fn aof_config(path: &std::path::Path) -> Config {
+        Config {
+            appendonly: true,
+            appendfilename: path.to_path_buf(),
+            appendfsync: FsyncPolicy::Always,
+            auto_aof_rewrite: NO_AUTO_REWRITE,
+            ..Config::default()
+        }
+    }
+
+    fn key(id: u32) -> Key {
+        id.to_be_bytes().to_vec()
+    }
+
+    /// data of the entry without its version
+    async fn data(db: &Repository, key: Key) -> Option<Vec<u8>> {
+        db.get(key).await.map(|(data, _)| data)
+    }
+
+    /// sorted keys of all entries
+    async fn keys(db: &Repository) -> Vec<Key> {
+        let mut keys = Vec::new();
+        for shard in &db.shards {
+            let shard = shard.read().await;
+            keys.extend((0..shard.len()).filter_map(|index| shard.get_index(index)).map(|(key, _)| key.clone()));
+        }
+        keys.sort();
+        keys
+    }
+
+    fn snapshot_config(path: &std::path::Path) -> Config {
+        Config {
+            dbfilename: path.to_path_buf(),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get() {
+        let db = Repository::new();
+
+        db.shard(&key(1)).write().await.insert(key(1), RwLock::new(Entry::new(b"hello".to_vec(), None, 1)));
+        db.shard(&key(2)).write().await.insert(key(2), RwLock::new(Entry::new(b"world".to_vec(), None, 1)));
+
+        assert_eq!(data(&db, key(1)).await, Some(b"hello".to_vec()));
+        assert_eq!(data(&db, key(2)).await, Some(b"world".to_vec()));
+        assert_eq!(data(&db, key(3)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_binary_keys() {
+        let db = Repository::new();
+
+        db.insert(b"session:abc123".to_vec(), b"hello".to_vec(), None).await.unwrap();
+        db.insert(vec![0, 0xFF, 0], b"world".to_vec(), None).await.unwrap();
+
+        assert_eq!(data(&db, b"session:abc123".to_vec()).await, Some(b"hello".to_vec()));
+        assert_eq!(data(&db, vec![0, 0xFF, 0]).await, Some(b"world".to_vec()));
+        assert_eq!(data(&db, b"session".to_vec()).await, None);
+    }
+
+    #[test]
+    fn test_exceeds_max_data_length() {
+        assert!(!exceeds_max_data_length(MAX_DATA_LENGTH as usize - 5, 5));
+        assert!(exceeds_max_data_length(MAX_DATA_LENGTH as usize - 5, 6));
+        assert!(exceeds_max_data_length(usize::MAX, 1));
+    }
+
+    #[tokio::test]
+    async fn test_set_range_too_large() {
+        let db = Repository::new();
+
+        assert_eq!(db.set_range(key(1), MAX_DATA_LENGTH - 4, b"hello".to_vec()).await, Err(DataTooLarge(key(1))));
+        assert_eq!(data(&db, key(1)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_key_too_long_for_record() {
+        let db = Repository::new();
+        let key = vec![b'k'; u16::MAX as usize + 1];
+
+        assert!(matches!(db.insert(key.clone(), b"hello".to_vec(), None).await, Err(Persistence(_))));
+        assert!(matches!(db.upsert(key.clone(), b"hello".to_vec(), None).await, Err(Persistence(_))));
+        assert_eq!(data(&db, key).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_set() {
+        let db = Repository::new();
+
+        db.shard(&key(1)).write().await.insert(key(1), RwLock::new(Entry::new(b"hello".to_vec(), None, 1)));
+
+        db.set(key(1), b"updated hello".to_vec(), None).await.unwrap();
+
+        assert_eq!(b"updated hello".to_vec(), db.shard(&key(1)).read().await.get(&key(1)).unwrap().read().await.data);
+    }
+
+    #[tokio::test]
+    async fn test_set_not_found() {
+        let db = Repository::new();
+
+        let err = db.set(key(1), b"updated hello".to_vec(), None).await.unwrap_err();
+
+        assert_eq!(err, NotFound(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_insert() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+
+        assert_eq!(b"hello".to_vec(), db.shard(&key(1)).read().await.get(&key(1)).unwrap().read().await.data);
+    }
+
+    #[tokio::test]
+    async fn test_insert_already_exists() {
+        let db = Repository::new();
+
+        db.shard(&key(1)).write().await.insert(key(1), RwLock::new(Entry::new(b"hello".to_vec(), None, 1)));
+
+        let err = db.insert(key(1), b"new hello".to_vec(), None).await.unwrap_err();
+
+        assert_eq!(err, AlreadyExists(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_auto() {
+        let db = Repository::new();
+
+        db.shard(&key(1)).write().await.insert(key(1), RwLock::new(Entry::new(b"hello".to_vec(), None, 1)));
+
+        assert_eq!(db.insert_auto(b"first".to_vec()).await.unwrap(), 0);
+        // id 1 is already used
+        assert_eq!(db.insert_auto(b"second".to_vec()).await.unwrap(), 2);
+
+        // removed ids are not reused
+        db.remove(key(2)).await.unwrap();
+        assert_eq!(db.insert_auto(b"third".to_vec()).await.unwrap(), 3);
+
+        assert_eq!(data(&db, key(0)).await, Some(b"first".to_vec()));
+        assert_eq!(data(&db, key(3)).await, Some(b"third".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_insert_auto_ids_exhausted() {
+        let db = Repository::new();
+
+        db.next_id.store(u32::MAX, Ordering::Relaxed);
+
+        let err = db.insert_auto(b"hello".to_vec()).await.unwrap_err();
+
+        assert_eq!(err, IdsExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_upsert() {
+        let db = Repository::new();
+
+        assert!(db.upsert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap());
+        assert_eq!(data(&db, key(1)).await, Some(b"hello".to_vec()));
+        assert!(db.ttl(key(1)).await.unwrap().is_some());
+
+        // the expiry is replaced like with set
+        assert!(!db.upsert(key(1), b"updated hello".to_vec(), None).await.unwrap());
+        assert_eq!(data(&db, key(1)).await, Some(b"updated hello".to_vec()));
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+        assert_eq!(db.memory_usage(key(1)).await.unwrap().1, memory_usage(&key(1), b"updated hello") as u64);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_replaces_expired_entry() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::ZERO)).await.unwrap();
+
+        assert!(db.upsert(key(1), b"new hello".to_vec(), None).await.unwrap());
+        assert_eq!(data(&db, key(1)).await, Some(b"new hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_upsert_replays_aof() {
+        let path = temp_path("repository-upsert-replays-aof.aof");
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+        db.upsert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.upsert(key(1), b"updated hello".to_vec(), None).await.unwrap();
+        db.upsert(key(2), b"world".to_vec(), None).await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"updated hello".to_vec()));
+        assert_eq!(data(&db, key(2)).await, Some(b"world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_increment() {
+        let db = Repository::new();
+
+        assert_eq!(db.increment(key(1), 5, IntegerEncoding::Ascii).await.unwrap(), 5);
+        assert_eq!(db.increment(key(1), -7, IntegerEncoding::Ascii).await.unwrap(), -2);
+        assert_eq!(data(&db, key(1)).await, Some(b"-2".to_vec()));
+
+        assert_eq!(db.increment(key(2), 1, IntegerEncoding::BigEndian).await.unwrap(), 1);
+        assert_eq!(data(&db, key(2)).await, Some(1i64.to_be_bytes().to_vec()));
+
+        // the same data read in another encoding
+        assert_eq!(db.increment(key(1), 1, IntegerEncoding::BigEndian).await.unwrap_err(), NotAnInteger(key(1)));
+        assert_eq!(db.memory_usage(key(1)).await.unwrap().1, (memory_usage(&key(1), b"-2") + memory_usage(&key(2), &[0; 8])) as u64);
+    }
+
+    #[tokio::test]
+    async fn test_increment_keeps_expiry() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"41".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        assert_eq!(db.increment(key(1), 1, IntegerEncoding::Ascii).await.unwrap(), 42);
+        assert!(db.ttl(key(1)).await.unwrap().is_some());
+
+        // an expired counter starts again at 0 without an expiry
+        db.expire(key(1), Duration::ZERO).await.unwrap();
+        assert_eq!(db.increment(key(1), 1, IntegerEncoding::Ascii).await.unwrap(), 1);
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_increment_overflow() {
+        let db = Repository::new();
+
+        db.insert(key(1), i64::MIN.to_be_bytes().to_vec(), None).await.unwrap();
+
+        assert_eq!(db.increment(key(1), -1, IntegerEncoding::BigEndian).await.unwrap_err(), IntegerOverflow(key(1)));
+        assert_eq!(data(&db, key(1)).await, Some(i64::MIN.to_be_bytes().to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_increment_concurrently() {
+        let db = Arc::new(Repository::new());
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        db.increment(key(1), 1, IntegerEncoding::Ascii).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(data(&db, key(1)).await, Some(b"800".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_append() {
+        let db = Repository::new();
+
+        assert_eq!(db.append(key(1), b"hello".to_vec()).await.unwrap(), 5);
+        db.expire(key(1), Duration::from_secs(60)).await.unwrap();
+        assert_eq!(db.append(key(1), b" world".to_vec()).await.unwrap(), 11);
+
+        assert_eq!(data(&db, key(1)).await, Some(b"hello world".to_vec()));
+        assert!(db.ttl(key(1)).await.unwrap().is_some());
+        assert_eq!(db.memory_usage(key(1)).await.unwrap().1, memory_usage(&key(1), b"hello world") as u64);
+    }
+
+    #[tokio::test]
+    async fn test_length() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello world".to_vec(), None).await.unwrap();
+
+        assert_eq!(db.length(key(1)).await, Some(11));
+        assert_eq!(db.length(key(2)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_get_range() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello world".to_vec(), None).await.unwrap();
+
+        assert_eq!(db.get_range(key(1), 6, 5).await, Some(b"world".to_vec()));
+        assert_eq!(db.get_range(key(1), 6, u64::MAX).await, Some(b"world".to_vec()));
+        assert_eq!(db.get_range(key(1), 0, 0).await, Some(vec![]));
+        assert_eq!(db.get_range(key(1), u64::MAX, 5).await, Some(vec![]));
+        assert_eq!(db.get_range(key(2), 0, 5).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_set_range() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello world".to_vec(), None).await.unwrap();
+
+        assert_eq!(db.set_range(key(1), 6, b"there".to_vec()).await.unwrap(), 11);
+        assert_eq!(data(&db, key(1)).await, Some(b"hello there".to_vec()));
+
+        assert_eq!(db.set_range(key(1), 6, b"there, world".to_vec()).await.unwrap(), 18);
+        assert_eq!(data(&db, key(1)).await, Some(b"hello there, world".to_vec()));
+
+        // the gap is filled with zero bytes
+        assert_eq!(db.set_range(key(2), 2, b"hi".to_vec()).await.unwrap(), 4);
+        assert_eq!(data(&db, key(2)).await, Some(b"\0\0hi".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_append_replays_aof() {
+        let path = temp_path("repository-append-replays-aof.aof");
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+        db.append(key(1), b"hello".to_vec()).await.unwrap();
+        db.append(key(1), b" world".to_vec()).await.unwrap();
+        db.set_range(key(1), 0, b"H".to_vec()).await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"Hello world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let db = Repository::new();
+
+        db.shard(&key(1)).write().await.insert(key(1), RwLock::new(Entry::new(b"hello".to_vec(), None, 1)));
+
+        db.remove(key(1)).await.unwrap();
+
+        assert!(db.shard(&key(1)).read().await.get(&key(1)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remove_not_found() {
+        let db = Repository::new();
+
+        db.shard(&key(1)).write().await.insert(key(1), RwLock::new(Entry::new(b"hello".to_vec(), None, 1)));
+
+        let err = db.remove(key(2)).await.unwrap_err();
+
+        assert_eq!(err, NotFound(key(2)));
+        assert!(db.shard(&key(1)).read().await.get(&key(1)).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_open_replays_aof() {
+        let path = temp_path("repository-open-replays-aof.aof");
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(2), b"world".to_vec(), None).await.unwrap();
+        db.insert(b"session:abc123".to_vec(), b"session".to_vec(), None).await.unwrap();
+        db.set(key(1), b"updated hello".to_vec(), None).await.unwrap();
+        db.remove(key(2)).await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"updated hello".to_vec()));
+        assert_eq!(data(&db, key(2)).await, None);
+        assert_eq!(data(&db, b"session:abc123".to_vec()).await, Some(b"session".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_open_aof_skips_failed_changes() {
+        let path = temp_path("repository-open-aof-skips-failed-changes.aof");
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(1), b"world".to_vec(), None).await.unwrap_err();
+        db.set(key(2), b"world".to_vec(), None).await.unwrap_err();
+        drop(db);
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![Record { operation: Operation::Insert, key: key(1), expires_at: None, data: b"hello".to_vec() }]);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_aof() {
+        let path = temp_path("repository-rewrite-aof.aof");
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(2), b"world".to_vec(), None).await.unwrap();
+        for i in 0..10u8 {
+            db.set(key(1), vec![i], None).await.unwrap();
+        }
+        db.remove(key(2)).await.unwrap();
+
+        let size_before = std::fs::metadata(&path).unwrap().len();
+        db.rewrite_aof().await.unwrap();
+        let size_after = std::fs::metadata(&path).unwrap().len();
+
+        db.insert(key(3), b"after rewrite".to_vec(), None).await.unwrap();
+        drop(db);
+
+        assert!(size_after < size_before);
+
+        let (_, records) = Aof::open(&path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+
+        assert_eq!(records, vec![
+            Record { operation: Operation::Insert, key: key(1), expires_at: None, data: vec![9] },
+            Record { operation: Operation::NextId, key: vec![], expires_at: None, data: 0u32.to_be_bytes().to_vec() },
+            Record { operation: Operation::Insert, key: key(3), expires_at: None, data: b"after rewrite".to_vec() },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_aof_disabled() {
+        let db = Repository::new();
+
+        let err = db.rewrite_aof().await.unwrap_err();
+
+        assert_eq!(err, AofDisabled);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_aof_in_progress() {
+        let path = temp_path("repository-rewrite-aof-in-progress.aof");
+
+        let db = Repository::open(&aof_config(&path)).unwrap();
+        db.aof.as_ref().unwrap().lock().unwrap().start_rewrite();
+
+        let err = db.rewrite_aof().await.unwrap_err();
+
+        assert_eq!(err, RewriteInProgress);
+    }
+
+    #[tokio::test]
+    async fn test_aof_needs_rewrite() {
+        let path = temp_path("repository-aof-needs-rewrite.aof");
+
+        let db = Repository::open(&Config {
+            auto_aof_rewrite: AutoRewrite { percentage: 100, min_size: 1 },
+            ..aof_config(&path)
+        }).unwrap();
+        assert!(!db.aof_needs_rewrite());
+
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        assert!(db.aof_needs_rewrite());
+
+        db.rewrite_aof().await.unwrap();
+        assert!(!db.aof_needs_rewrite());
+    }
+
+    #[tokio::test]
+    async fn test_save_snapshot() {
+        let path = temp_path("repository-save-snapshot.rdb");
+
+        let db = Repository::open(&snapshot_config(&path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(2), b"world".to_vec(), None).await.unwrap();
+        db.save_snapshot().await.unwrap();
+
+        // changes after the snapshot are not saved
+        db.remove(key(2)).await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&snapshot_config(&path)).unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"hello".to_vec()));
+        assert_eq!(data(&db, key(2)).await, Some(b"world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_background_save_snapshot() {
+        let path = temp_path("repository-background-save-snapshot.rdb");
+
+        let db = Repository::open(&snapshot_config(&path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.background_save_snapshot().await.unwrap();
+
+        // the snapshot is already taken, changes after it are not saved
+        db.set(key(1), b"updated hello".to_vec(), None).await.unwrap();
+
+        while db.saving_snapshot.load(Ordering::Acquire) {
+            tokio::task::yield_now().await;
+        }
+
+        let db = Repository::open(&snapshot_config(&path)).unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_save_snapshot_in_progress() {
+        let path = temp_path("repository-save-snapshot-in-progress.rdb");
+
+        let db = Repository::open(&snapshot_config(&path)).unwrap();
+        db.saving_snapshot.store(true, Ordering::Release);
+
+        assert_eq!(db.save_snapshot().await.unwrap_err(), SaveInProgress);
+        assert_eq!(db.background_save_snapshot().await.unwrap_err(), SaveInProgress);
+    }
+
+    #[tokio::test]
+    async fn test_save_snapshot_disabled() {
+        let db = Repository::new();
+
+        assert_eq!(db.save_snapshot().await.unwrap_err(), SnapshotDisabled);
+    }
+
+    #[tokio::test]
+    async fn test_next_id_survives_restart() {
+        let aof_path = temp_path("repository-next-id-survives-restart.aof");
+        let snapshot_path = temp_path("repository-next-id-survives-restart.rdb");
+
+        let db = Repository::open(&aof_config(&aof_path)).unwrap();
+        db.insert_auto(b"hello".to_vec()).await.unwrap();
+        db.insert_auto(b"world".to_vec()).await.unwrap();
+        db.remove(key(1)).await.unwrap();
+        drop(db);
+
+        // replayed from the append only file
+        let db = Repository::open(&aof_config(&aof_path)).unwrap();
+        assert_eq!(db.insert_auto(b"after restart".to_vec()).await.unwrap(), 2);
+
+        // kept by the rewrite, even though the entry with the highest id is removed
+        db.remove(key(2)).await.unwrap();
+        db.rewrite_aof().await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&aof_config(&aof_path)).unwrap();
+        assert_eq!(db.insert_auto(b"after rewrite".to_vec()).await.unwrap(), 3);
+
+        // kept by snapshots
+        let db = Repository::open(&snapshot_config(&snapshot_path)).unwrap();
+        db.insert_auto(b"hello".to_vec()).await.unwrap();
+        db.remove(key(0)).await.unwrap();
+        db.save_snapshot().await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&snapshot_config(&snapshot_path)).unwrap();
+        assert_eq!(db.insert_auto(b"after restart".to_vec()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_expired() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::ZERO)).await.unwrap();
+        db.insert(key(2), b"world".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+
+        assert_eq!(data(&db, key(1)).await, None);
+        assert_eq!(data(&db, key(2)).await, Some(b"world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_expire_ttl_persist() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+
+        db.expire(key(1), Duration::from_secs(60)).await.unwrap();
+        let ttl = db.ttl(key(1)).await.unwrap().unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(59));
+
+        db.persist(key(1)).await.unwrap();
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+
+        db.expire(key(1), Duration::ZERO).await.unwrap();
+        assert_eq!(db.ttl(key(1)).await.unwrap_err(), NotFound(key(1)));
+        assert_eq!(db.persist(key(1)).await.unwrap_err(), NotFound(key(1)));
+        assert_eq!(db.expire(key(1), Duration::from_secs(60)).await.unwrap_err(), NotFound(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_expire_not_found() {
+        let db = Repository::new();
+
+        assert_eq!(db.expire(key(1), Duration::from_secs(60)).await.unwrap_err(), NotFound(key(1)));
+        assert_eq!(db.ttl(key(1)).await.unwrap_err(), NotFound(key(1)));
+        assert_eq!(db.persist(key(1)).await.unwrap_err(), NotFound(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_set_replaces_expiry() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+
+        db.set(key(1), b"updated hello".to_vec(), None).await.unwrap();
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+
+        db.set(key(1), b"expired hello".to_vec(), Some(Duration::ZERO)).await.unwrap();
+        assert_eq!(db.set(key(1), b"hello".to_vec(), None).await.unwrap_err(), NotFound(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_replaces_expired_entry() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::ZERO)).await.unwrap();
+        db.insert(key(1), b"new hello".to_vec(), None).await.unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"new hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_remove_expired() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::ZERO)).await.unwrap();
+
+        assert_eq!(db.remove(key(1)).await.unwrap_err(), NotFound(key(1)));
+        assert!(db.shard(&key(1)).read().await.get(&key(1)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expiry_survives_restart() {
+        let aof_path = temp_path("repository-expiry-survives-restart.aof");
+        let snapshot_path = temp_path("repository-expiry-survives-restart.rdb");
+
+        let db = Repository::open(&aof_config(&aof_path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.insert(key(2), b"world".to_vec(), None).await.unwrap();
+        db.expire(key(2), Duration::ZERO).await.unwrap();
+        db.insert(key(3), b"persisted".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.persist(key(3)).await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&aof_config(&aof_path)).unwrap();
+        assert!(db.ttl(key(1)).await.unwrap().is_some());
+        assert_eq!(data(&db, key(2)).await, None);
+        assert_eq!(db.ttl(key(3)).await.unwrap(), None);
+
+        // expired entries are dropped by the rewrite
+        db.rewrite_aof().await.unwrap();
+        drop(db);
+
+        let (_, records) = Aof::open(&aof_path, FsyncPolicy::Always, NO_AUTO_REWRITE).unwrap();
+        assert!(records.iter().all(|record| record.key != key(2)));
+
+        let db = Repository::open(&aof_config(&aof_path)).unwrap();
+        assert!(db.ttl(key(1)).await.unwrap().is_some());
+
+        // kept by snapshots
+        let db = Repository::open(&snapshot_config(&snapshot_path)).unwrap();
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.save_snapshot().await.unwrap();
+        drop(db);
+
+        let db = Repository::open(&snapshot_config(&snapshot_path)).unwrap();
+        assert!(db.ttl(key(1)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_remove_expired_in_batches() {
+        let db = Repository::new();
+
+        for i in 0..5 {
+            db.insert(key(i), b"expired".to_vec(), Some(Duration::ZERO)).await.unwrap();
+        }
+        db.insert(key(5), b"expires later".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.insert(key(6), b"never expires".to_vec(), None).await.unwrap();
+
+        assert_eq!(db.remove_expired(3).await, 3);
+        assert_eq!(db.remove_expired(3).await, 2);
+        assert_eq!(db.remove_expired(3).await, 0);
+
+        assert_eq!(keys(&db).await, vec![key(5), key(6)]);
+        assert_eq!(expiring(&db).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expiring_index_follows_changes() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.insert(key(2), b"world".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.insert(key(3), b"persisted".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        assert_eq!(expiring(&db).len(), 3);
+
+        // the expiry changes, so the old expiry has to be replaced
+        db.expire(key(1), Duration::ZERO).await.unwrap();
+        db.set(key(2), b"updated world".to_vec(), None).await.unwrap();
+        db.persist(key(3)).await.unwrap();
+
+        let expiring = expiring(&db);
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].1, key(1));
+
+        // a key that changed before the sweeper acquires the write lock is skipped
+        db.remove(key(1)).await.unwrap_err();
+        assert!(super::tests::expiring(&db).is_empty());
+        assert_eq!(db.remove_expired(10).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_task() {
+        let db = Arc::new(Repository::new());
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_millis(10))).await.unwrap();
+        spawn_expiry_task(Arc::downgrade(&db));
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert!(keys(&db).await.is_empty());
+    }
+    /// the expiring keys of all shards
+    fn expiring(db: &Repository) -> Vec<(u64, Key)> {
+        db.expiring.iter().flat_map(|expiring| expiring.lock().unwrap().clone()).collect()
+    }
+
+    /// a single shard, so the eviction policies compare all entries
+    fn limited(max_memory: u64, eviction_policy: EvictionPolicy) -> Repository {
+        Repository { max_memory, eviction_policy, ..Repository::with_shards(1) }
+    }
+
+    /// memory limit for the number of entries with a 4 bytes key and "hello" as data, with a bit to spare
+    fn limit_for(entries: u64) -> u64 {
+        entries * memory_usage(&key(1), b"hello") as u64 + 3
+    }
+
+    #[tokio::test]
+    async fn test_used_memory() {
+        let db = Repository::new();
+
+        let hello = memory_usage(&key(1), b"updated hello") as u64;
+        let session = memory_usage(b"session", b"world") as u64;
+
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(b"session".to_vec(), b"world".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        assert_eq!(db.used_memory.load(Ordering::Relaxed), hello - 8 + session);
+
+        db.set(key(1), b"updated hello".to_vec(), None).await.unwrap();
+        assert_eq!(db.used_memory.load(Ordering::Relaxed), hello + session);
+        assert_eq!(db.memory_usage(key(1)).await.unwrap(), (hello, hello + session));
+
+        db.remove(b"session".to_vec()).await.unwrap();
+        assert_eq!(db.used_memory.load(Ordering::Relaxed), hello);
+        assert_eq!(db.memory_usage(b"session".to_vec()).await.unwrap_err(), NotFound(b"session".to_vec()));
+
+        db.expire(key(1), Duration::ZERO).await.unwrap();
+        db.remove_expired(10).await;
+        assert_eq!(db.used_memory.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_no_eviction_rejects_writes() {
+        let db = limited(limit_for(2), EvictionPolicy::NoEviction);
+
+        db.insert(key(1), b"hello world".to_vec(), None).await.unwrap();
+
+        assert_eq!(db.insert(key(2), b"hello world".to_vec(), None).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.insert_auto(b"hello world".to_vec()).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.set(key(1), vec![0; 1000], None).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.upsert(key(1), vec![0; 1000], None).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.upsert(key(2), b"hello world".to_vec(), None).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.increment(key(2), 1, IntegerEncoding::BigEndian).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.append(key(1), vec![0; 1000]).await.unwrap_err(), OutOfMemory);
+        assert_eq!(db.set_range(key(1), 1000, b"hello".to_vec()).await.unwrap_err(), OutOfMemory);
+
+        // writes that don't need more memory are still accepted
+        db.set(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(2), b"hello".to_vec(), None).await.unwrap();
+        assert_eq!(keys(&db).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_all_keys_lru_evicts_least_recently_used() {
+        let db = limited(limit_for(3), EvictionPolicy::AllKeysLru);
+
+        for i in 1..=3 {
+            db.insert(key(i), b"hello".to_vec(), None).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        data(&db, key(1)).await.unwrap();
+
+        db.insert(key(4), b"hello".to_vec(), None).await.unwrap();
+
+        assert_eq!(data(&db, key(2)).await, None);
+        assert!(data(&db, key(1)).await.is_some() && data(&db, key(3)).await.is_some());
+        assert_eq!(db.used_memory.load(Ordering::Relaxed), limit_for(3) - 3);
+    }
+
+    #[tokio::test]
+    async fn test_all_keys_lfu_evicts_least_frequently_used() {
+        let db = limited(limit_for(3), EvictionPolicy::AllKeysLfu);
+
+        for i in 1..=3 {
+            db.insert(key(i), b"hello".to_vec(), None).await.unwrap();
+        }
+        data(&db, key(1)).await.unwrap();
+        data(&db, key(3)).await.unwrap();
+
+        db.set(key(3), b"hello world".to_vec(), None).await.unwrap();
+
+        assert_eq!(data(&db, key(2)).await, None);
+        assert_eq!(data(&db, key(3)).await, Some(b"hello world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_volatile_ttl_evicts_soonest_expiring() {
+        let db = limited(limit_for(3), EvictionPolicy::VolatileTtl);
+
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(2), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        db.insert(key(3), b"hello".to_vec(), Some(Duration::from_secs(30))).await.unwrap();
+
+        db.insert(key(4), b"hello".to_vec(), None).await.unwrap();
+        assert_eq!(data(&db, key(3)).await, None);
+
+        db.insert(key(5), b"hello".to_vec(), None).await.unwrap();
+        assert_eq!(data(&db, key(2)).await, None);
+
+        // only entries with an expiry are evicted
+        assert_eq!(db.insert(key(6), b"hello".to_vec(), None).await.unwrap_err(), OutOfMemory);
+        assert!(expiring(&db).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_random_eviction() {
+        // entries are evicted from other shards if the shard of the new entry is empty
+        let db = Repository { max_memory: limit_for(3), eviction_policy: EvictionPolicy::Random, ..Repository::new() };
+
+        for i in 1..=10 {
+            db.insert(key(i), b"hello".to_vec(), None).await.unwrap();
+        }
+
+        assert_eq!(keys(&db).await.len(), 3);
+        assert!(data(&db, key(10)).await.is_some());
+
+        // the entry itself is never evicted, even if it is the only one
+        assert_eq!(db.insert(b"too large".to_vec(), vec![0; limit_for(3) as usize], None).await.unwrap_err(), OutOfMemory);
+    }
+
+    #[tokio::test]
+    async fn test_evictions_survive_restart() {
+        let path = temp_path("repository-evictions-survive-restart.aof");
+        let config = Config {
+            maxmemory: limit_for(3),
+            maxmemory_policy: EvictionPolicy::Random,
+            ..aof_config(&path)
+        };
+
+        let db = Repository::open(&config).unwrap();
+        for i in 1..=10 {
+            db.insert(key(i), b"hello".to_vec(), None).await.unwrap();
+        }
+        let keys_before = keys(&db).await;
+        drop(db);
+
+        let db = Repository::open(&config).unwrap();
+        assert_eq!(keys_before, keys(&db).await);
+        assert_eq!(db.used_memory.load(Ordering::Relaxed), limit_for(3) - 3);
+    }
+
+    #[tokio::test]
+    async fn test_shards_are_locked_independently() {
+        let db = Repository::new();
+        let other = (2..).map(key).find(|other| db.shard_index(other) != db.shard_index(&key(1))).unwrap();
+
+        let _blocked = db.shard(&key(1)).write().await;
+
+        let insert = db.insert(other.clone(), b"hello".to_vec(), None);
+        tokio::time::timeout(Duration::from_secs(1), insert).await.unwrap().unwrap();
+        assert_eq!(data(&db, other).await, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_insert_auto() {
+        let db = Arc::new(Repository::new());
+        db.insert(key(3), b"taken".to_vec(), None).await.unwrap();
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    let mut ids = Vec::new();
+                    for _ in 0..50 {
+                        ids.push(db.insert_auto(b"hello".to_vec()).await.unwrap());
+                    }
+                    ids
+                })
+            })
+            .collect();
+
+        let mut ids = Vec::new();
+        for task in tasks {
+            ids.extend(task.await.unwrap());
+        }
+        ids.sort();
+
+        // every id is assigned once and the id inserted before is skipped
+        let expected: Vec<u32> = (0..401).filter(|id| *id != 3).collect();
+        assert_eq!(ids, expected);
+        assert_eq!(keys(&db).await.len(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_set_wait_times_out() {
+        let db = Repository::new();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+
+        let shard_guard = db.shard(&key(1)).read().await;
+        let _entry_guard = shard_guard.get(&key(1)).unwrap().read().await;
+
+        assert_eq!(db.set(key(1), b"updated hello".to_vec(), None).await.unwrap_err(), WriteBlocked(key(1)));
+        assert_eq!(db.set_wait(key(1), b"updated hello".to_vec(), Duration::from_millis(20)).await.unwrap_err(), Timeout(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_set_wait_times_out_on_shard() {
+        let db = Repository::new();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+
+        let _shard_guard = db.shard(&key(1)).write().await;
+
+        let set = db.set_wait(key(1), b"updated hello".to_vec(), Duration::from_millis(20));
+        let result = tokio::time::timeout(Duration::from_secs(1), set).await.expect("set_wait waited for the shard without a limit");
+        assert_eq!(result.unwrap_err(), Timeout(key(1)));
+    }
+
+    #[tokio::test]
+    async fn test_set_wait_times_out_on_shard_while_evicting() {
+        let db = limited(limit_for(2), EvictionPolicy::AllKeysLru);
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        db.insert(key(2), b"hello".to_vec(), None).await.unwrap();
+
+        // the set needs more memory, so it has to write lock the shard to evict
+        let _shard_guard = db.shard(&key(1)).read().await;
+
+        let set = db.set_wait(key(1), b"updated hello".to_vec(), Duration::from_millis(20));
+        let result = tokio::time::timeout(Duration::from_secs(1), set).await.expect("set_wait waited for the shard without a limit");
+        assert_eq!(result.unwrap_err(), Timeout(key(1)));
+        assert_eq!(data(&db, key(1)).await, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_set_wait_waits_for_entry() {
+        let db = Arc::new(Repository::new());
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+
+        let (locked_tx, locked_rx) = tokio::sync::oneshot::channel();
+        let reader = {
+            let db = db.clone();
+            tokio::spawn(async move {
+                let shard_guard = db.shard(&key(1)).read().await;
+                let _entry_guard = shard_guard.get(&key(1)).unwrap().read().await;
+                locked_tx.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            })
+        };
+        locked_rx.await.unwrap();
+
+        db.set_wait(key(1), b"updated hello".to_vec(), Duration::from_secs(5)).await.unwrap();
+        reader.await.unwrap();
+
+        assert_eq!(data(&db, key(1)).await, Some(b"updated hello".to_vec()));
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_versions_increase() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        let (_, inserted) = db.get(key(1)).await.unwrap();
+
+        db.set(key(1), b"updated hello".to_vec(), None).await.unwrap();
+        let (_, updated) = db.get(key(1)).await.unwrap();
+        assert!(updated > inserted);
+
+        // a new entry with the same key never gets an old version
+        db.remove(key(1)).await.unwrap();
+        db.insert(key(1), b"hello".to_vec(), None).await.unwrap();
+        let (_, reinserted) = db.get(key(1)).await.unwrap();
+        assert!(reinserted > updated);
+
+        db.expire(key(1), Duration::from_secs(60)).await.unwrap();
+        assert!(db.version(key(1)).await.unwrap() > reinserted);
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_set() {
+        let db = Repository::new();
+
+        db.insert(key(1), b"hello".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        let (_, version) = db.get(key(1)).await.unwrap();
+
+        let new_version = db.compare_and_set(key(1), version, b"updated hello".to_vec()).await.unwrap();
+        assert_eq!(db.get(key(1)).await, Some((b"updated hello".to_vec(), new_version)));
+        assert_eq!(db.ttl(key(1)).await.unwrap(), None);
+
+        // the read version is outdated now
+        let err = db.compare_and_set(key(1), version, b"lost update".to_vec()).await.unwrap_err();
+        assert_eq!(err, VersionMismatch(new_version));
+        assert_eq!(data(&db, key(1)).await, Some(b"updated hello".to_vec()));
+
+        assert_eq!(db.compare_and_set(key(2), version, b"hello".to_vec()).await.unwrap_err(), NotFound(key(2)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_compare_and_set_read_modify_write() {
+        let db = Arc::new(Repository::new());
+        db.insert(key(1), 0u64.to_be_bytes().to_vec(), None).await.unwrap();
+
+        // every task increments the counter, retrying with the current version on a mismatch
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        loop {
+                            let (data, version) = db.get(key(1)).await.unwrap();
+                            let counter = u64::from_be_bytes(data.try_into().unwrap()) + 1;
+                            match db.compare_and_set(key(1), version, counter.to_be_bytes().to_vec()).await {
+                                Ok(_) => break,
+                                Err(VersionMismatch(_)) => continue,
+                                Err(err) => panic!("{}", err),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(data(&db, key(1)).await, Some(200u64.to_be_bytes().to_vec()));
+    }
+
+    /// Inserts and removes entries with different keys from one task per worker thread at the same time
+    /// and prints the operations per second with 1 and 16 shards for 1, 2, 4, ... threads up to twice the number of cores (at least 8).
+    /// Run it with `cargo test --release benchmark_shards -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_shards() {
+        const OPERATIONS: u32 = 100_000;
+
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        let threads: Vec<usize> = std::iter::successors(Some(1), |threads| Some(threads * 2))
+            .take_while(|threads| *threads <= (2 * cores).max(8))
+            .collect();
+
+        println!("{} cores, {} inserts and removes per thread", cores, OPERATIONS);
+        println!("threads | 1 shard ops/s | 16 shards ops/s");
+        for threads in threads {
+            let results: Vec<f64> = [1, 16].into_iter()
+                .map(|shards| {
+                    let runtime = tokio::runtime::Builder::new_multi_thread()
+                        .worker_threads(threads)
+                        .build()
+                        .unwrap();
+
+                    runtime.block_on(async {
+                        let db = Arc::new(Repository::with_shards(shards));
+                        let start = std::time::Instant::now();
+
+                        let tasks: Vec<_> = (0..threads as u32)
+                            .map(|task| {
+                                let db = db.clone();
+                                tokio::spawn(async move {
+                                    for id in 0..OPERATIONS {
+                                        let key = [task.to_be_bytes(), id.to_be_bytes()].concat();
+                                        db.insert(key.clone(), b"hello".to_vec(), None).await.unwrap();
+                                        db.remove(key).await.unwrap();
+                                    }
+                                })
+                            })
+                            .collect();
+                        for task in tasks {
+                            task.await.unwrap();
+                        }
+
+                        (2 * OPERATIONS as usize * threads) as f64 / start.elapsed().as_secs_f64()
+                    })
+                })
+                .collect();
+
+            println!("{:>7} | {:>13.0} | {:>15.0}", threads, results[0], results[1]);
+        }
+    }
+}
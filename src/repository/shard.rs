@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use crate::repository::entry::Entry;
+use crate::repository::Key;
+
+/// The entries of a part of the keyspace
+///
+/// Besides the map, the keys are kept in a vector, so the eviction can pick entries at random
+/// positions without walking the map. Every entry knows the position of its key in the vector.
+#[derive(Debug, Default)]
+pub(crate) struct Shard {
+    entries: HashMap<Key, (RwLock<Entry>, usize)>,
+    keys: Vec<Key>,
+}
+
+impl Shard {
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&RwLock<Entry>> {
+        self.entries.get(key).map(|(rw_lock, _)| rw_lock)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &[u8]) -> Option<&mut RwLock<Entry>> {
+        self.entries.get_mut(key).map(|(rw_lock, _)| rw_lock)
+    }
+
+    pub(crate) fn contains_key(&self, key: &[u8]) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// The key and entry at the position, every entry has a position below len
+    pub(crate) fn get_index(&self, index: usize) -> Option<(&Key, &RwLock<Entry>)> {
+        let key = self.keys.get(index)?;
+        self.get(key).map(|rw_lock| (key, rw_lock))
+    }
+
+    /// Inserts or replaces the entry, returns the replaced entry
+    pub(crate) fn insert(&mut self, key: Key, rw_lock: RwLock<Entry>) -> Option<RwLock<Entry>> {
+        if let Some((old, _)) = self.entries.get_mut(&key) {
+            return Some(std::mem::replace(old, rw_lock));
+        }
+
+        self.entries.insert(key.clone(), (rw_lock, self.keys.len()));
+        self.keys.push(key);
+        None
+    }
+
+    /// Removes the entry, the last key takes the position of its key
+    pub(crate) fn remove(&mut self, key: &[u8]) -> Option<RwLock<Entry>> {
+        let (rw_lock, index) = self.entries.remove(key)?;
+
+        self.keys.swap_remove(index);
+        if let Some(moved) = self.keys.get(index) {
+            self.entries.get_mut(moved).expect("every key has an entry").1 = index;
+        }
+
+        Some(rw_lock)
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&Key, &mut RwLock<Entry>)> {
+        self.entries.iter_mut().map(|(key, (rw_lock, _))| (key, rw_lock))
+    }
+
+    /// Keeps only the entries the predicate returns true for
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&Key, &mut RwLock<Entry>) -> bool) {
+        let removed: Vec<Key> = self.entries.iter_mut()
+            .filter_map(|(key, (rw_lock, _))| (!keep(key, rw_lock)).then(|| key.clone()))
+            .collect();
+
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(data: &[u8]) -> RwLock<Entry> {
+        RwLock::new(Entry::new(data.to_vec(), None, 1))
+    }
+
+    /// Every key is at the position its entry knows
+    fn assert_consistent(shard: &Shard) {
+        assert_eq!(shard.entries.len(), shard.keys.len());
+        for (index, key) in shard.keys.iter().enumerate() {
+            assert_eq!(shard.entries[key].1, index);
+        }
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut shard = Shard::default();
+
+        for key in [b"a", b"b", b"c"] {
+            assert!(shard.insert(key.to_vec(), entry(b"hello")).is_none());
+        }
+        assert!(shard.insert(b"b".to_vec(), entry(b"world")).is_some());
+        assert_eq!(shard.len(), 3);
+        assert_consistent(&shard);
+
+        assert!(shard.remove(b"a").is_some());
+        assert!(shard.remove(b"a").is_none());
+        assert_consistent(&shard);
+
+        assert_eq!(shard.get(b"b").unwrap().try_read().unwrap().data, b"world");
+        assert!(!shard.contains_key(b"a"));
+        assert!(shard.get_index(2).is_none());
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut shard = Shard::default();
+        for id in 0u32..10 {
+            shard.insert(id.to_be_bytes().to_vec(), entry(b"hello"));
+        }
+
+        shard.retain(|key, _| key[3] % 2 == 0);
+
+        assert_eq!(shard.len(), 5);
+        assert_consistent(&shard);
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use tokio::sync::{RwLock, RwLockWriteGuard};
+use crate::persistence::{Operation, Record};
+use crate::repository::entry::{memory_usage, now, Entry};
+use crate::repository::error::DatabaseError;
+use crate::repository::error::DatabaseError::{AlreadyExists, NotFound, OutOfMemory, TransactionFailed, WatchedEntryChanged};
+use crate::repository::shard::Shard;
+use crate::repository::{Key, Repository};
+
+/// An operation queued by a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TransactionOperation {
+    Get(Key),
+    // removes the expiry of the entry like SET
+    Set(Key, Vec<u8>),
+    Insert(Key, Vec<u8>),
+    Remove(Key),
+}
+
+impl TransactionOperation {
+    pub(crate) fn key(&self) -> &Key {
+        match self {
+            TransactionOperation::Get(key)
+            | TransactionOperation::Set(key, _)
+            | TransactionOperation::Insert(key, _)
+            | TransactionOperation::Remove(key) => key,
+        }
+    }
+}
+
+/// The outcome of an operation of an executed transaction
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TransactionOutcome {
+    // data and version of the entry read by GET
+    Found(Vec<u8>, u64),
+    // a GET of a missing entry doesn't abort the transaction
+    NotFound,
+    Done,
+}
+
+impl Repository {
+    /// Executes the operations while holding the write locks of all shards they use,
+    /// so nobody sees the state between two operations.
+    ///
+    /// The watched entries are checked first, an entry that didn't exist when it was watched counts as unchanged
+    /// as long as it doesn't exist.
+    /// All operations are checked before anything is changed, the changes are logged as a single
+    /// TRANSACTION record, so they are replayed all or nothing as well.
+    /// Transactions don't evict entries, OutOfMemory is returned if the changes don't fit into the memory limit.
+    pub(super) async fn execute_transaction(
+        &self,
+        watched: Vec<(Key, Option<u64>)>,
+        operations: Vec<TransactionOperation>,
+    ) -> Result<Vec<TransactionOutcome>, DatabaseError> {
+        // the shards are locked in the order of their index, like everyone else who locks multiple shards
+        let mut indices: Vec<usize> = watched.iter().map(|(key, _)| key)
+            .chain(operations.iter().map(TransactionOperation::key))
+            .map(|key| self.shard_index(key))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let mut shard_guards: HashMap<usize, RwLockWriteGuard<Shard>> = HashMap::with_capacity(indices.len());
+        for index in indices {
+            shard_guards.insert(index, self.shards[index].write().await);
+        }
+
+        let now = now();
+
+        for (key, version) in watched {
+            let current = shard_guards.get_mut(&self.shard_index(&key))
+                .expect("the shards of all watched keys are locked")
+                .get_mut(&key)
+                .map(RwLock::get_mut)
+                .filter(|entry| !entry.is_expired(now))
+                .map(|entry| entry.version);
+
+            if current != version {
+                return Err(WatchedEntryChanged(key));
+            }
+        }
+
+        // data length of the entries as left by the operations checked so far, None if there is no entry
+        let mut staged: HashMap<&[u8], Option<usize>> = HashMap::new();
+        let mut additional: i64 = 0;
+
+        for (i, operation) in operations.iter().enumerate() {
+            let key = operation.key();
+            let current = match staged.get(key.as_slice()) {
+                Some(length) => *length,
+                None => shard_guards.get_mut(&self.shard_index(key))
+                    .expect("the shards of all operations are locked")
+                    .get_mut(key)
+                    .map(RwLock::get_mut)
+                    .filter(|entry| !entry.is_expired(now))
+                    .map(|entry| entry.data.len()),
+            };
+
+            let failed = |err| TransactionFailed(i, Box::new(err));
+
+            match (operation, current) {
+                (TransactionOperation::Get(_), _) => {}
+                (TransactionOperation::Set(_, data), Some(length)) => {
+                    additional += data.len() as i64 - length as i64;
+                    staged.insert(key, Some(data.len()));
+                }
+                (TransactionOperation::Insert(_, data), None) => {
+                    additional += memory_usage(key, data) as i64;
+                    staged.insert(key, Some(data.len()));
+                }
+                (TransactionOperation::Remove(_), Some(length)) => {
+                    additional -= (memory_usage(key, &[]) + length) as i64;
+                    staged.insert(key, None);
+                }
+                (TransactionOperation::Insert(..), Some(_)) => return Err(failed(AlreadyExists(key.clone()))),
+                (TransactionOperation::Set(..) | TransactionOperation::Remove(_), None) => return Err(failed(NotFound(key.clone()))),
+            }
+        }
+
+        if additional > 0 && self.exceeds_memory_limit(additional as usize) {
+            return Err(OutOfMemory);
+        }
+
+        let records: Vec<Record> = operations.iter()
+            .filter_map(|operation| match operation {
+                TransactionOperation::Get(_) => None,
+                TransactionOperation::Set(key, data) => Some(Record { operation: Operation::Set, key: key.clone(), expires_at: None, data: data.clone() }),
+                TransactionOperation::Insert(key, data) => Some(Record { operation: Operation::Insert, key: key.clone(), expires_at: None, data: data.clone() }),
+                TransactionOperation::Remove(key) => Some(Record { operation: Operation::Remove, key: key.clone(), expires_at: None, data: vec![] }),
+            })
+            .collect();
+
+        if !records.is_empty() {
+            let transaction = Record::transaction(&records)
+                .map_err(|e| DatabaseError::Persistence(format!("{:#}", e)))?;
+            self.log(&transaction)?;
+        }
+
+        let mut outcomes = Vec::with_capacity(operations.len());
+        for operation in operations {
+            let shard = shard_guards.get_mut(&self.shard_index(operation.key()))
+                .expect("the shards of all operations are locked");
+
+            let outcome = match operation {
+                TransactionOperation::Get(key) => match shard.get_mut(&key).map(RwLock::get_mut) {
+                    Some(entry) if !entry.is_expired(now) => {
+                        entry.touch(now);
+                        TransactionOutcome::Found(entry.data.clone(), entry.version)
+                    }
+                    _ => TransactionOutcome::NotFound,
+                },
+                TransactionOperation::Set(key, data) => {
+                    let entry = shard.get_mut(&key)
+                        .expect("the entry was checked before")
+                        .get_mut();
+                    self.apply_replace(&key, entry, data, None);
+                    TransactionOutcome::Done
+                }
+                TransactionOperation::Insert(key, data) => {
+                    // an expired entry is replaced
+                    if let Some(old) = shard.remove(&key) {
+                        self.forget_entry(&key, old.into_inner());
+                    }
+                    self.used_memory.fetch_add(memory_usage(&key, &data) as u64, Ordering::Relaxed);
+                    shard.insert(key, RwLock::new(Entry::new(data, None, self.next_version())));
+                    TransactionOutcome::Done
+                }
+                TransactionOperation::Remove(key) => {
+                    if let Some(old) = shard.remove(&key) {
+                        self.forget_entry(&key, old.into_inner());
+                    }
+                    TransactionOutcome::Done
+                }
+            };
+
+            outcomes.push(outcome);
+        }
+
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::config::Config;
+    use crate::repository::eviction::EvictionPolicy;
+    use crate::persistence::aof::FsyncPolicy;
+    use crate::persistence::temp_path;
+    use crate::repository::RepositoryApi;
+
+    fn aof_config(path: &std::path::Path) -> Config {
+        Config {
+            appendonly: true,
+            appendfilename: path.to_path_buf(),
+            appendfsync: FsyncPolicy::Always,
+            ..Config::default()
+        }
+    }
+
+    fn rename(from: &[u8], to: &[u8], data: &[u8]) -> Vec<TransactionOperation> {
+        vec![
+            TransactionOperation::Insert(to.to_vec(), data.to_vec()),
+            TransactionOperation::Remove(from.to_vec()),
+        ]
+    }
+
+    async fn data(db: &Repository, key: &[u8]) -> Option<Vec<u8>> {
+        db.get(key.to_vec()).await.map(|(data, _)| data)
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn test_execute() {
+        let db = Repository::new();
+        db.insert(b"session:old".to_vec(), b"hello".to_vec(), None).await.unwrap();
+
+        let mut operations = vec![TransactionOperation::Get(b"session:old".to_vec())];
+        operations.extend(rename(b"session:old", b"session:new", b"hello"));
+        operations.push(TransactionOperation::Get(b"session:old".to_vec()));
+
+        let outcomes = db.execute(vec![], operations).await.unwrap();
+
+        let (_, version) = db.get(b"session:new".to_vec()).await.unwrap();
+        assert!(matches!(&outcomes[0], TransactionOutcome::Found(data, _) if data == b"hello"));
+        assert_eq!(outcomes[1..], [TransactionOutcome::Done, TransactionOutcome::Done, TransactionOutcome::NotFound]);
+        assert!(version > 0);
+        assert_eq!(data(&db, b"session:new").await, Some(b"hello".to_vec()));
+        assert_eq!(data(&db, b"session:old").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_execute_sees_earlier_operations() {
+        let db = Repository::new();
+
+        let operations = vec![
+            TransactionOperation::Insert(b"key".to_vec(), b"hello".to_vec()),
+            TransactionOperation::Set(b"key".to_vec(), b"world".to_vec()),
+            TransactionOperation::Get(b"key".to_vec()),
+            TransactionOperation::Remove(b"key".to_vec()),
+            TransactionOperation::Insert(b"key".to_vec(), b"again".to_vec()),
+        ];
+
+        let outcomes = db.execute(vec![], operations).await.unwrap();
+
+        assert!(matches!(&outcomes[2], TransactionOutcome::Found(data, _) if data == b"world"));
+        assert_eq!(data(&db, b"key").await, Some(b"again".to_vec()));
+        assert_eq!(db.memory_usage(b"key".to_vec()).await.unwrap(), (memory_usage(b"key", b"again") as u64, memory_usage(b"key", b"again") as u64));
+    }
+
+    #[tokio::test]
+    async fn test_execute_failed_changes_nothing() {
+        let db = Repository::new();
+        db.insert(b"session:old".to_vec(), b"hello".to_vec(), None).await.unwrap();
+        db.insert(b"session:new".to_vec(), b"taken".to_vec(), None).await.unwrap();
+
+        let mut operations = vec![TransactionOperation::Set(b"session:old".to_vec(), b"changed".to_vec())];
+        operations.extend(rename(b"session:old", b"session:new", b"hello"));
+
+        let err = db.execute(vec![], operations).await.unwrap_err();
+
+        assert_eq!(err, TransactionFailed(1, Box::new(AlreadyExists(b"session:new".to_vec()))));
+        assert_eq!(data(&db, b"session:old").await, Some(b"hello".to_vec()));
+        assert_eq!(data(&db, b"session:new").await, Some(b"taken".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_execute_not_found() {
+        let db = Repository::new();
+        db.insert(b"expired".to_vec(), b"hello".to_vec(), Some(Duration::ZERO)).await.unwrap();
+
+        let err = db.execute(vec![], vec![TransactionOperation::Remove(b"expired".to_vec())]).await.unwrap_err();
+        assert_eq!(err, TransactionFailed(0, Box::new(NotFound(b"expired".to_vec()))));
+
+        let err = db.execute(vec![], vec![TransactionOperation::Set(b"missing".to_vec(), b"hello".to_vec())]).await.unwrap_err();
+        assert_eq!(err, TransactionFailed(0, Box::new(NotFound(b"missing".to_vec()))));
+    }
+
+    #[tokio::test]
+    async fn test_execute_out_of_memory() {
+        let db = Repository::open(&Config {
+            maxmemory: memory_usage(b"key", b"hello") as u64,
+            maxmemory_policy: EvictionPolicy::AllKeysLru,
+            ..Config::default()
+        }).unwrap();
+        db.insert(b"key".to_vec(), b"hello".to_vec(), None).await.unwrap();
+
+        let err = db.execute(vec![], vec![TransactionOperation::Insert(b"other".to_vec(), b"hello".to_vec())]).await.unwrap_err();
+        assert_eq!(err, OutOfMemory);
+
+        // the memory freed by earlier operations is taken into account
+        let outcomes = db.execute(vec![], rename(b"key", b"new", b"hi")).await.unwrap();
+        assert_eq!(outcomes, [TransactionOutcome::Done, TransactionOutcome::Done]);
+        assert_eq!(data(&db, b"new").await, Some(b"hi".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_execute_watched() {
+        let db = Repository::new();
+        db.insert(b"session:old".to_vec(), b"hello".to_vec(), None).await.unwrap();
+
+        let watched = vec![
+            (b"session:old".to_vec(), db.version(b"session:old".to_vec()).await),
+            (b"session:new".to_vec(), db.version(b"session:new".to_vec()).await),
+        ];
+
+        let outcomes = db.execute(watched, rename(b"session:old", b"session:new", b"hello")).await.unwrap();
+        assert_eq!(outcomes, [TransactionOutcome::Done, TransactionOutcome::Done]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_watched_changed() {
+        let db = Repository::new();
+        db.insert(b"session:old".to_vec(), b"hello".to_vec(), None).await.unwrap();
+
+        // changed data
+        let watched = vec![(b"session:old".to_vec(), db.version(b"session:old".to_vec()).await)];
+        db.set(b"session:old".to_vec(), b"changed".to_vec(), None).await.unwrap();
+        let err = db.execute(watched, rename(b"session:old", b"session:new", b"changed")).await.unwrap_err();
+        assert_eq!(err, WatchedEntryChanged(b"session:old".to_vec()));
+
+        // changed expiry
+        let watched = vec![(b"session:old".to_vec(), db.version(b"session:old".to_vec()).await)];
+        db.expire(b"session:old".to_vec(), Duration::from_secs(60)).await.unwrap();
+        let err = db.execute(watched, vec![]).await.unwrap_err();
+        assert_eq!(err, WatchedEntryChanged(b"session:old".to_vec()));
+
+        // created entry
+        let watched = vec![(b"session:new".to_vec(), db.version(b"session:new".to_vec()).await)];
+        db.insert(b"session:new".to_vec(), b"taken".to_vec(), None).await.unwrap();
+        let err = db.execute(watched, rename(b"session:old", b"session:new", b"changed")).await.unwrap_err();
+        assert_eq!(err, WatchedEntryChanged(b"session:new".to_vec()));
+
+        assert_eq!(data(&db, b"session:old").await, Some(b"changed".to_vec()));
+        assert_eq!(data(&db, b"session:new").await, Some(b"taken".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_execute_replays_aof() {
+        let config = aof_config(&temp_path("transaction-replays-aof.aof"));
+
+        {
+            let db = Repository::open(&config).unwrap();
+            db.insert(b"session:old".to_vec(), b"hello".to_vec(), None).await.unwrap();
+            db.execute(vec![], rename(b"session:old", b"session:new", b"hello")).await.unwrap();
+            // a transaction of only reads isn't logged
+            db.execute(vec![], vec![TransactionOperation::Get(b"session:new".to_vec())]).await.unwrap();
+        }
+
+        let db = Repository::open(&config).unwrap();
+        assert_eq!(data(&db, b"session:new").await, Some(b"hello".to_vec()));
+        assert_eq!(data(&db, b"session:old").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_replay_skips_torn_transaction() {
+        let config = aof_config(&temp_path("transaction-skips-torn.aof"));
+
+        let records = [
+            Record { operation: Operation::Insert, key: b"first".to_vec(), expires_at: None, data: b"hello".to_vec() },
+            Record { operation: Operation::Insert, key: b"second".to_vec(), expires_at: None, data: b"hello".to_vec() },
+        ];
+        let mut transaction = Record::transaction(&records).unwrap();
+        transaction.data.truncate(transaction.data.len() - 1);
+        std::fs::write(&config.appendfilename, transaction.encode().unwrap()).unwrap();
+
+        let db = Repository::open(&config).unwrap();
+        assert_eq!(data(&db, b"first").await, None);
+        assert_eq!(data(&db, b"second").await, None);
+    }
+}
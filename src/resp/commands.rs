@@ -0,0 +1,837 @@
+use std::time::Duration;
+use crate::repository::error::DatabaseError;
+use crate::repository::integer::IntegerEncoding;
+use crate::repository::{Key, SharedRepository, MAX_DATA_LENGTH};
+use crate::resp::value::Value;
+
+/// longest key of an entry, like the u16 key length of the binary protocol and the append only file
+const MAX_KEY_LENGTH: usize = u16::MAX as usize;
+
+/// The state of a RESP connection
+#[derive(Debug)]
+pub(super) struct Session {
+    // RESP version of the replies, switched with HELLO
+    pub(super) protocol: u8,
+    // QUIT was sent, the connection is closed after the reply
+    pub(super) quit: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            protocol: 2,
+            quit: false,
+        }
+    }
+}
+
+/// Executes the command (the first argument, case insensitive) and returns its reply.
+///
+/// The commands work on the same entries as the binary protocol, the data is used as is (RESP strings are binary safe)
+/// and integers are stored as decimal text (IntegerEncoding::Ascii), like Redis does.
+///
+/// Connection: PING, ECHO, HELLO, QUIT, SELECT (only 0), CLIENT SETNAME / SETINFO, COMMAND (no documentation), CONFIG GET (no settings)
+/// Strings: GET, SET [EX seconds | PX milliseconds] [NX | XX], SETNX, SETEX, PSETEX, MGET, MSET (not atomic), STRLEN,
+///     APPEND, GETRANGE, SETRANGE, INCR, DECR, INCRBY, DECRBY
+/// Keys: DEL, UNLINK, EXISTS, EXPIRE, PEXPIRE, TTL, PTTL, PERSIST, MEMORY USAGE
+/// Persistence: SAVE, BGSAVE, BGREWRITEAOF
+pub(super) async fn execute_command(mut arguments: Vec<Vec<u8>>, db: SharedRepository, session: &mut Session) -> Value {
+    if arguments.is_empty() {
+        return Value::error("empty command");
+    }
+    let arguments_after_name = arguments.split_off(1);
+    let name = String::from_utf8_lossy(&arguments[0]).to_ascii_lowercase();
+
+    // RESP strings can be longer than the keys of the entries
+    if keys(&name, &arguments_after_name).any(|key| key.len() > MAX_KEY_LENGTH) {
+        return Value::error("key too long");
+    }
+
+    match name.as_str() {
+        "ping" => ping(&name, arguments_after_name),
+        "echo" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            Ok([message]) => Value::BulkString(message),
+            Err(_) => wrong_arguments(&name),
+        },
+        "hello" => hello(arguments_after_name, session),
+        "quit" => {
+            session.quit = true;
+            Value::ok()
+        }
+        "select" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            Ok([index]) => match parse_integer(&index) {
+                Some(0) => Value::ok(),
+                Some(_) => Value::error("DB index is out of range"),
+                None => not_an_integer(),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "client" => match arguments_after_name.first().map(|subcommand| subcommand.to_ascii_lowercase()) {
+            Some(subcommand) if subcommand == b"setname" || subcommand == b"setinfo" => Value::ok(),
+            Some(_) => Value::error("unknown subcommand"),
+            None => wrong_arguments(&name),
+        },
+        // there is no command documentation, clients fall back to their own
+        "command" => Value::Array(vec![]),
+        "config" => match arguments_after_name.first().map(|subcommand| subcommand.to_ascii_lowercase()) {
+            Some(subcommand) if subcommand == b"get" => Value::Map(vec![]),
+            Some(_) => Value::error("unknown subcommand"),
+            None => wrong_arguments(&name),
+        },
+        "get" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            Ok([key]) => match db.get(key).await {
+                Some((data, _)) => Value::BulkString(data),
+                None => Value::Null,
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "set" => set(&name, arguments_after_name, db).await,
+        "setnx" => match <[Vec<u8>; 2]>::try_from(arguments_after_name) {
+            Ok([key, data]) => match db.insert(key, data, None).await {
+                Ok(()) => Value::Integer(1),
+                Err(DatabaseError::AlreadyExists(_)) => Value::Integer(0),
+                Err(err) => database_error(err),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "setex" | "psetex" => match <[Vec<u8>; 3]>::try_from(arguments_after_name) {
+            Ok([key, ttl, data]) => match parse_integer(&ttl) {
+                Some(ttl) if ttl > 0 => {
+                    let ttl = if name == "setex" { Duration::from_secs(ttl as u64) } else { Duration::from_millis(ttl as u64) };
+                    match db.upsert(key, data, Some(ttl)).await {
+                        Ok(_) => Value::ok(),
+                        Err(err) => database_error(err),
+                    }
+                }
+                Some(_) => Value::error(format!("invalid expire time in '{}' command", name)),
+                None => not_an_integer(),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "mget" if !arguments_after_name.is_empty() => {
+            let mut values = Vec::with_capacity(arguments_after_name.len());
+            for key in arguments_after_name {
+                values.push(match db.get(key).await {
+                    Some((data, _)) => Value::BulkString(data),
+                    None => Value::Null,
+                });
+            }
+            Value::Array(values)
+        }
+        "mset" if !arguments_after_name.is_empty() && arguments_after_name.len().is_multiple_of(2) => {
+            let mut arguments = arguments_after_name.into_iter();
+            while let (Some(key), Some(data)) = (arguments.next(), arguments.next()) {
+                if let Err(err) = db.upsert(key, data, None).await {
+                    return database_error(err);
+                }
+            }
+            Value::ok()
+        }
+        "strlen" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            Ok([key]) => Value::Integer(db.length(key).await.unwrap_or(0) as i64),
+            Err(_) => wrong_arguments(&name),
+        },
+        "append" => match <[Vec<u8>; 2]>::try_from(arguments_after_name) {
+            Ok([key, data]) => match db.append(key, data).await {
+                Ok(length) => Value::Integer(length as i64),
+                Err(err) => database_error(err),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "getrange" | "substr" => match <[Vec<u8>; 3]>::try_from(arguments_after_name) {
+            Ok([key, start, end]) => match (parse_integer(&start), parse_integer(&end)) {
+                (Some(start), Some(end)) => get_range(key, start, end, db).await,
+                _ => not_an_integer(),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "setrange" => match <[Vec<u8>; 3]>::try_from(arguments_after_name) {
+            Ok([key, offset, data]) => set_range(key, offset, data, db).await,
+            Err(_) => wrong_arguments(&name),
+        },
+        "incr" | "decr" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            Ok([key]) => increment(key, if name == "incr" { 1 } else { -1 }, db).await,
+            Err(_) => wrong_arguments(&name),
+        },
+        "incrby" | "decrby" => match <[Vec<u8>; 2]>::try_from(arguments_after_name) {
+            Ok([key, delta]) => {
+                let delta = parse_integer(&delta)
+                    .and_then(|delta| if name == "incrby" { Some(delta) } else { delta.checked_neg() });
+                match delta {
+                    Some(delta) => increment(key, delta, db).await,
+                    None => not_an_integer(),
+                }
+            }
+            Err(_) => wrong_arguments(&name),
+        },
+        "del" | "unlink" if !arguments_after_name.is_empty() => {
+            let mut removed = 0;
+            for key in arguments_after_name {
+                match db.remove(key).await {
+                    Ok(()) => removed += 1,
+                    Err(DatabaseError::NotFound(_)) => {}
+                    Err(err) => return database_error(err),
+                }
+            }
+            Value::Integer(removed)
+        }
+        "exists" if !arguments_after_name.is_empty() => {
+            let mut existing = 0;
+            for key in arguments_after_name {
+                if db.version(key).await.is_some() {
+                    existing += 1;
+                }
+            }
+            Value::Integer(existing)
+        }
+        "expire" | "pexpire" => match <[Vec<u8>; 2]>::try_from(arguments_after_name) {
+            Ok([key, ttl]) => match parse_integer(&ttl) {
+                Some(ttl) => expire(key, ttl, name == "expire", db).await,
+                None => not_an_integer(),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "ttl" | "pttl" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            Ok([key]) => match db.ttl(key).await {
+                // rounded to the nearest second, like Redis
+                Ok(Some(ttl)) if name == "ttl" => Value::Integer(((ttl.as_millis() + 500) / 1000) as i64),
+                Ok(Some(ttl)) => Value::Integer(ttl.as_millis() as i64),
+                Ok(None) => Value::Integer(-1),
+                Err(DatabaseError::NotFound(_)) => Value::Integer(-2),
+                Err(err) => database_error(err),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "persist" => match <[Vec<u8>; 1]>::try_from(arguments_after_name) {
+            // only an entry with an expiry counts as changed
+            Ok([key]) => match db.ttl(key.clone()).await {
+                Ok(Some(_)) => match db.persist(key).await {
+                    Ok(()) => Value::Integer(1),
+                    Err(DatabaseError::NotFound(_)) => Value::Integer(0),
+                    Err(err) => database_error(err),
+                },
+                Ok(None) | Err(DatabaseError::NotFound(_)) => Value::Integer(0),
+                Err(err) => database_error(err),
+            },
+            Err(_) => wrong_arguments(&name),
+        },
+        "memory" => match <[Vec<u8>; 2]>::try_from(arguments_after_name) {
+            Ok([subcommand, key]) if subcommand.eq_ignore_ascii_case(b"usage") => match db.memory_usage(key).await {
+                Ok((bytes, _)) => Value::Integer(bytes as i64),
+                Err(DatabaseError::NotFound(_)) => Value::Null,
+                Err(err) => database_error(err),
+            },
+            _ => Value::error("unknown subcommand or wrong number of arguments for 'memory' command"),
+        },
+        "save" if arguments_after_name.is_empty() => match db.save_snapshot().await {
+            Ok(()) => Value::ok(),
+            Err(err) => database_error(err),
+        },
+        "bgsave" if arguments_after_name.is_empty() => match db.background_save_snapshot().await {
+            Ok(()) => Value::SimpleString("Background saving started".to_string()),
+            Err(err) => database_error(err),
+        },
+        // the reply is sent once the file is rewritten
+        "bgrewriteaof" if arguments_after_name.is_empty() => match db.rewrite_aof().await {
+            Ok(()) => Value::ok(),
+            Err(err) => database_error(err),
+        },
+        "mget" | "mset" | "del" | "unlink" | "exists" | "save" | "bgsave" | "bgrewriteaof" => wrong_arguments(&name),
+        _ => Value::error(format!("unknown command '{}'", String::from_utf8_lossy(&arguments[0]))),
+    }
+}
+
+/// The arguments of the command that are keys
+fn keys<'a>(name: &str, arguments: &'a [Vec<u8>]) -> Box<dyn Iterator<Item = &'a Vec<u8>> + 'a> {
+    match name {
+        "mget" | "del" | "unlink" | "exists" => Box::new(arguments.iter()),
+        "mset" => Box::new(arguments.iter().step_by(2)),
+        "memory" => Box::new(arguments.iter().skip(1).take(1)),
+        "get" | "set" | "setnx" | "setex" | "psetex" | "strlen" | "append" | "getrange" | "substr" | "setrange"
+        | "incr" | "decr" | "incrby" | "decrby" | "expire" | "pexpire" | "ttl" | "pttl" | "persist" => Box::new(arguments.iter().take(1)),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn ping(name: &str, arguments: Vec<Vec<u8>>) -> Value {
+    match <[Vec<u8>; 1]>::try_from(arguments) {
+        Ok([message]) => Value::BulkString(message),
+        Err(arguments) if arguments.is_empty() => Value::SimpleString("PONG".to_string()),
+        Err(_) => wrong_arguments(name),
+    }
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]
+///
+/// Switches the connection to RESP2 or RESP3 and replies with information about the server.
+/// There are no users, so AUTH is accepted with any credentials.
+fn hello(arguments: Vec<Vec<u8>>, session: &mut Session) -> Value {
+    let mut arguments = arguments.into_iter();
+
+    let protocol = match arguments.next() {
+        Some(protocol) => match parse_integer(&protocol) {
+            Some(protocol @ (2 | 3)) => protocol as u8,
+            Some(_) => return Value::Error("NOPROTO unsupported protocol version".to_string()),
+            None => return Value::error("Protocol version is not an integer or out of range"),
+        },
+        None => session.protocol,
+    };
+
+    while let Some(option) = arguments.next() {
+        let values = match option.to_ascii_lowercase().as_slice() {
+            b"auth" => 2,
+            b"setname" => 1,
+            _ => return Value::error("syntax error"),
+        };
+        if arguments.by_ref().take(values).count() < values {
+            return Value::error("syntax error");
+        }
+    }
+
+    session.protocol = protocol;
+
+    let text = |text: &str| Value::BulkString(text.as_bytes().to_vec());
+    Value::Map(vec![
+        (text("server"), text("redis-clone-rust")),
+        (text("version"), text(env!("CARGO_PKG_VERSION"))),
+        (text("proto"), Value::Integer(protocol as i64)),
+        (text("id"), Value::Integer(0)),
+        (text("mode"), text("standalone")),
+        (text("role"), text("master")),
+        (text("modules"), Value::Array(vec![])),
+    ])
+}
+
+/// SET key value [EX seconds | PX milliseconds] [NX | XX]
+///
+/// Without NX or XX the entry is inserted or replaced (UPSERT), NX only inserts it (INSERT) and XX only replaces it (SET).
+/// The reply is null if the entry wasn't set because of NX or XX.
+async fn set(name: &str, arguments: Vec<Vec<u8>>, db: SharedRepository) -> Value {
+    if arguments.len() < 2 {
+        return wrong_arguments(name);
+    }
+    let mut arguments = arguments.into_iter();
+    let (Some(key), Some(data)) = (arguments.next(), arguments.next()) else {
+        return wrong_arguments(name);
+    };
+
+    let mut ttl = None;
+    let mut only_insert = false;
+    let mut only_replace = false;
+
+    while let Some(option) = arguments.next() {
+        match option.to_ascii_lowercase().as_slice() {
+            b"nx" if !only_replace => only_insert = true,
+            b"xx" if !only_insert => only_replace = true,
+            unit @ (b"ex" | b"px") if ttl.is_none() => {
+                let Some(value) = arguments.next() else {
+                    return Value::error("syntax error");
+                };
+                ttl = match parse_integer(&value) {
+                    Some(value) if value > 0 && unit == b"ex" => Some(Duration::from_secs(value as u64)),
+                    Some(value) if value > 0 => Some(Duration::from_millis(value as u64)),
+                    Some(_) => return Value::error("invalid expire time in 'set' command"),
+                    None => return not_an_integer(),
+                };
+            }
+            _ => return Value::error("syntax error"),
+        }
+    }
+
+    let result = if only_insert {
+        db.insert(key, data, ttl).await
+    } else if only_replace {
+        db.set(key, data, ttl).await
+    } else {
+        db.upsert(key, data, ttl).await.map(|_| ())
+    };
+
+    match result {
+        Ok(()) => Value::ok(),
+        Err(DatabaseError::AlreadyExists(_)) if only_insert => Value::Null,
+        Err(DatabaseError::NotFound(_)) if only_replace => Value::Null,
+        Err(err) => database_error(err),
+    }
+}
+
+async fn set_range(key: Key, offset: Vec<u8>, data: Vec<u8>, db: SharedRepository) -> Value {
+    let offset = match parse_integer(&offset) {
+        Some(offset) if offset >= 0 => offset as u64,
+        Some(_) => return Value::error("offset is out of range"),
+        None => return not_an_integer(),
+    };
+
+    // nothing is written, so a missing entry isn't created
+    if data.is_empty() {
+        return Value::Integer(db.get(key).await.map_or(0, |(data, _)| data.len() as i64));
+    }
+
+    if offset.saturating_add(data.len() as u64) > MAX_DATA_LENGTH {
+        return Value::error("string exceeds maximum allowed size (proto-max-bulk-len)");
+    }
+
+    match db.set_range(key, offset, data).await {
+        Ok(length) => Value::Integer(length as i64),
+        Err(err) => database_error(err),
+    }
+}
+
+/// The bytes from start to end (both inclusive), negative positions count from the end of the data
+async fn get_range(key: Key, start: i64, end: i64, db: SharedRepository) -> Value {
+    // the length is only needed to resolve positions from the end of the data
+    let (start, end) = if start < 0 || end < 0 {
+        let Some(length) = db.length(key.clone()).await else {
+            return Value::BulkString(vec![]);
+        };
+        let length = length as i64;
+        let start = if start < 0 { (length + start).max(0) } else { start };
+        let end = if end < 0 { length + end } else { end };
+        (start, end)
+    } else {
+        (start, end)
+    };
+
+    if start > end || end < 0 {
+        return Value::BulkString(vec![]);
+    }
+
+    // get_range leaves out the bytes after the end of the data
+    let data = db.get_range(key, start as u64, (end - start) as u64 + 1).await;
+    Value::BulkString(data.unwrap_or_default())
+}
+
+async fn increment(key: Key, delta: i64, db: SharedRepository) -> Value {
+    match db.increment(key, delta, IntegerEncoding::Ascii).await {
+        Ok(value) => Value::Integer(value),
+        Err(err) => database_error(err),
+    }
+}
+
+/// A ttl of 0 or less removes the entry, like Redis
+async fn expire(key: Key, ttl: i64, seconds: bool, db: SharedRepository) -> Value {
+    let result = if ttl <= 0 {
+        db.remove(key).await
+    } else if seconds {
+        db.expire(key, Duration::from_secs(ttl as u64)).await
+    } else {
+        db.expire(key, Duration::from_millis(ttl as u64)).await
+    };
+
+    match result {
+        Ok(()) => Value::Integer(1),
+        Err(DatabaseError::NotFound(_)) => Value::Integer(0),
+        Err(err) => database_error(err),
+    }
+}
+
+fn parse_integer(argument: &[u8]) -> Option<i64> {
+    std::str::from_utf8(argument).ok()?.parse().ok()
+}
+
+fn wrong_arguments(name: &str) -> Value {
+    Value::error(format!("wrong number of arguments for '{}' command", name))
+}
+
+fn not_an_integer() -> Value {
+    Value::error("value is not an integer or out of range")
+}
+
+fn database_error(err: DatabaseError) -> Value {
+    match err {
+        DatabaseError::OutOfMemory => Value::Error("OOM command not allowed when used memory > 'maxmemory'".to_string()),
+        DatabaseError::NotAnInteger(_) => not_an_integer(),
+        DatabaseError::IntegerOverflow(_) => Value::error("increment or decrement would overflow"),
+        DatabaseError::DataTooLarge(_) => Value::error("string exceeds maximum allowed size (proto-max-bulk-len)"),
+        // like the 409 of the binary protocol, the client can retry
+        DatabaseError::WriteBlocked(_) => Value::Error("BUSY the entry is being written by another client, try again".to_string()),
+        err => Value::error(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::repository::MockRepository;
+
+    async fn execute(command: &str, mock: MockRepository) -> Value {
+        execute_with_session(command, mock, &mut Session::default()).await
+    }
+
+    async fn execute_with_session(command: &str, mock: MockRepository, session: &mut Session) -> Value {
+        let arguments = command.split(' ').map(|argument| argument.as_bytes().to_vec()).collect();
+        execute_command(arguments, Arc::new(mock), session).await
+    }
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn ping() {
+        assert_eq!(execute("PING", MockRepository::new()).await, Value::SimpleString("PONG".to_string()));
+        assert_eq!(execute("ping hello", MockRepository::new()).await, Value::BulkString(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn unknown_command() {
+        assert_eq!(execute("LPUSH list a", MockRepository::new()).await, Value::error("unknown command 'LPUSH'"));
+    }
+
+    #[tokio::test]
+    async fn unknown_command_with_line_break() {
+        let arguments = vec![b"GET\r\n+OK".to_vec()];
+        let reply = execute_command(arguments, Arc::new(MockRepository::new()), &mut Session::default()).await;
+
+        let mut output = Vec::new();
+        reply.encode(2, &mut output);
+
+        // a single error line, not an error followed by a fake +OK reply
+        assert_eq!(output, b"-ERR unknown command 'GET  +OK'\r\n");
+    }
+
+    #[tokio::test]
+    async fn wrong_number_of_arguments() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get().never();
+
+        assert_eq!(execute("GET a b", mock).await, Value::error("wrong number of arguments for 'get' command"));
+        assert_eq!(execute("DEL", MockRepository::new()).await, Value::error("wrong number of arguments for 'del' command"));
+        assert_eq!(execute("MSET a", MockRepository::new()).await, Value::error("wrong number of arguments for 'mset' command"));
+    }
+
+    #[tokio::test]
+    async fn hello_switches_protocol() {
+        let mut session = Session::default();
+
+        let reply = execute_with_session("HELLO 3 SETNAME app", MockRepository::new(), &mut session).await;
+
+        assert!(matches!(reply, Value::Map(_)));
+        assert_eq!(session.protocol, 3);
+    }
+
+    #[tokio::test]
+    async fn hello_unsupported_protocol() {
+        let mut session = Session::default();
+
+        let reply = execute_with_session("HELLO 4", MockRepository::new(), &mut session).await;
+
+        assert_eq!(reply, Value::Error("NOPROTO unsupported protocol version".to_string()));
+        assert_eq!(session.protocol, 2);
+    }
+
+    #[tokio::test]
+    async fn get() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .with(mockall::predicate::eq(b"key".to_vec()))
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 1)));
+
+        assert_eq!(execute("GET key", mock).await, Value::BulkString(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn key_too_long() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert().never();
+        mock.expect_remove().never();
+        let mock = Arc::new(mock);
+
+        let key = vec![b'k'; 70_000];
+        let reply = execute_command(vec![b"SET".to_vec(), key.clone(), b"hello".to_vec()], mock.clone(), &mut Session::default()).await;
+        assert_eq!(reply, Value::error("key too long"));
+
+        let reply = execute_command(vec![b"DEL".to_vec(), b"a".to_vec(), key], mock, &mut Session::default()).await;
+        assert_eq!(reply, Value::error("key too long"));
+    }
+
+    #[tokio::test]
+    async fn get_missing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(1)
+            .returning(|_| None);
+
+        assert_eq!(execute("GET key", mock).await, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn set_upserts() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .with(mockall::predicate::eq(b"key".to_vec()), mockall::predicate::eq(b"hello".to_vec()), mockall::predicate::eq(Some(Duration::from_secs(10))))
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+
+        assert_eq!(execute("SET key hello EX 10", mock).await, Value::ok());
+    }
+
+    #[tokio::test]
+    async fn set_nx_inserts() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_insert()
+            .with(mockall::predicate::always(), mockall::predicate::always(), mockall::predicate::eq(Some(Duration::from_millis(500))))
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::AlreadyExists(key)));
+
+        assert_eq!(execute("SET key hello px 500 nx", mock).await, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn set_xx_replaces() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::NotFound(key)));
+
+        assert_eq!(execute("SET key hello XX", mock).await, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn set_xx_write_blocked() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::WriteBlocked(key)));
+
+        assert_eq!(
+            execute("SET key hello XX", mock).await,
+            Value::Error("BUSY the entry is being written by another client, try again".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn set_invalid_options() {
+        let mock = || {
+            let mut mock = MockRepository::new();
+            mock.expect_upsert().never();
+            mock
+        };
+
+        assert_eq!(execute("SET key hello NX XX", mock()).await, Value::error("syntax error"));
+        assert_eq!(execute("SET key hello EX", mock()).await, Value::error("syntax error"));
+        assert_eq!(execute("SET key hello EX 0", mock()).await, Value::error("invalid expire time in 'set' command"));
+        assert_eq!(execute("SET key hello EX ten", mock()).await, Value::error("value is not an integer or out of range"));
+    }
+
+    #[tokio::test]
+    async fn set_out_of_memory() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::OutOfMemory));
+
+        assert_eq!(execute("SET key hello", mock).await, Value::Error("OOM command not allowed when used memory > 'maxmemory'".to_string()));
+    }
+
+    #[tokio::test]
+    async fn del_counts_removed() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_remove()
+            .times(2)
+            .returning(|key| if key == b"a" { Ok(()) } else { Err(DatabaseError::NotFound(key)) });
+
+        assert_eq!(execute("DEL a b", mock).await, Value::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn exists_counts_existing() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_version()
+            .times(3)
+            .returning(|key| if key == b"a" { Some(1) } else { None });
+
+        assert_eq!(execute("EXISTS a b a", mock).await, Value::Integer(2));
+    }
+
+    #[tokio::test]
+    async fn incr() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_increment()
+            .with(mockall::predicate::eq(b"counter".to_vec()), mockall::predicate::eq(-5), mockall::predicate::eq(IntegerEncoding::Ascii))
+            .times(1)
+            .returning(|_, _, _| Ok(-4));
+
+        assert_eq!(execute("DECRBY counter 5", mock).await, Value::Integer(-4));
+    }
+
+    #[tokio::test]
+    async fn incr_not_an_integer() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_increment()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::NotAnInteger(key)));
+
+        assert_eq!(execute("INCR key", mock).await, Value::error("value is not an integer or out of range"));
+    }
+
+    #[tokio::test]
+    async fn expire() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire()
+            .with(mockall::predicate::eq(b"key".to_vec()), mockall::predicate::eq(Duration::from_secs(10)))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        assert_eq!(execute("EXPIRE key 10", mock).await, Value::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn expire_in_the_past_removes() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_expire().never();
+        mock.expect_remove()
+            .times(1)
+            .returning(|key| Err(DatabaseError::NotFound(key)));
+
+        assert_eq!(execute("PEXPIRE key -1", mock).await, Value::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn ttl() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_ttl()
+            .times(3)
+            .returning(|key| match key.as_slice() {
+                b"a" => Ok(Some(Duration::from_millis(1500))),
+                b"b" => Ok(None),
+                _ => Err(DatabaseError::NotFound(key)),
+            });
+        let mock = Arc::new(mock);
+
+        assert_eq!(execute_command(vec![b"TTL".to_vec(), b"a".to_vec()], mock.clone(), &mut Session::default()).await, Value::Integer(2));
+        assert_eq!(execute_command(vec![b"PTTL".to_vec(), b"b".to_vec()], mock.clone(), &mut Session::default()).await, Value::Integer(-1));
+        assert_eq!(execute_command(vec![b"TTL".to_vec(), b"c".to_vec()], mock, &mut Session::default()).await, Value::Integer(-2));
+    }
+
+    #[tokio::test]
+    async fn persist_without_expiry() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_ttl()
+            .times(1)
+            .returning(|_| Ok(None));
+        mock.expect_persist().never();
+
+        assert_eq!(execute("PERSIST key", mock).await, Value::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn mget() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(2)
+            .returning(|key| if key == b"a" { Some((b"hello".to_vec(), 1)) } else { None });
+
+        assert_eq!(execute("MGET a b", mock).await, Value::Array(vec![Value::BulkString(b"hello".to_vec()), Value::Null]));
+    }
+
+    #[tokio::test]
+    async fn mset() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .with(mockall::predicate::always(), mockall::predicate::always(), mockall::predicate::eq(None))
+            .times(2)
+            .returning(|_, _, _| Ok(false));
+
+        assert_eq!(execute("MSET a 1 b 2", mock).await, Value::ok());
+    }
+
+    #[tokio::test]
+    async fn getrange() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_length().never();
+        mock.expect_get_range()
+            .with(mockall::predicate::always(), mockall::predicate::eq(0), mockall::predicate::eq(4))
+            .times(1)
+            .returning(|_, _, _| Some(b"This".to_vec()));
+
+        assert_eq!(execute("GETRANGE key 0 3", mock).await, Value::BulkString(b"This".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn getrange_from_end() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_length()
+            .times(1)
+            .returning(|_| Some(16));
+        mock.expect_get_range()
+            .with(mockall::predicate::always(), mockall::predicate::eq(10), mockall::predicate::eq(6))
+            .times(1)
+            .returning(|_, _, _| Some(b"string".to_vec()));
+
+        assert_eq!(execute("GETRANGE key -6 -1", mock).await, Value::BulkString(b"string".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn getrange_empty() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_length()
+            .times(2)
+            .returning(|key| if key == b"key" { Some(16) } else { None });
+        mock.expect_get_range().never();
+
+        assert_eq!(execute("GETRANGE key 5 2", MockRepository::new()).await, Value::BulkString(vec![]));
+
+        let mock = Arc::new(mock);
+        for command in ["GETRANGE key -100 -20", "GETRANGE missing 0 -1"] {
+            let arguments = command.split(' ').map(|argument| argument.as_bytes().to_vec()).collect();
+            assert_eq!(execute_command(arguments, mock.clone(), &mut Session::default()).await, Value::BulkString(vec![]));
+        }
+    }
+
+    #[tokio::test]
+    async fn setrange_out_of_range() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_set_range().never();
+
+        assert_eq!(execute("SETRANGE key -1 hello", mock).await, Value::error("offset is out of range"));
+        assert_eq!(
+            execute("SETRANGE key 536870910 hello", MockRepository::new()).await,
+            Value::error("string exceeds maximum allowed size (proto-max-bulk-len)")
+        );
+    }
+
+    #[tokio::test]
+    async fn append_too_large() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_append()
+            .times(1)
+            .returning(|key, _| Err(DatabaseError::DataTooLarge(key)));
+
+        assert_eq!(execute("APPEND key hello", mock).await, Value::error("string exceeds maximum allowed size (proto-max-bulk-len)"));
+    }
+
+    #[tokio::test]
+    async fn memory_usage() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_memory_usage()
+            .times(1)
+            .returning(|_| Ok((64, 1024)));
+
+        assert_eq!(execute("MEMORY USAGE key", mock).await, Value::Integer(64));
+    }
+}
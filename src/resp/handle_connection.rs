@@ -0,0 +1,129 @@
+use tokio::io::{AsyncWriteExt, BufReader};
+use crate::repository::SharedRepository;
+use crate::resp::commands::{execute_command, Session};
+use crate::resp::read_command::read_command;
+use crate::resp::value::Value;
+
+pub(super) async fn handle_connection<S>(stream: S, db: SharedRepository, max_frame_size: u32)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let mut session = Session::default();
+    let mut replies = Vec::new();
+
+    loop {
+        let arguments = match read_command(&mut reader, max_frame_size).await {
+            Ok(Some(arguments)) => arguments,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("read command failed: {}", e);
+                // the rest of the stream can't be parsed, so the connection is closed after the error
+                Value::error(format!("Protocol error: {}", e)).encode(session.protocol, &mut replies);
+                let _ = writer.write_all(&replies).await;
+                break;
+            }
+        };
+
+        // empty lines of inline commands are ignored
+        if arguments.is_empty() {
+            continue;
+        }
+
+        let reply = execute_command(arguments, db.clone(), &mut session).await;
+        reply.encode(session.protocol, &mut replies);
+
+        // pipelined commands are answered in order, the replies are sent together
+        // once there are no more commands already received
+        if reader.buffer().is_empty() || session.quit {
+            if let Err(e) = writer.write_all(&replies).await {
+                eprintln!("sending reply failed: {}", e);
+                break;
+            }
+            replies.clear();
+        }
+
+        if session.quit {
+            break;
+        }
+    }
+
+    println!("close resp connection");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::AsyncReadExt;
+    use crate::repository::MockRepository;
+
+    // ---- TESTS ----
+
+    #[tokio::test]
+    async fn pipelined_commands_answered_in_order() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_upsert()
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+        mock.expect_get()
+            .times(1)
+            .returning(|_| Some((b"hello".to_vec(), 1)));
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(mock), 1024));
+
+        client.write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nhello\r\nGET key\r\nQUIT\r\n").await.unwrap();
+
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        assert_eq!(replies, b"+OK\r\n$5\r\nhello\r\n+OK\r\n");
+
+        connection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resp3_after_hello() {
+        let mut mock = MockRepository::new();
+
+        mock.expect_get()
+            .times(1)
+            .returning(|_| None);
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(mock), 1024));
+
+        client.write_all(b"HELLO 3\r\n").await.unwrap();
+        let mut reply = [0u8; 4];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"%7\r\n");
+
+        // the rest of the HELLO reply
+        let mut rest = vec![0u8; 1024];
+        let _ = client.read(&mut rest).await.unwrap();
+
+        client.write_all(b"GET key\r\nQUIT\r\n").await.unwrap();
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        assert_eq!(replies, b"_\r\n+OK\r\n");
+
+        connection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn protocol_error_closes_connection() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let connection = tokio::spawn(handle_connection(server, Arc::new(MockRepository::new()), 1024));
+
+        client.write_all(b"*1\r\n$2048\r\n").await.unwrap();
+
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        assert_eq!(replies, b"-ERR Protocol error: invalid bulk length\r\n");
+
+        connection.await.unwrap();
+    }
+}
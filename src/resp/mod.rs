@@ -0,0 +1,28 @@
+mod commands;
+mod handle_connection;
+mod read_command;
+mod value;
+
+use tokio::net::{TcpListener};
+use crate::repository::SharedRepository;
+use crate::resp::handle_connection::handle_connection;
+
+/// Accepts connections speaking the Redis serialization protocol (RESP2 and RESP3),
+/// so redis-cli, redis-benchmark and Redis client libraries can use the entries
+pub async fn listen_for_resp_connections(tcp_listener: TcpListener, db: SharedRepository, max_frame_size: u32) {
+    loop {
+        let (stream, _) = match tcp_listener.accept().await {
+            Ok(res) => res,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let local_db = db.clone();
+
+        tokio::spawn(async move {
+            handle_connection(stream, local_db, max_frame_size).await;
+        });
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Context;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// longest line of an inline command or of the header of an array or bulk string
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+
+/// most arguments of a command
+const MAX_ARGUMENTS: usize = 1024 * 1024;
+
+/// the declared lengths are only trusted up to these, the rest grows with the received data
+const INITIAL_ARGUMENTS: usize = 1024;
+const INITIAL_CAPACITY: usize = 64 * 1024;
+
+/// Reads the next command with its arguments, None if the connection was closed before it.
+///
+/// Commands are sent as an array of bulk strings (`*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n`) or inline as one line
+/// of arguments separated by spaces (`GET key\r\n`), like redis-cli and telnet do. An empty command has no arguments.
+/// The bulk strings of a command together can't be longer than the max frame size.
+pub(super) async fn read_command<R>(reader: &mut R, max_frame_size: u32) -> Result<Option<Vec<Vec<u8>>>, anyhow::Error>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+
+    let Some(count) = line.strip_prefix(b"*") else {
+        // inline command
+        let arguments = line.split(|byte| *byte == b' ' || *byte == b'\t')
+            .filter(|argument| !argument.is_empty())
+            .map(|argument| argument.to_vec())
+            .collect();
+        return Ok(Some(arguments));
+    };
+
+    let count = parse_length(count).context("invalid multibulk length")?;
+    if count > MAX_ARGUMENTS {
+        return Err(anyhow::anyhow!("invalid multibulk length"));
+    }
+
+    let mut arguments = Vec::with_capacity(count.min(INITIAL_ARGUMENTS));
+    let mut total_length = 0usize;
+    for _ in 0..count {
+        let line = read_line(reader).await?
+            .context("connection closed in the middle of a command")?;
+
+        let length = line.strip_prefix(b"$")
+            .and_then(parse_length)
+            .context("expected a bulk string")?;
+        if length > max_frame_size as usize {
+            return Err(anyhow::anyhow!("invalid bulk length"));
+        }
+        // checked before the data is read, so a command with many long arguments isn't buffered
+        total_length += length;
+        if total_length > max_frame_size as usize {
+            return Err(anyhow::anyhow!("command longer than the max frame size"));
+        }
+
+        // the data and the CRLF after it
+        let mut argument = Vec::with_capacity((length + 2).min(INITIAL_CAPACITY));
+        (&mut *reader).take(length as u64 + 2).read_to_end(&mut argument).await
+            .context("bulk string shorter than its length")?;
+
+        if argument.len() < length + 2 {
+            return Err(anyhow::anyhow!("bulk string shorter than its length"));
+        }
+        if !argument.ends_with(b"\r\n") {
+            return Err(anyhow::anyhow!("bulk string not followed by CRLF"));
+        }
+        argument.truncate(length);
+        arguments.push(argument);
+    }
+
+    Ok(Some(arguments))
+}
+
+/// Reads a line without its line ending, None if the connection was closed before it
+async fn read_line<R>(reader: &mut R) -> Result<Option<Vec<u8>>, anyhow::Error>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    reader.take(MAX_LINE_LENGTH).read_until(b'\n', &mut line).await
+        .context("read line failed")?;
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    if line.pop() != Some(b'\n') {
+        return Err(anyhow::anyhow!("line too long or not terminated"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    Ok(Some(line))
+}
+
+fn parse_length(digits: &[u8]) -> Option<usize> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    async fn read(data: &[u8]) -> Result<Option<Vec<Vec<u8>>>, anyhow::Error> {
+        read_command(&mut Cursor::new(data.to_vec()), 1024).await
+    }
+
+    #[tokio::test]
+    async fn test_read_command() {
+        let command = read(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nhe\r\no\r\n").await.unwrap();
+
+        assert_eq!(command, Some(vec![b"SET".to_vec(), b"key".to_vec(), b"he\r\no".to_vec()]));
+    }
+
+    #[tokio::test]
+    async fn test_read_command_inline() {
+        let command = read(b"GET  key\r\n").await.unwrap();
+
+        assert_eq!(command, Some(vec![b"GET".to_vec(), b"key".to_vec()]));
+        assert_eq!(read(b"\n").await.unwrap(), Some(vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_read_command_pipelined() {
+        let mut cursor = Cursor::new(b"*1\r\n$4\r\nPING\r\nPING\r\n".to_vec());
+
+        assert_eq!(read_command(&mut cursor, 1024).await.unwrap(), Some(vec![b"PING".to_vec()]));
+        assert_eq!(read_command(&mut cursor, 1024).await.unwrap(), Some(vec![b"PING".to_vec()]));
+        assert_eq!(read_command(&mut cursor, 1024).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_command_large() {
+        let mut data = b"*1\r\n$200000\r\n".to_vec();
+        data.extend_from_slice(&[b'a'; 200_000]);
+        data.extend_from_slice(b"\r\n");
+
+        let command = read_command(&mut Cursor::new(data), 512 * 1024).await.unwrap();
+
+        assert_eq!(command, Some(vec![vec![b'a'; 200_000]]));
+    }
+
+    #[tokio::test]
+    async fn test_read_command_too_long() {
+        let mut data = b"*3\r\n$3\r\nSET\r\n$600\r\n".to_vec();
+        data.extend_from_slice(&[b'a'; 600]);
+        data.extend_from_slice(b"\r\n$600\r\n");
+        data.extend_from_slice(&[b'b'; 600]);
+        data.extend_from_slice(b"\r\n");
+
+        // every argument fits into the max frame size of 1024 bytes, but not all of them together
+        let error = read(&data).await.unwrap_err();
+        assert_eq!(error.to_string(), "command longer than the max frame size");
+    }
+
+    #[tokio::test]
+    async fn test_read_command_closed() {
+        assert_eq!(read(b"").await.unwrap(), None);
+        assert!(read(b"*2\r\n$3\r\nGET\r\n").await.is_err());
+        assert!(read(b"*1\r\n$1000\r\nGET\r\n").await.is_err());
+        assert!(read(b"GET key").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_command_invalid() {
+        assert!(read(b"*x\r\n").await.is_err());
+        assert!(read(b"*1\r\n:1\r\n").await.is_err());
+        assert!(read(b"*1\r\n$3\r\nGETX\r\n").await.is_err());
+        // longer than the max frame size
+        assert!(read(b"*1\r\n$2048\r\n").await.is_err());
+    }
+}
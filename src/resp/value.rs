@@ -0,0 +1,117 @@
+/// A reply of the Redis serialization protocol (RESP)
+///
+/// RESP2 has no null or map type, a null is sent as a null bulk string and a map as an array
+/// of its keys and values. RESP3 is used once the client switched to it with HELLO 3.
+#[derive(Debug, PartialEq)]
+pub(super) enum Value {
+    SimpleString(String),
+    /// starts with the error code, e.g. "ERR unknown command"
+    Error(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    Null,
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    pub(super) fn ok() -> Value {
+        Value::SimpleString("OK".to_string())
+    }
+
+    pub(super) fn error(message: impl Into<String>) -> Value {
+        Value::Error(format!("ERR {}", message.into()))
+    }
+
+    /// Appends the value to the output in the format of the protocol version (2 or 3)
+    pub(super) fn encode(&self, protocol: u8, output: &mut Vec<u8>) {
+        match self {
+            Value::SimpleString(text) => {
+                output.push(b'+');
+                extend_line(output, text);
+            }
+            Value::Error(message) => {
+                output.push(b'-');
+                extend_line(output, message);
+            }
+            Value::Integer(number) => {
+                output.extend_from_slice(format!(":{}\r\n", number).as_bytes());
+            }
+            Value::BulkString(data) => {
+                output.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                output.extend_from_slice(data);
+                output.extend_from_slice(b"\r\n");
+            }
+            Value::Null if protocol >= 3 => output.extend_from_slice(b"_\r\n"),
+            Value::Null => output.extend_from_slice(b"$-1\r\n"),
+            Value::Array(values) => {
+                output.extend_from_slice(format!("*{}\r\n", values.len()).as_bytes());
+                for value in values {
+                    value.encode(protocol, output);
+                }
+            }
+            Value::Map(entries) => {
+                if protocol >= 3 {
+                    output.extend_from_slice(format!("%{}\r\n", entries.len()).as_bytes());
+                } else {
+                    output.extend_from_slice(format!("*{}\r\n", entries.len() * 2).as_bytes());
+                }
+                for (key, value) in entries {
+                    key.encode(protocol, output);
+                    value.encode(protocol, output);
+                }
+            }
+        }
+    }
+}
+
+/// Appends the text as a single line, line breaks are replaced with spaces like Redis does,
+/// since the text can contain bytes of the client (e.g. an unknown command) that would otherwise end the line early.
+fn extend_line(output: &mut Vec<u8>, text: &str) {
+    output.extend(text.bytes().map(|byte| if byte == b'\r' || byte == b'\n' { b' ' } else { byte }));
+    output.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(value: Value, protocol: u8) -> Vec<u8> {
+        let mut output = Vec::new();
+        value.encode(protocol, &mut output);
+        output
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(Value::ok(), 2), b"+OK\r\n");
+        assert_eq!(encode(Value::error("unknown command"), 2), b"-ERR unknown command\r\n");
+        assert_eq!(encode(Value::Integer(-42), 2), b":-42\r\n");
+        assert_eq!(encode(Value::BulkString(b"hello".to_vec()), 2), b"$5\r\nhello\r\n");
+        assert_eq!(encode(Value::BulkString(vec![]), 2), b"$0\r\n\r\n");
+        assert_eq!(
+            encode(Value::Array(vec![Value::BulkString(b"hi".to_vec()), Value::Integer(1)]), 2),
+            b"*2\r\n$2\r\nhi\r\n:1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_line_breaks() {
+        assert_eq!(encode(Value::error("unknown command 'A\r\n+OK'"), 2), b"-ERR unknown command 'A  +OK'\r\n");
+        assert_eq!(encode(Value::SimpleString("a\nb".to_string()), 2), b"+a b\r\n");
+    }
+
+    #[test]
+    fn test_encode_null() {
+        assert_eq!(encode(Value::Null, 2), b"$-1\r\n");
+        assert_eq!(encode(Value::Null, 3), b"_\r\n");
+    }
+
+    #[test]
+    fn test_encode_map() {
+        let map = || Value::Map(vec![(Value::SimpleString("proto".to_string()), Value::Integer(3))]);
+
+        assert_eq!(encode(map(), 2), b"*2\r\n+proto\r\n:3\r\n");
+        assert_eq!(encode(map(), 3), b"%1\r\n+proto\r\n:3\r\n");
+    }
+}
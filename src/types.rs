@@ -1,28 +1,150 @@
+use std::time::Duration;
+
 /// Example Request Structure
 ///
 /// u8 version
 /// u8 command
-/// u16 content length
+/// content length (u16 before version 5, u32 since version 5)
 /// content of specified length
 pub(crate) struct Request {
     pub(crate) version: u8,
     pub(crate) command: Command,
-    pub(crate) content_length: u16,
+    pub(crate) content_length: u32,
     pub(crate) content: Option<Vec<u8>>,
 }
 
+/// first protocol version with variable length binary keys, version 1 uses 4 bytes u32 ids
+pub(crate) const PROTOCOL_VERSION_KEY: u8 = 2;
+
+/// first protocol version where GET responds with the u64 version of the entry before the data
+pub(crate) const PROTOCOL_VERSION_ENTRY_VERSION: u8 = 3;
+
+/// first protocol version with a u32 request id chosen by the client after the header of requests and responses,
+/// the requests of a connection with a request id are processed concurrently
+pub(crate) const PROTOCOL_VERSION_REQUEST_ID: u8 = 4;
+
+/// first protocol version with u32 content lengths in the header of requests and responses
+/// and in the results and entries of requests with multiple operations, the versions before use u16
+pub(crate) const PROTOCOL_VERSION_LARGE_CONTENT: u8 = 5;
+
+/// lowest protocol version the server supports, requests of other versions are rejected with 505
+pub(crate) const PROTOCOL_VERSION_MIN: u8 = 1;
+
+/// highest protocol version the server supports, negotiated with HELLO
+pub(crate) const PROTOCOL_VERSION_MAX: u8 = PROTOCOL_VERSION_LARGE_CONTENT;
+
+pub(crate) fn is_supported_version(version: u8) -> bool {
+    (PROTOCOL_VERSION_MIN..=PROTOCOL_VERSION_MAX).contains(&version)
+}
+
+/// The largest content length the version can send, the content of a response can't be longer than this
+pub(crate) fn max_content_length(version: u8) -> u32 {
+    if version >= PROTOCOL_VERSION_LARGE_CONTENT {
+        u32::MAX
+    } else {
+        u16::MAX as u32
+    }
+}
+
+/// Splits the content into a content length at its start and the rest of the content.
+///
+/// u16 content length before version 5, u32 content length since version 5
+///
+/// Returns None if the content is too short.
+pub(crate) fn split_content_length(version: u8, content: &[u8]) -> Option<(usize, &[u8])> {
+    if version >= PROTOCOL_VERSION_LARGE_CONTENT {
+        let (length, rest) = content.split_first_chunk::<4>()?;
+        Some((u32::from_be_bytes(*length) as usize, rest))
+    } else {
+        let (length, rest) = content.split_first_chunk::<2>()?;
+        Some((u16::from_be_bytes(*length) as usize, rest))
+    }
+}
+
+/// Splits the content of a request into the key at its start and the rest of the content.
+///
+/// version 1: 4 bytes u32 id, the key are its big endian bytes
+/// version 2: u16 key length + key of specified length (at least 1 byte)
+///
+/// Returns None if the content doesn't start with a valid key.
+pub(crate) fn split_key(version: u8, content: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    if version < PROTOCOL_VERSION_KEY {
+        let (id, rest) = content.split_first_chunk::<4>()?;
+        return Some((id.to_vec(), rest));
+    }
+
+    let (key_length, rest) = content.split_first_chunk::<2>()?;
+    let key_length = u16::from_be_bytes(*key_length) as usize;
+
+    if key_length == 0 || rest.len() < key_length {
+        return None;
+    }
+
+    let (key, rest) = rest.split_at(key_length);
+    Some((key.to_vec(), rest))
+}
+
+/// Splits the content into keys, None if it isn't a sequence of valid keys (see split_key).
+pub(crate) fn split_keys(version: u8, mut content: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut keys = Vec::new();
+    while !content.is_empty() {
+        let (key, rest) = split_key(version, content)?;
+        keys.push(key);
+        content = rest;
+    }
+    Some(keys)
+}
+
+/// Length of a result without content (see append_result), e.g. of MSET and MREMOVE
+pub(crate) fn empty_result_length(version: u8) -> usize {
+    if version >= PROTOCOL_VERSION_LARGE_CONTENT { 6 } else { 4 }
+}
+
+/// Appends the result of a single operation of a request with multiple operations (e.g. EXEC or MGET) to the content.
+///
+/// u16 status code
+/// content length (u16 before version 5, u32 since version 5)
+/// content of specified length
+///
+/// The value has to fit into the content length of the version, like the content of the whole response.
+pub(crate) fn append_result(version: u8, content: &mut Vec<u8>, status_code: StatusCode, value: &[u8]) {
+    content.extend_from_slice(&(status_code as u16).to_be_bytes());
+    if version >= PROTOCOL_VERSION_LARGE_CONTENT {
+        content.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    } else {
+        content.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    }
+    content.extend_from_slice(value);
+}
+
+/// Splits the content into a u64 ttl in milliseconds at its start and the rest of the content.
+///
+/// Returns None if the content is shorter than 8 bytes.
+pub(crate) fn split_ttl(content: &[u8]) -> Option<(Duration, &[u8])> {
+    let (ttl, rest) = split_u64(content)?;
+    Some((Duration::from_millis(ttl), rest))
+}
+
+/// Splits the content into a big endian u64 at its start and the rest of the content.
+///
+/// Returns None if the content is shorter than 8 bytes.
+pub(crate) fn split_u64(content: &[u8]) -> Option<(u64, &[u8])> {
+    let (number, rest) = content.split_first_chunk::<8>()?;
+    Some((u64::from_be_bytes(*number), rest))
+}
+
 /// Example Response Structure
 ///
 /// u8 version
 /// u8 command
 /// u16 status code
-/// u16 content length
+/// content length (u16 before version 5, u32 since version 5)
 /// content of specified length
 pub(crate) struct Response {
     pub(crate) version: u8,
     pub(crate) command: Command,
     pub(crate) status_code: StatusCode,
-    pub(crate) content_length: u16,
+    pub(crate) content_length: u32,
     pub(crate) content: Option<Vec<u8>>,
 }
 
@@ -33,6 +155,35 @@ pub(crate) enum Command {
     Set = 1,
     Insert = 2,
     Remove = 3,
+    RewriteAof = 4,
+    Save = 5,
+    BackgroundSave = 6,
+    InsertAuto = 7,
+    Expire = 8,
+    Ttl = 9,
+    Persist = 10,
+    SetEx = 11,
+    InsertEx = 12,
+    MemoryUsage = 13,
+    SetWait = 14,
+    CompareAndSet = 15,
+    Multi = 16,
+    Exec = 17,
+    Discard = 18,
+    Watch = 19,
+    Unwatch = 20,
+    Upsert = 21,
+    UpsertEx = 22,
+    Incr = 23,
+    Decr = 24,
+    IncrBy = 25,
+    Append = 26,
+    GetRange = 27,
+    SetRange = 28,
+    MGet = 29,
+    MSet = 30,
+    MRemove = 31,
+    Hello = 32,
     Invalid = 0xFF // the invalid command is given to the router if the command doesn't exist
 }
 
@@ -43,6 +194,35 @@ impl From<u8> for Command {
             1 => Command::Set,
             2 => Command::Insert,
             3 => Command::Remove,
+            4 => Command::RewriteAof,
+            5 => Command::Save,
+            6 => Command::BackgroundSave,
+            7 => Command::InsertAuto,
+            8 => Command::Expire,
+            9 => Command::Ttl,
+            10 => Command::Persist,
+            11 => Command::SetEx,
+            12 => Command::InsertEx,
+            13 => Command::MemoryUsage,
+            14 => Command::SetWait,
+            15 => Command::CompareAndSet,
+            16 => Command::Multi,
+            17 => Command::Exec,
+            18 => Command::Discard,
+            19 => Command::Watch,
+            20 => Command::Unwatch,
+            21 => Command::Upsert,
+            22 => Command::UpsertEx,
+            23 => Command::Incr,
+            24 => Command::Decr,
+            25 => Command::IncrBy,
+            26 => Command::Append,
+            27 => Command::GetRange,
+            28 => Command::SetRange,
+            29 => Command::MGet,
+            30 => Command::MSet,
+            31 => Command::MRemove,
+            32 => Command::Hello,
             _ => Command::Invalid,
         }
     }
@@ -52,9 +232,110 @@ impl From<u8> for Command {
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub(crate) enum StatusCode {
     Ok = 200,
+    Created = 201, // the entry didn't exist and was inserted
+    Queued = 202, // the request is queued by the transaction of the connection
     InvalidRequest = 400,
     NotFound = 404,
+    Timeout = 408, // waited for the entry until the timeout of the request
     Conflict = 409, // someone else is currently writing
+    PreconditionFailed = 412, // an entry watched by the transaction was changed
+    ContentTooLarge = 413, // the content of the request or response is longer than its version or the max frame size allows
+    UnprocessableContent = 422, // the data of the entry can't be used by the request, e.g. it isn't an integer
     InternalServerError = 500,
     NotImplemented = 501,
-}
\ No newline at end of file
+    VersionNotSupported = 505, // the server doesn't support the protocol version of the request
+    InsufficientStorage = 507, // the memory limit is reached and no entry can be evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_key_id() {
+        assert_eq!(split_key(1, &[0, 0, 0, 42, b'h', b'i']), Some((vec![0, 0, 0, 42], &b"hi"[..])));
+        assert_eq!(split_key(1, &[0, 0, 42]), None);
+    }
+
+    #[test]
+    fn test_split_ttl() {
+        assert_eq!(split_ttl(&[0, 0, 0, 0, 0, 0, 0x03, 0xE8, b'h', b'i']), Some((Duration::from_secs(1), &b"hi"[..])));
+        assert_eq!(split_ttl(&[0, 0, 0, 0, 0, 0, 0x03]), None);
+    }
+
+    #[test]
+    fn test_split_u64() {
+        assert_eq!(split_u64(&[0, 0, 0, 0, 0, 0, 0x01, 0x02, b'h', b'i']), Some((0x0102, &b"hi"[..])));
+        assert_eq!(split_u64(&[0, 0, 0, 0, 0, 0, 0, 7]), Some((7, &b""[..])));
+        assert_eq!(split_u64(&[0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_split_keys() {
+        assert_eq!(split_keys(1, &[0, 0, 0, 42, 0, 0, 0, 7]), Some(vec![vec![0, 0, 0, 42], vec![0, 0, 0, 7]]));
+        assert_eq!(split_keys(PROTOCOL_VERSION_KEY, b"\x00\x03key\x00\x02hi"), Some(vec![b"key".to_vec(), b"hi".to_vec()]));
+        assert_eq!(split_keys(PROTOCOL_VERSION_KEY, b""), Some(vec![]));
+        // incomplete second key
+        assert_eq!(split_keys(PROTOCOL_VERSION_KEY, b"\x00\x03key\x00"), None);
+    }
+
+    #[test]
+    fn test_append_result() {
+        let mut content = vec![];
+        append_result(PROTOCOL_VERSION_KEY, &mut content, StatusCode::Ok, b"hi");
+        append_result(PROTOCOL_VERSION_KEY, &mut content, StatusCode::NotFound, b"");
+
+        assert_eq!(content, vec![0, 200, 0, 2, b'h', b'i', 0x01, 0x94, 0, 0]);
+    }
+
+    #[test]
+    fn test_empty_result_length() {
+        for version in [PROTOCOL_VERSION_KEY, PROTOCOL_VERSION_LARGE_CONTENT] {
+            let mut content = vec![];
+            append_result(version, &mut content, StatusCode::Ok, b"");
+            assert_eq!(content.len(), empty_result_length(version));
+        }
+    }
+
+    #[test]
+    fn test_append_result_large_content() {
+        let mut content = vec![];
+        append_result(PROTOCOL_VERSION_LARGE_CONTENT, &mut content, StatusCode::Ok, b"hi");
+
+        assert_eq!(content, vec![0, 200, 0, 0, 0, 2, b'h', b'i']);
+    }
+
+    #[test]
+    fn test_split_content_length() {
+        assert_eq!(split_content_length(PROTOCOL_VERSION_KEY, &[0x01, 0x02, b'h']), Some((0x0102, &b"h"[..])));
+        assert_eq!(split_content_length(PROTOCOL_VERSION_LARGE_CONTENT, &[0, 0x01, 0x02, 0x03, b'h']), Some((0x010203, &b"h"[..])));
+        assert_eq!(split_content_length(PROTOCOL_VERSION_LARGE_CONTENT, &[0x01, 0x02]), None);
+    }
+
+    #[test]
+    fn test_is_supported_version() {
+        assert!(!is_supported_version(0));
+        assert!(is_supported_version(PROTOCOL_VERSION_MIN));
+        assert!(is_supported_version(PROTOCOL_VERSION_MAX));
+        assert!(!is_supported_version(PROTOCOL_VERSION_MAX + 1));
+    }
+
+    #[test]
+    fn test_max_content_length() {
+        assert_eq!(max_content_length(1), 65535);
+        assert_eq!(max_content_length(PROTOCOL_VERSION_REQUEST_ID), 65535);
+        assert_eq!(max_content_length(PROTOCOL_VERSION_LARGE_CONTENT), u32::MAX);
+    }
+
+    #[test]
+    fn test_split_key() {
+        assert_eq!(split_key(PROTOCOL_VERSION_KEY, b"\x00\x03keyhi"), Some((b"key".to_vec(), &b"hi"[..])));
+        assert_eq!(split_key(PROTOCOL_VERSION_KEY, b"\x00\x03key"), Some((b"key".to_vec(), &b""[..])));
+        // key shorter than its length
+        assert_eq!(split_key(PROTOCOL_VERSION_KEY, b"\x00\x04key"), None);
+        // empty key
+        assert_eq!(split_key(PROTOCOL_VERSION_KEY, b"\x00\x00hi"), None);
+        // key length missing
+        assert_eq!(split_key(PROTOCOL_VERSION_KEY, b"\x00"), None);
+    }
+}